@@ -0,0 +1,83 @@
+//! Generates the FlatBuffers field slots from `schema/atlas.fbs`, so that `src/formats/fbs.rs`
+//! can't get out of sync with the schema and building impact doesn't require `flatc`.
+
+use std::fmt::Write;
+use std::path::Path;
+
+const SCHEMA: &str = "schema/atlas.fbs";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", SCHEMA);
+    let schema = std::fs::read_to_string(SCHEMA).expect("failed to read the FlatBuffers schema");
+
+    let mut code = String::new();
+    let mut table: Option<(String, u16)> = None;
+    for (number, line) in schema.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fail = |reason: &str| -> ! {
+            panic!("{}:{}: {}: {}", SCHEMA, number + 1, reason, line);
+        };
+
+        match &mut table {
+            None => {
+                if let Some(name) = line.strip_prefix("table ") {
+                    let name = name.trim_end_matches('{').trim();
+                    writeln!(code, "mod {}_slot {{", snake_case(name)).unwrap();
+                    writeln!(code, "    use flatbuffers::VOffsetT;\n").unwrap();
+                    table = Some((name.to_owned(), 0));
+                } else if line.starts_with("struct ")
+                    || line.starts_with("union ")
+                    || line.starts_with("enum ")
+                {
+                    fail("only tables are supported");
+                }
+            }
+            Some((_, index)) => {
+                if line == "}" {
+                    writeln!(code, "}}\n").unwrap();
+                    table = None;
+                    continue;
+                }
+                let field = match line.split(':').next() {
+                    Some(field) if line.ends_with(';') && line.contains(':') => field.trim(),
+                    _ => fail("expected a field"),
+                };
+                let attributes = line.split('(').nth(1).unwrap_or_default();
+                if attributes.contains("id:") {
+                    fail("explicit field ids aren't supported");
+                }
+                // Every field takes a slot in the order it's declared, even deprecated ones
+                writeln!(
+                    code,
+                    "    pub const {}: VOffsetT = {};",
+                    field.to_uppercase(),
+                    4 + 2 * *index
+                )
+                .unwrap();
+                *index += 1;
+            }
+        }
+    }
+    if let Some((name, _)) = table {
+        panic!("{}: table {} isn't closed", SCHEMA, name);
+    }
+
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    std::fs::write(Path::new(&out_dir).join("fbs_slots.rs"), code)
+        .expect("failed to write the FlatBuffers slots");
+}
+
+/// `TextureAtlas` becomes `texture_atlas`
+fn snake_case(name: &str) -> String {
+    let mut res = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            res.push('_');
+        }
+        res.extend(c.to_lowercase());
+    }
+    res
+}
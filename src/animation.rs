@@ -0,0 +1,171 @@
+use crate::serial::{Animation, Atlas};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// The suffix that marks a sprite as a numbered animation frame, e.g. `_(\d+)$`.
+///
+/// The first capture group holds the frame number; everything before the match is the
+/// name of the animation.
+#[derive(Debug, Clone)]
+pub struct SuffixPattern(Regex);
+
+impl FromStr for SuffixPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let regex = Regex::new(s).map_err(|err| format!("invalid animation suffix: {}", err))?;
+        if regex.captures_len() < 2 {
+            return Err(format!(
+                "animation suffix must capture the frame number: {}",
+                s
+            ));
+        }
+        Ok(Self(regex))
+    }
+}
+
+impl Hash for SuffixPattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+
+impl SuffixPattern {
+    /// Splits a sprite name into its animation name and frame number
+    fn split<'a>(&self, name: &'a str) -> Option<(&'a str, u64)> {
+        let captures = self.0.captures(name)?;
+        let number = captures.get(1)?.as_str().parse().ok()?;
+        let prefix = &name[..captures.get(0)?.start()];
+        if prefix.is_empty() {
+            return None;
+        }
+        Some((prefix, number))
+    }
+}
+
+/// Groups the numbered sprites of the atlas into animations, sorted by frame number.
+///
+/// Only sequences of at least two frames are kept, so that a lone `icon_2` isn't
+/// mistaken for an animation.
+pub fn from_suffixes(atlas: &Atlas, suffix: &SuffixPattern) -> Vec<Animation> {
+    let mut groups: BTreeMap<&str, Vec<(u64, &str)>> = BTreeMap::new();
+    for image in atlas
+        .textures
+        .iter()
+        .flat_map(|texture| texture.images.iter())
+    {
+        if let Some((prefix, number)) = suffix.split(&image.name) {
+            groups
+                .entry(prefix)
+                .or_default()
+                .push((number, image.name.as_str()));
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, frames)| frames.len() > 1)
+        .map(|(name, mut frames)| {
+            frames.sort();
+            Animation {
+                name: name.to_owned(),
+                frames: frames
+                    .into_iter()
+                    .map(|(_, frame)| frame.to_owned())
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Makes an animation of every leaf folder, i.e. every folder of sprites without any
+/// folders of sprites inside it, with its frames sorted naturally (`2` before `10`).
+///
+/// Folders are delimited by `separator` in sprite names.
+pub fn from_folders(atlas: &Atlas, separator: &str) -> Vec<Animation> {
+    if separator.is_empty() {
+        return vec![];
+    }
+
+    let mut folders: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for image in atlas
+        .textures
+        .iter()
+        .flat_map(|texture| texture.images.iter())
+    {
+        if let Some(idx) = image.name.rfind(separator) {
+            folders
+                .entry(&image.name[..idx])
+                .or_default()
+                .push(image.name.as_str());
+        }
+    }
+
+    let is_leaf = |folder: &str| {
+        !folders.keys().any(|other| {
+            other.len() > folder.len()
+                && other.starts_with(folder)
+                && other[folder.len()..].starts_with(separator)
+        })
+    };
+
+    folders
+        .iter()
+        .filter(|(folder, _)| is_leaf(folder))
+        .map(|(folder, frames)| {
+            let mut frames = frames.clone();
+            frames.sort_by(|a, b| natural_cmp(a, b));
+            Animation {
+                name: (*folder).to_owned(),
+                frames: frames.into_iter().map(str::to_owned).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Compares two strings, treating runs of digits as numbers
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // Compare by length first so that arbitrarily long numbers work
+                let (xs, ys) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ord = xs
+                    .len()
+                    .cmp(&ys.len())
+                    .then_with(|| xs.cmp(ys))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.cmp(y);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut res = String::new();
+    while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+        res.push(*c);
+        chars.next();
+    }
+    res
+}
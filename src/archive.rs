@@ -0,0 +1,66 @@
+//! Writes the pages and atlas data into a single zip archive for `--archive`, for asset
+//! pipelines that take one file per atlas.
+
+use crate::error::Result;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Writes `files` into a zip archive at `path`, named relative to `base`, and removes them.
+/// Folders (such as a SpriteKit `.atlasc`) are added with everything in them.
+pub fn write(path: &Path, base: &Path, files: &[PathBuf]) -> Result<()> {
+    log::info!("writing archive {}", path.display());
+    let mut zip = ZipWriter::new(File::create(path)?);
+    for file in files {
+        add(&mut zip, base, file)?;
+    }
+    zip.finish()?;
+
+    for file in files {
+        if file.is_dir() {
+            std::fs::remove_dir_all(file)?;
+        } else {
+            std::fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+fn add(zip: &mut ZipWriter<File>, base: &Path, path: &Path) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?.collect::<std::io::Result<Vec<_>>>()?;
+        // Keep the archive the same from one run to the next
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            add(zip, base, &entry.path())?;
+        }
+        return Ok(());
+    }
+
+    // Zip entries always use forward slashes
+    let name = path
+        .strip_prefix(base)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    // PNG and WebP pages are already compressed, so they're only stored
+    let compressed = !matches!(
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .as_deref(),
+        Some("png") | Some("webp") | Some("avif")
+    );
+    let method = if compressed {
+        CompressionMethod::Deflated
+    } else {
+        CompressionMethod::Stored
+    };
+    log::info!("archiving {}", name);
+    zip.start_file(name, FileOptions::default().compression_method(method))?;
+    zip.write_all(&std::fs::read(path)?)?;
+    Ok(())
+}
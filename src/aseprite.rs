@@ -0,0 +1,493 @@
+use crate::blend;
+use crate::error::{ImpactError, Result};
+use crate::serial::Animation;
+use image::RgbaImage;
+use std::io::Read;
+use std::path::Path;
+
+/// The file extensions of Aseprite documents
+pub const EXTENSIONS: &[&str] = &["ase", "aseprite"];
+
+/// Whether `path` has the extension of an Aseprite document
+pub fn is_aseprite_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            EXTENSIONS
+                .iter()
+                .any(|extension| ext.eq_ignore_ascii_case(extension))
+        })
+}
+
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+
+const LAYER_VISIBLE: u16 = 1;
+const LAYER_BACKGROUND: u16 = 8;
+const LAYER_REFERENCE: u16 = 64;
+
+/// An Aseprite document, as documented in
+/// <https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md>.
+///
+/// Only what's needed to render the frames is kept: the layers, their cels, the palette
+/// of indexed documents and the tags.
+pub struct Aseprite {
+    width: u32,
+    height: u32,
+    layers: Vec<Layer>,
+    /// The cels of every frame, sorted in drawing order
+    frames: Vec<Vec<Cel>>,
+    tags: Vec<Tag>,
+}
+
+struct Layer {
+    name: String,
+    /// Whether the layer and all of its parents are visible
+    visible: bool,
+    /// Whether the layer has pixels of its own (i.e. isn't a group or tilemap)
+    is_image: bool,
+    /// Background layers are opaque, even where they use the transparent color index
+    background: bool,
+    opacity: u8,
+}
+
+#[derive(Clone)]
+struct Cel {
+    layer: usize,
+    x: i32,
+    y: i32,
+    opacity: u8,
+    /// The position of the cel in the drawing order of its frame
+    order: (i32, i32),
+    image: RgbaImage,
+}
+
+struct Tag {
+    name: String,
+    from: usize,
+    to: usize,
+    direction: u8,
+}
+
+/// The color depth of a document, and how to read its pixels
+enum ColorDepth {
+    Rgba,
+    Grayscale,
+    Indexed {
+        palette: Vec<[u8; 4]>,
+        transparent: u8,
+    },
+}
+
+impl Aseprite {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::parse(&bytes).map_err(|reason| ImpactError::InvalidAseprite {
+            path: path.display().to_string(),
+            reason,
+        })
+    }
+
+    fn parse(bytes: &[u8]) -> std::result::Result<Self, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        // Header
+        reader.u32()?;
+        if reader.u16()? != HEADER_MAGIC {
+            return Err("not an aseprite file".to_owned());
+        }
+        let frame_count = reader.u16()? as usize;
+        let width = reader.u16()? as u32;
+        let height = reader.u16()? as u32;
+        let depth = reader.u16()?;
+        let layer_opacity_valid = reader.u32()? & 1 != 0;
+        reader.skip(2 + 4 + 4)?;
+        let transparent = reader.u8()?;
+        reader.skip(128 - 29)?;
+
+        let mut depth = match depth {
+            32 => ColorDepth::Rgba,
+            16 => ColorDepth::Grayscale,
+            8 => ColorDepth::Indexed {
+                palette: vec![[0; 4]; 256],
+                transparent,
+            },
+            _ => return Err(format!("unsupported color depth {}", depth)),
+        };
+        let mut has_new_palette = false;
+
+        let mut layers: Vec<Layer> = vec![];
+        // Whether the layers at each level of the current group hierarchy are visible
+        let mut visible_parents: Vec<bool> = vec![];
+        let mut frames: Vec<Vec<Cel>> = vec![];
+        let mut tags = vec![];
+
+        for frame_idx in 0..frame_count {
+            let frame_start = reader.pos;
+            let frame_size = reader.u32()? as usize;
+            if reader.u16()? != FRAME_MAGIC {
+                return Err(format!("frame {} is corrupted", frame_idx));
+            }
+            let old_chunk_count = reader.u16()? as usize;
+            reader.skip(2 + 2)?;
+            let chunk_count = match reader.u32()? as usize {
+                0 => old_chunk_count,
+                count => count,
+            };
+
+            let mut cels = vec![];
+            for _ in 0..chunk_count {
+                let chunk_start = reader.pos;
+                let chunk_size = reader.u32()? as usize;
+                let chunk_type = reader.u16()?;
+                let mut chunk = Reader {
+                    bytes: reader.take(chunk_size.saturating_sub(6))?,
+                    pos: 0,
+                };
+
+                match chunk_type {
+                    CHUNK_LAYER => {
+                        let flags = chunk.u16()?;
+                        let layer_type = chunk.u16()?;
+                        let level = chunk.u16()? as usize;
+                        chunk.skip(2 + 2)?;
+                        let blend_mode = chunk.u16()?;
+                        let opacity = chunk.u8()?;
+                        chunk.skip(3)?;
+                        let name = chunk.string()?;
+
+                        visible_parents.truncate(level);
+                        let visible = flags & LAYER_VISIBLE != 0
+                            && flags & LAYER_REFERENCE == 0
+                            && visible_parents.iter().all(|visible| *visible);
+                        visible_parents.push(visible);
+
+                        if layer_type == 2 {
+                            log::warn!("tilemap layers aren't supported, skipping {}", name);
+                        } else if blend_mode != 0 && layer_type == 0 {
+                            log::warn!("unsupported blend mode in layer {}, using normal", name);
+                        }
+                        layers.push(Layer {
+                            name,
+                            visible,
+                            is_image: layer_type == 0,
+                            background: flags & LAYER_BACKGROUND != 0,
+                            opacity: if layer_opacity_valid { opacity } else { 255 },
+                        });
+                    }
+                    CHUNK_CEL => {
+                        let layer = chunk.u16()? as usize;
+                        let x = chunk.u16()? as i16 as i32;
+                        let y = chunk.u16()? as i16 as i32;
+                        let opacity = chunk.u8()?;
+                        let cel_type = chunk.u16()?;
+                        let z_index = chunk.u16()? as i16 as i32;
+                        chunk.skip(5)?;
+                        let order = (layer as i32 + z_index, z_index);
+
+                        let image = match cel_type {
+                            0 | 2 => {
+                                let w = chunk.u16()? as u32;
+                                let h = chunk.u16()? as u32;
+                                let data = if cel_type == 0 {
+                                    chunk.bytes[chunk.pos..].to_vec()
+                                } else {
+                                    let mut data = vec![];
+                                    flate2::read::ZlibDecoder::new(&chunk.bytes[chunk.pos..])
+                                        .read_to_end(&mut data)
+                                        .map_err(|err| format!("invalid cel data: {}", err))?;
+                                    data
+                                };
+                                let background =
+                                    layers.get(layer).map_or(false, |layer| layer.background);
+                                depth.to_rgba(w, h, &data, background)?
+                            }
+                            1 => {
+                                let linked = chunk.u16()? as usize;
+                                let cel = frames
+                                    .get(linked)
+                                    .and_then(|cels| cels.iter().find(|cel| cel.layer == layer))
+                                    .ok_or_else(|| {
+                                        format!("invalid linked cel in frame {}", frame_idx)
+                                    })?;
+                                cels.push(Cel {
+                                    x,
+                                    y,
+                                    opacity,
+                                    order,
+                                    ..cel.clone()
+                                });
+                                continue;
+                            }
+                            _ => continue,
+                        };
+                        cels.push(Cel {
+                            layer,
+                            x,
+                            y,
+                            opacity,
+                            order,
+                            image,
+                        });
+                    }
+                    CHUNK_TAGS => {
+                        let count = chunk.u16()?;
+                        chunk.skip(8)?;
+                        for _ in 0..count {
+                            let from = chunk.u16()? as usize;
+                            let to = chunk.u16()? as usize;
+                            let direction = chunk.u8()?;
+                            chunk.skip(2 + 6 + 3 + 1)?;
+                            let name = chunk.string()?;
+                            tags.push(Tag {
+                                name,
+                                from,
+                                to,
+                                direction,
+                            });
+                        }
+                    }
+                    CHUNK_PALETTE => {
+                        if let ColorDepth::Indexed { palette, .. } = &mut depth {
+                            has_new_palette = true;
+                            chunk.u32()?;
+                            let first = chunk.u32()? as usize;
+                            let last = chunk.u32()? as usize;
+                            chunk.skip(8)?;
+                            for idx in first..=last {
+                                let flags = chunk.u16()?;
+                                let color = [chunk.u8()?, chunk.u8()?, chunk.u8()?, chunk.u8()?];
+                                if flags & 1 != 0 {
+                                    chunk.string()?;
+                                }
+                                if let Some(entry) = palette.get_mut(idx) {
+                                    *entry = color;
+                                }
+                            }
+                        }
+                    }
+                    CHUNK_OLD_PALETTE => {
+                        if let ColorDepth::Indexed { palette, .. } = &mut depth {
+                            if !has_new_palette {
+                                let mut idx = 0;
+                                for _ in 0..chunk.u16()? {
+                                    idx += chunk.u8()? as usize;
+                                    let count = match chunk.u8()? {
+                                        0 => 256,
+                                        count => count as usize,
+                                    };
+                                    for _ in 0..count {
+                                        let color = [chunk.u8()?, chunk.u8()?, chunk.u8()?, 255];
+                                        if let Some(entry) = palette.get_mut(idx) {
+                                            *entry = color;
+                                        }
+                                        idx += 1;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                reader.pos = chunk_start + chunk_size.max(6);
+            }
+
+            cels.sort_by_key(|cel| cel.order);
+            frames.push(cels);
+            reader.pos = frame_start + frame_size;
+        }
+
+        Ok(Self {
+            width,
+            height,
+            layers,
+            frames,
+            tags,
+        })
+    }
+
+    /// The name of a frame's sprite, numbered if the document has more than one frame
+    fn frame_name(&self, name: &str, frame: usize) -> String {
+        if self.frames.len() == 1 {
+            name.to_owned()
+        } else {
+            format!("{}_{}", name, frame)
+        }
+    }
+
+    /// Renders the visible layers (or, if `layer` is given, only that one) of a frame
+    fn render(&self, frame: usize, layer: Option<usize>) -> RgbaImage {
+        let mut res = RgbaImage::new(self.width, self.height);
+        for cel in self.frames[frame].iter() {
+            let cel_layer = match self.layers.get(cel.layer) {
+                Some(cel_layer) => cel_layer,
+                None => continue,
+            };
+            if !cel_layer.visible || !cel_layer.is_image || layer.map_or(false, |l| l != cel.layer)
+            {
+                continue;
+            }
+            let opacity = cel.opacity as f32 / 255.0 * cel_layer.opacity as f32 / 255.0;
+            blend::over(&mut res, &cel.image, cel.x, cel.y, opacity);
+        }
+        res
+    }
+
+    /// Every sprite of the document, named after `name`.
+    ///
+    /// Frames are flattened into a single sprite each (`name_0`, `name_1`...), unless
+    /// `split_layers` is set, in which case every visible layer of every frame is its own
+    /// sprite (`name/layer_0`...).
+    pub fn sprites(&self, name: &str, split_layers: bool) -> Vec<(String, RgbaImage)> {
+        let mut res = vec![];
+        for frame in 0..self.frames.len() {
+            if split_layers {
+                for (idx, layer) in self.layers.iter().enumerate() {
+                    if layer.visible && layer.is_image {
+                        let layer_name = format!("{}/{}", name, layer.name);
+                        res.push((
+                            self.frame_name(&layer_name, frame),
+                            self.render(frame, Some(idx)),
+                        ));
+                    }
+                }
+            } else {
+                res.push((self.frame_name(name, frame), self.render(frame, None)));
+            }
+        }
+        res
+    }
+
+    /// An animation for every tag of the document, named `name/tag` (or `name/layer/tag`
+    /// when the layers are split), with its frames in playback order
+    pub fn animations(&self, name: &str, split_layers: bool) -> Vec<Animation> {
+        let bases: Vec<String> = if split_layers {
+            self.layers
+                .iter()
+                .filter(|layer| layer.visible && layer.is_image)
+                .map(|layer| format!("{}/{}", name, layer.name))
+                .collect()
+        } else {
+            vec![name.to_owned()]
+        };
+
+        let mut res = vec![];
+        for base in bases.iter() {
+            for tag in self.tags.iter() {
+                let to = tag.to.min(self.frames.len().saturating_sub(1));
+                let forward: Vec<usize> = (tag.from..=to).collect();
+                let frames: Vec<usize> = match tag.direction {
+                    // Reverse
+                    1 => forward.into_iter().rev().collect(),
+                    // Ping-pong, without repeating the frames at either end
+                    2 => {
+                        let back = forward.iter().rev().skip(1);
+                        let back = back.take(forward.len().saturating_sub(2));
+                        forward.iter().chain(back).cloned().collect()
+                    }
+                    // Ping-pong, starting in reverse
+                    3 => {
+                        let back = forward.iter().skip(1);
+                        let back = back.take(forward.len().saturating_sub(2));
+                        forward.iter().rev().chain(back).cloned().collect()
+                    }
+                    _ => forward,
+                };
+                res.push(Animation {
+                    name: format!("{}/{}", base, tag.name),
+                    frames: frames
+                        .into_iter()
+                        .map(|frame| self.frame_name(base, frame))
+                        .collect(),
+                });
+            }
+        }
+        res
+    }
+}
+
+impl ColorDepth {
+    fn to_rgba(
+        &self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        background: bool,
+    ) -> std::result::Result<RgbaImage, String> {
+        let count = (width * height) as usize;
+        let pixels: Vec<u8> = match self {
+            ColorDepth::Rgba => data.get(..count * 4).map(|data| data.to_vec()),
+            ColorDepth::Grayscale => data.get(..count * 2).map(|data| {
+                data.chunks(2)
+                    .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+                    .collect()
+            }),
+            ColorDepth::Indexed {
+                palette,
+                transparent,
+            } => data.get(..count).map(|data| {
+                data.iter()
+                    .flat_map(|idx| {
+                        if idx == transparent && !background {
+                            [0; 4]
+                        } else {
+                            palette[*idx as usize]
+                        }
+                    })
+                    .collect()
+            }),
+        }
+        .ok_or_else(|| "cel data is too short".to_owned())?;
+
+        Ok(RgbaImage::from_vec(width, height, pixels).expect("cel size mismatch"))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], String> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err("unexpected end of file".to_owned());
+        }
+        let res = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    fn skip(&mut self, len: usize) -> std::result::Result<(), String> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> std::result::Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, String> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn string(&mut self) -> std::result::Result<String, String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::rect::Rect;
 use std::convert::TryInto;
 
+/// How to choose the free rectangle to place each rectangle in
 #[derive(Debug, Copy, Clone)]
 pub enum FreeRectChoiceHeuristic {
     /// BSSF: Positions the rectangle against the short side of a free rectangle into which it fits the best.
@@ -15,9 +16,16 @@ pub enum FreeRectChoiceHeuristic {
     RectContactPointRule,
 }
 
+/// A MaxRects bin packer, which tracks every maximal free rectangle of the bin
 pub struct MaxRectsBinPack {
     bin_width: i32,
     bin_height: i32,
+    /// Rectangles are only placed at positions that are multiples of this
+    align: i32,
+    /// How far into the next rectangle the aligned position is, e.g. past an extruded border
+    align_offset: i32,
+    /// How much worse a rotated placement scores than an unrotated one, in pixels
+    rotation_penalty: i32,
     used_rectangles: Vec<Rect>,
     free_rectangles: Vec<Rect>,
 }
@@ -27,6 +35,9 @@ impl MaxRectsBinPack {
         Self {
             bin_width: width,
             bin_height: height,
+            align: 1,
+            align_offset: 0,
+            rotation_penalty: 0,
             used_rectangles: vec![],
             free_rectangles: vec![Rect {
                 x: 0,
@@ -37,6 +48,27 @@ impl MaxRectsBinPack {
         }
     }
 
+    /// Only places rectangles at positions that are multiples of `align`
+    pub fn with_align(mut self, align: i32) -> Self {
+        self.align = std::cmp::max(align, 1);
+        self
+    }
+
+    /// Places the next rectangles so that the position `offset` pixels into them is aligned,
+    /// rather than their corner, e.g. so that a sprite is aligned rather than its extruded border
+    pub fn set_align_offset(&mut self, offset: i32) {
+        self.align_offset = offset;
+    }
+
+    /// Makes rotated placements score `penalty` pixels worse than unrotated ones, so that
+    /// rectangles are only rotated when it fits them noticeably better. The penalty is added to
+    /// the side lengths and positions the heuristics compare, or to the area of a strip along the
+    /// long side of the rectangle for `RectBestAreaFit`.
+    pub fn with_rotation_penalty(mut self, penalty: i32) -> Self {
+        self.rotation_penalty = std::cmp::max(penalty, 0);
+        self
+    }
+
     pub fn insert_list(
         &mut self,
         rects: &[Rect],
@@ -76,6 +108,8 @@ impl MaxRectsBinPack {
         dst
     }
 
+    /// Places a rectangle, optionally allowing it to be rotated. Returns an empty rectangle if
+    /// it doesn't fit.
     pub fn insert(
         &mut self,
         width: i32,
@@ -154,20 +188,48 @@ impl MaxRectsBinPack {
         (new_node, score1, score2)
     }
 
-    fn place_rect(&mut self, node: &Rect) {
-        let mut num_rectangles_to_process = self.free_rectangles.len();
+    /// The free rectangles, each moved to the next aligned position and shrunk to match
+    fn aligned_free_rectangles(&self) -> impl Iterator<Item = Rect> + '_ {
+        let align = self.align;
+        let offset = self.align_offset;
+        // The smallest position at least `value` that's aligned once the offset is added
+        let round_up = move |value: i32| (value + offset + align - 1) / align * align - offset;
+        self.free_rectangles.iter().filter_map(move |rect| {
+            let x = round_up(rect.x);
+            let y = round_up(rect.y);
+            let aligned = Rect {
+                x,
+                y,
+                width: rect.width - (x - rect.x),
+                height: rect.height - (y - rect.y),
+            };
+            if aligned.width > 0 && aligned.height > 0 {
+                Some(aligned)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The free rectangles left in the bin, which can overlap each other
+    pub fn free_rectangles(&self) -> &[Rect] {
+        &self.free_rectangles
+    }
+
+    /// Marks a rectangle of the bin as used, e.g. to rebuild the free space of a bin packed
+    /// earlier
+    pub fn place_rect(&mut self, node: &Rect) {
+        let mut new_rectangles = vec![];
         let mut i = 0;
-        while i < num_rectangles_to_process {
-            let r = self.free_rectangles[i].clone();
-            if self.split_free_node(&r, node) {
-                self.free_rectangles.remove(i);
-                num_rectangles_to_process -= 1;
+        while i < self.free_rectangles.len() {
+            if Self::split_free_node(&self.free_rectangles[i], node, &mut new_rectangles) {
+                self.free_rectangles.swap_remove(i);
             } else {
                 i += 1;
             }
         }
 
-        self.prune_free_list();
+        self.prune_free_list(new_rectangles);
 
         self.used_rectangles.push(node.clone());
     }
@@ -204,7 +266,7 @@ impl MaxRectsBinPack {
         let mut best_y = i32::max_value();
         let mut best_x = i32::max_value();
 
-        for rect in &self.free_rectangles {
+        for rect in self.aligned_free_rectangles() {
             // Try to place the rectangle in upright (non-flipped) orientation
             if rect.width >= width && rect.height >= height {
                 let top_side_y = rect.y + height;
@@ -218,7 +280,7 @@ impl MaxRectsBinPack {
                 }
             }
             if rot && rect.width >= height && rect.height >= width {
-                let top_side_y = rect.y + width;
+                let top_side_y = rect.y + width + self.rotation_penalty;
                 if top_side_y < best_y || (top_side_y == best_y && rect.x < best_x) {
                     best_node.x = rect.x;
                     best_node.y = rect.y;
@@ -243,7 +305,7 @@ impl MaxRectsBinPack {
         let mut best_short_side_fit = i32::max_value();
         let mut best_long_side_fit = i32::max_value();
 
-        for rect in &self.free_rectangles {
+        for rect in self.aligned_free_rectangles() {
             // Try to place the rectangle in upright (non-flipped) orientation
             if rect.width >= width && rect.height >= height {
                 let leftover_horiz = (rect.width - width).abs();
@@ -264,8 +326,10 @@ impl MaxRectsBinPack {
             if rot && rect.width >= height && rect.height >= width {
                 let leftover_horiz = (rect.width - height).abs();
                 let leftover_vert = (rect.height - width).abs();
-                let short_side_fit = std::cmp::min(leftover_horiz, leftover_vert);
-                let long_side_fit = std::cmp::max(leftover_horiz, leftover_vert);
+                let short_side_fit =
+                    std::cmp::min(leftover_horiz, leftover_vert) + self.rotation_penalty;
+                let long_side_fit =
+                    std::cmp::max(leftover_horiz, leftover_vert) + self.rotation_penalty;
                 if short_side_fit < best_short_side_fit
                     || (short_side_fit == best_short_side_fit && long_side_fit < best_long_side_fit)
                 {
@@ -292,7 +356,7 @@ impl MaxRectsBinPack {
         let mut best_short_side_fit = i32::max_value();
         let mut best_long_side_fit = i32::max_value();
 
-        for rect in &self.free_rectangles {
+        for rect in self.aligned_free_rectangles() {
             // Try to place the rectangle in upright (non-flipped) orientation
             if rect.width >= width && rect.height >= height {
                 let leftover_horiz = (rect.width - width).abs();
@@ -313,8 +377,10 @@ impl MaxRectsBinPack {
             if rot && rect.width >= height && rect.height >= width {
                 let leftover_horiz = (rect.width - height).abs();
                 let leftover_vert = (rect.height - width).abs();
-                let short_side_fit = std::cmp::min(leftover_horiz, leftover_vert);
-                let long_side_fit = std::cmp::max(leftover_horiz, leftover_vert);
+                let short_side_fit =
+                    std::cmp::min(leftover_horiz, leftover_vert) + self.rotation_penalty;
+                let long_side_fit =
+                    std::cmp::max(leftover_horiz, leftover_vert) + self.rotation_penalty;
                 if long_side_fit < best_long_side_fit
                     || (long_side_fit == best_long_side_fit && short_side_fit < best_short_side_fit)
                 {
@@ -341,7 +407,7 @@ impl MaxRectsBinPack {
         let mut best_area_fit = i32::max_value();
         let mut best_short_side_fit = i32::max_value();
 
-        for rect in &self.free_rectangles {
+        for rect in self.aligned_free_rectangles() {
             let area_fit = rect.width * rect.height - width * height;
 
             // Try to place the rectangle in upright (non-flipped) orientation
@@ -365,6 +431,7 @@ impl MaxRectsBinPack {
                 let leftover_horiz = (rect.width - height).abs();
                 let leftover_vert = (rect.height - width).abs();
                 let short_side_fit = std::cmp::min(leftover_horiz, leftover_vert);
+                let area_fit = area_fit + self.rotation_penalty * std::cmp::max(width, height);
 
                 if area_fit < best_area_fit
                     || (area_fit == best_area_fit && short_side_fit < best_short_side_fit)
@@ -390,12 +457,16 @@ impl MaxRectsBinPack {
         let mut best_node = Rect::default();
 
         let mut best_contact_score = -1;
+        // The rotation penalty can make scores negative, but it only breaks ties between
+        // placements: a rotated one that fits still beats none at all
+        let mut found = false;
 
-        for rect in &self.free_rectangles {
+        for rect in self.aligned_free_rectangles() {
             // Try to place the rectangle in upright (non-flipped) orientation
             if rect.width >= width && rect.height >= height {
                 let score = self.contact_point_score_node(rect.x, rect.y, rect.width, rect.height);
-                if score > best_contact_score {
+                if !found || score > best_contact_score {
+                    found = true;
                     best_node.x = rect.x;
                     best_node.y = rect.y;
                     best_node.width = width;
@@ -404,8 +475,10 @@ impl MaxRectsBinPack {
                 }
             }
             if rot && rect.width >= height && rect.height >= width {
-                let score = self.contact_point_score_node(rect.x, rect.y, rect.height, rect.width);
-                if score > best_contact_score {
+                let score = self.contact_point_score_node(rect.x, rect.y, rect.height, rect.width)
+                    - self.rotation_penalty;
+                if !found || score > best_contact_score {
+                    found = true;
                     best_node.x = rect.x;
                     best_node.y = rect.y;
                     best_node.width = height;
@@ -418,7 +491,9 @@ impl MaxRectsBinPack {
         (best_node, best_contact_score)
     }
 
-    fn split_free_node(&mut self, free_node: &Rect, used_node: &Rect) -> bool {
+    /// Splits `free_node` around `used_node`, adding the leftover free space to `new_rectangles`.
+    /// Returns false if they don't intersect.
+    fn split_free_node(free_node: &Rect, used_node: &Rect, new_rectangles: &mut Vec<Rect>) -> bool {
         // Test if the rectangles even intersect.
         if used_node.x >= free_node.x + free_node.width
             || used_node.x + used_node.width <= free_node.x
@@ -435,7 +510,7 @@ impl MaxRectsBinPack {
             if used_node.y > free_node.y && used_node.y < free_node.y + free_node.height {
                 let mut new_node = free_node.clone();
                 new_node.height = used_node.y - new_node.y;
-                self.free_rectangles.push(new_node);
+                new_rectangles.push(new_node);
             }
 
             // New node at the bottom side of the used node
@@ -443,7 +518,7 @@ impl MaxRectsBinPack {
                 let mut new_node = free_node.clone();
                 new_node.y = used_node.y + used_node.height;
                 new_node.height = free_node.y + free_node.height - (used_node.y + used_node.height);
-                self.free_rectangles.push(new_node);
+                new_rectangles.push(new_node);
             }
         }
 
@@ -454,7 +529,7 @@ impl MaxRectsBinPack {
             if used_node.x > free_node.x && used_node.x < free_node.x + free_node.width {
                 let mut new_node = free_node.clone();
                 new_node.width = used_node.x - new_node.x;
-                self.free_rectangles.push(new_node);
+                new_rectangles.push(new_node);
             }
 
             // New node at the right side of the used node
@@ -462,32 +537,28 @@ impl MaxRectsBinPack {
                 let mut new_node = free_node.clone();
                 new_node.x = used_node.x + used_node.width;
                 new_node.width = free_node.x + free_node.width - (used_node.x + used_node.width);
-                self.free_rectangles.push(new_node);
+                new_rectangles.push(new_node);
             }
         }
 
         true
     }
 
-    fn prune_free_list(&mut self) {
-        let mut i = 0;
-        while i < self.free_rectangles.len() {
-            let mut j = i + 1;
-            while j < self.free_rectangles.len() {
-                let a = &self.free_rectangles[i];
-                let b = &self.free_rectangles[j];
-                if a.is_contained_in(b) {
-                    self.free_rectangles.remove(i);
-                    i -= 1;
-                    break;
-                }
-                if b.is_contained_in(a) {
-                    self.free_rectangles.remove(j);
-                    j -= 1;
-                }
-                j += 1;
+    /// Adds the free rectangles left over by a split, dropping those contained in another free
+    /// rectangle. The existing free rectangles are never contained in each other, and can't be
+    /// contained in a new one either since it's part of a rectangle that was maximal, so only the
+    /// new ones need to be checked.
+    fn prune_free_list(&mut self, mut new_rectangles: Vec<Rect>) {
+        // Largest first, so any rectangle containing another one has already been added
+        new_rectangles.sort_by_key(|rect| std::cmp::Reverse(rect.width * rect.height));
+        for rect in new_rectangles {
+            if !self
+                .free_rectangles
+                .iter()
+                .any(|free| rect.is_contained_in(free))
+            {
+                self.free_rectangles.push(rect);
             }
-            i += 1;
         }
     }
 }
@@ -498,3 +569,16 @@ fn common_interval_length(i1start: i32, i1end: i32, i2start: i32, i2end: i32) ->
     }
     return std::cmp::min(i1end, i2end) - std::cmp::max(i1start, i2start);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_penalty_does_not_reject_the_only_fit() {
+        // Only fits rotated, and the penalty is far larger than any contact score
+        let mut bin = MaxRectsBinPack::new(20, 10).with_rotation_penalty(1000);
+        let node = bin.insert(10, 20, true, FreeRectChoiceHeuristic::RectContactPointRule);
+        assert_eq!((node.x, node.y, node.width, node.height), (0, 0, 20, 10));
+    }
+}
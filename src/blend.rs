@@ -0,0 +1,27 @@
+use image::RgbaImage;
+
+/// Draws `src` over `dst` at (`x`, `y`), with the given opacity
+pub fn over(dst: &mut RgbaImage, src: &RgbaImage, x: i32, y: i32, opacity: f32) {
+    for (sx, sy, pixel) in src.enumerate_pixels() {
+        let dx = x + sx as i32;
+        let dy = y + sy as i32;
+        if dx < 0 || dy < 0 || dx >= dst.width() as i32 || dy >= dst.height() as i32 {
+            continue;
+        }
+
+        let src_a = pixel[3] as f32 / 255.0 * opacity;
+        if src_a <= 0.0 {
+            continue;
+        }
+        let out = dst.get_pixel_mut(dx as u32, dy as u32);
+        let dst_a = out[3] as f32 / 255.0;
+        let out_a = src_a + dst_a * (1.0 - src_a);
+        for channel in 0..3 {
+            let color = (pixel[channel] as f32 * src_a
+                + out[channel] as f32 * dst_a * (1.0 - src_a))
+                / out_a;
+            out[channel] = color.round() as u8;
+        }
+        out[3] = (out_a * 255.0).round() as u8;
+    }
+}
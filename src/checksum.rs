@@ -0,0 +1,10 @@
+//! Hashes of the page images, recorded in the atlas data with `--checksums` so that mismatched
+//! or corrupted pages can be detected, and of the source files, with `--sources`.
+
+use crate::error::Result;
+use std::path::Path;
+
+/// The BLAKE3 hash of the file at `path`, in hex
+pub fn of_file(path: &Path) -> Result<String> {
+    Ok(blake3::hash(&std::fs::read(path)?).to_hex().to_string())
+}
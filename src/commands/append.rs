@@ -0,0 +1,203 @@
+use crate::error::{ImpactError, Result};
+use crate::image_wrapper::ImageWrapper;
+use crate::layout::Indices;
+use crate::packer::{Packer, Point, Rotation};
+use crate::rect::Rect;
+use crate::serial::Texture;
+use crate::{reserve, Opt};
+use image::RgbaImage;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub struct Append {
+    /// The metadata of the atlas to add sprites to
+    #[structopt(parse(from_os_str))]
+    metadata: PathBuf,
+
+    /// Files or folders with the new sprites
+    #[structopt(parse(from_os_str), required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// The page images of the atlas (by default, `<texture name>.png` next to the metadata,
+    /// can be repeated)
+    #[structopt(long = "page", number_of_values = 1, parse(from_os_str))]
+    pages: Vec<PathBuf>,
+
+    /// The output file name (by default, the atlas is written over)
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+impl Append {
+    pub fn run(self, opt: &Opt) -> Result<()> {
+        let mut atlas = super::read_atlas(&self.metadata)?;
+        let mut indices = Indices::default();
+        indices.add_atlas(&atlas);
+        // Sprites are only ever added, so the new ones are turned the same way as the old ones
+        let opt = Opt {
+            inputs: self.inputs.clone(),
+            rotation: atlas.rotation,
+            ..opt.clone()
+        };
+        let opt = &Opt {
+            folders: crate::config::load_folder_overrides(&opt)?,
+            ..opt
+        };
+
+        let mut packers = vec![];
+        let mut names = HashSet::new();
+        for (index, texture) in atlas.textures.iter().enumerate() {
+            let page = super::read_page(&self.metadata, texture, &self.pages)?;
+            names.extend(texture.images.iter().map(|image| image.name.clone()));
+            packers.push(existing_page(opt, &page, texture, atlas.rotation, index));
+        }
+
+        let (mut images, mut animations) = crate::load_inputs(opt, &crate::FileHashes::new())?;
+        if let Some(image) = images.iter().find(|image| names.contains(&image.name)) {
+            return Err(ImpactError::AlreadyInAtlas {
+                name: image.name.clone(),
+            });
+        }
+        log::info!("appending {} sprites.", images.len());
+
+        // Fill the free space of the existing pages first, in order, and only then add pages
+        images.sort_unstable_by(|a: &ImageWrapper, b: &ImageWrapper| {
+            a.priority
+                .cmp(&b.priority)
+                .then((a.width * a.height).cmp(&(b.width * b.height)))
+        });
+        for (index, packer) in packers.iter_mut().enumerate() {
+            // Pages are packed even without any sprites left, to shrink them back to size
+            let before = images.len();
+            packer.pack(
+                &mut images,
+                opt.unique,
+                opt.rotate,
+                opt.heuristic.into(),
+                &mut || (),
+            );
+            log::info!("added {} sprites to page {}", before - images.len(), index);
+            packer.reserved = reserve::rects(&opt.reserve, index);
+        }
+        if !images.is_empty() {
+            packers.extend(crate::pack_images(opt, images)?);
+        }
+        crate::resize_pages(opt, &mut packers);
+        for packer in packers.iter_mut() {
+            packer.pad_fill = opt.pad_fill;
+        }
+
+        let output = match self.output {
+            Some(output) => output,
+            None => self.metadata.with_extension(""),
+        };
+        let output_dir = output
+            .parent()
+            .expect("could not retrieve output directory");
+        let output_name = output
+            .file_name()
+            .expect("could not retrieve output filename");
+
+        if opt.print_plan {
+            crate::print_plan(opt, &packers);
+        }
+        crate::report_stats(opt, &packers)?;
+        // Sprites already in the atlas keep their indices, and new ones get the next ones
+        let indices = if opt.stable_indices {
+            Some(crate::assign_indices(&indices, &packers))
+        } else {
+            None
+        };
+        if opt.dry_run {
+            return Ok(());
+        }
+
+        atlas.animations.append(&mut animations);
+        crate::remove_old_files(opt, output_dir, output_name)?;
+        let dirty = vec![true; packers.len()];
+        crate::save_pages(opt, &packers, &dirty, output_dir, output_name)?;
+        crate::write_metadata(
+            opt,
+            &packers,
+            &atlas.animations,
+            indices.as_ref(),
+            output_dir,
+            output_name,
+        )
+    }
+}
+
+/// Rebuilds the page `index` of an atlas from its image, with every sprite left where it is
+/// and the space they take up reserved, so that new sprites can only go around them.
+///
+/// Sprites are redrawn with the current `--extrude` and `--pad`, which should be the ones the
+/// atlas was packed with.
+fn existing_page(
+    opt: &Opt,
+    page: &RgbaImage,
+    texture: &Texture,
+    rotation: Rotation,
+    index: usize,
+) -> Packer {
+    let size = opt.size as i32;
+    let mut packer = Packer::new(
+        std::cmp::max(size, texture.width),
+        std::cmp::max(size, texture.height),
+        opt.pad as i32,
+    );
+    packer.rotation = rotation;
+    packer.align = opt.align as i32;
+    packer.rotation_penalty = opt.rotation_penalty as i32;
+    packer.max_diff = opt.unique_fuzzy.unwrap_or(0);
+    packer.reserved = reserve::rects(&opt.reserve, index);
+
+    // Aliases share the position of the sprite they duplicate
+    let mut positions = HashMap::new();
+    for image in texture.images.iter() {
+        let sprite = super::unpack::extract_trimmed(page, image, rotation);
+        let mut sprite = ImageWrapper::new(sprite, image.name.clone(), false, false, 0, 0);
+        sprite.frame_x = image.frame_x;
+        sprite.frame_y = image.frame_y;
+        sprite.frame_w = image.frame_width;
+        sprite.frame_h = image.frame_height;
+        sprite.pivot_x = image.pivot_x;
+        sprite.pivot_y = image.pivot_y;
+        sprite.user_data = image.data.clone();
+        sprite.extrude = opt.extrude as i32;
+
+        let dup_id = match positions.get(&(image.x, image.y)) {
+            Some(&idx) => idx as i32,
+            None => {
+                positions.insert((image.x, image.y), packer.points.len());
+                if opt.unique {
+                    packer
+                        .dup_lookup
+                        .insert(sprite.hash_value, packer.points.len());
+                }
+                let (w, h) = if image.rotated {
+                    (image.height, image.width)
+                } else {
+                    (image.width, image.height)
+                };
+                let e = sprite.extrude;
+                packer.reserved.push(Rect {
+                    x: image.x - e,
+                    y: image.y - e,
+                    width: w + 2 * e + packer.pad,
+                    height: h + 2 * e + packer.pad,
+                });
+                -1
+            }
+        };
+        packer.points.push(Point {
+            x: image.x,
+            y: image.y,
+            dup_id,
+            rot: image.rotated,
+        });
+        packer.images.push(sprite);
+    }
+    packer
+}
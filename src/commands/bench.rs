@@ -0,0 +1,60 @@
+use crate::error::Result;
+use crate::stats::Stats;
+use crate::{FreeRectChoiceHeuristic, Opt};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub struct Bench {
+    /// Files or folders to pack
+    #[structopt(parse(from_os_str), required = true)]
+    inputs: Vec<PathBuf>,
+}
+
+impl Bench {
+    pub fn run(self, opt: &Opt) -> Result<()> {
+        let opt = Opt {
+            inputs: self.inputs,
+            ..opt.clone()
+        };
+        let opt = &Opt {
+            folders: crate::config::load_folder_overrides(&opt)?,
+            ..opt
+        };
+        let (images, _) = crate::load_inputs(opt, &crate::FileHashes::new())?;
+
+        println!(
+            "{:<16} {:>6} {:>10} {:>5} {:>9}",
+            "heuristic", "rotate", "time (ms)", "pages", "occupancy"
+        );
+        for heuristic in FreeRectChoiceHeuristic::variants().iter() {
+            let heuristic: FreeRectChoiceHeuristic = heuristic.parse().unwrap();
+            for &rotate in &[false, true] {
+                let opt = Opt {
+                    heuristic,
+                    rotate,
+                    quiet: true,
+                    ..opt.clone()
+                };
+                let start = Instant::now();
+                let packed = crate::pack_images(&opt, images.clone());
+                let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+                let heuristic = heuristic.to_string();
+                let rotate = if rotate { "yes" } else { "no" };
+                match packed {
+                    Ok(packers) => println!(
+                        "{:<16} {:>6} {:>10.1} {:>5} {:>8.1}%",
+                        heuristic,
+                        rotate,
+                        elapsed,
+                        packers.len(),
+                        Stats::new(&packers).occupancy() * 100.0
+                    ),
+                    Err(err) => println!("{:<16} {:>6} failed: {}", heuristic, rotate, err),
+                }
+            }
+        }
+        Ok(())
+    }
+}
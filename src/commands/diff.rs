@@ -0,0 +1,168 @@
+use crate::error::Result;
+use crate::serial::{Atlas, Image};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub struct Diff {
+    /// The metadata of the old atlas
+    #[structopt(parse(from_os_str))]
+    old: PathBuf,
+
+    /// The metadata of the new atlas
+    #[structopt(parse(from_os_str))]
+    new: PathBuf,
+
+    /// Print the differences as JSON
+    #[structopt(long)]
+    json: bool,
+}
+
+/// Where a sprite is and how big it is, in one of the atlases
+#[derive(Serialize, Debug, PartialEq)]
+struct Placement {
+    page: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    frame_width: i32,
+    frame_height: i32,
+    rotated: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Change<'a> {
+    name: &'a str,
+    old: &'a Placement,
+    new: &'a Placement,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Report<'a> {
+    added: Vec<&'a str>,
+    removed: Vec<&'a str>,
+    resized: Vec<Change<'a>>,
+    moved: Vec<Change<'a>>,
+}
+
+impl Diff {
+    pub fn run(self) -> Result<()> {
+        let old = placements(&super::read_atlas(&self.old)?);
+        let new = placements(&super::read_atlas(&self.new)?);
+
+        let mut report = Report::default();
+        for (name, old_placement) in old.iter() {
+            let new_placement = match new.get(name) {
+                Some(placement) => placement,
+                None => {
+                    report.removed.push(name);
+                    continue;
+                }
+            };
+            let change = Change {
+                name,
+                old: old_placement,
+                new: new_placement,
+            };
+
+            if (old_placement.width, old_placement.height)
+                != (new_placement.width, new_placement.height)
+                || (old_placement.frame_width, old_placement.frame_height)
+                    != (new_placement.frame_width, new_placement.frame_height)
+            {
+                report.resized.push(change);
+            } else if old_placement != new_placement {
+                report.moved.push(change);
+            }
+        }
+        report.added = new
+            .keys()
+            .filter(|name| !old.contains_key(*name))
+            .map(|name| name.as_str())
+            .collect();
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            print_report(&report);
+        }
+
+        Ok(())
+    }
+}
+
+fn placements(atlas: &Atlas) -> BTreeMap<String, Placement> {
+    atlas
+        .textures
+        .iter()
+        .flat_map(|texture| {
+            texture
+                .images
+                .iter()
+                .map(move |image: &Image| (image.name.clone(), placement(&texture.name, image)))
+        })
+        .collect()
+}
+
+fn placement(page: &str, image: &Image) -> Placement {
+    Placement {
+        page: page.to_owned(),
+        x: image.x,
+        y: image.y,
+        width: image.width,
+        height: image.height,
+        frame_width: image.frame_width,
+        frame_height: image.frame_height,
+        rotated: image.rotated,
+    }
+}
+
+fn print_report(report: &Report) {
+    for name in report.added.iter() {
+        println!("+ {}", name);
+    }
+    for name in report.removed.iter() {
+        println!("- {}", name);
+    }
+    for change in report.resized.iter() {
+        println!(
+            "~ {}: resized from {}x{} to {}x{} (frame {}x{} to {}x{})",
+            change.name,
+            change.old.width,
+            change.old.height,
+            change.new.width,
+            change.new.height,
+            change.old.frame_width,
+            change.old.frame_height,
+            change.new.frame_width,
+            change.new.frame_height
+        );
+    }
+    for change in report.moved.iter() {
+        println!(
+            "> {}: moved from {} ({}, {}) to {} ({}, {}){}",
+            change.name,
+            change.old.page,
+            change.old.x,
+            change.old.y,
+            change.new.page,
+            change.new.x,
+            change.new.y,
+            if change.old.rotated != change.new.rotated {
+                ", rotation changed"
+            } else {
+                ""
+            }
+        );
+    }
+    println!(
+        "{} added, {} removed, {} resized, {} moved",
+        report.added.len(),
+        report.removed.len(),
+        report.resized.len(),
+        report.moved.len()
+    );
+}
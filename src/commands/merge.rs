@@ -0,0 +1,98 @@
+use crate::error::Result;
+use crate::image_wrapper::ImageWrapper;
+use crate::layout::Indices;
+use crate::Opt;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub struct Merge {
+    /// The metadata of the atlases to combine (their pages are looked up next to them)
+    #[structopt(parse(from_os_str), required = true, min_values = 2)]
+    atlases: Vec<PathBuf>,
+
+    /// The combined output file name
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+}
+
+impl Merge {
+    pub fn run(self, opt: &Opt) -> Result<()> {
+        let mut images = vec![];
+        let mut animations = vec![];
+        let mut names = HashSet::new();
+        // The first atlas to number a sprite, or to use an index, keeps it
+        let mut indices = Indices::default();
+        for path in self.atlases.iter() {
+            let mut atlas = super::read_atlas(path)?;
+            indices.add_atlas(&atlas);
+            animations.append(&mut atlas.animations);
+            for texture in atlas.textures.iter() {
+                let page = super::read_page(path, texture, &[])?;
+
+                for image in texture.images.iter() {
+                    if !names.insert(image.name.clone()) {
+                        log::warn!("sprite {} is in more than one atlas", image.name);
+                    }
+                }
+                let sprites = texture.images.par_iter().map(|image| {
+                    let sprite = super::unpack::extract(&page, image, atlas.rotation);
+                    // The pages were already premultiplied, if asked for, when first packed
+                    let mut sprite = ImageWrapper::new(
+                        sprite,
+                        image.name.clone(),
+                        false,
+                        opt.trim,
+                        opt.trim_margin as i32,
+                        0,
+                    );
+                    sprite.pivot_x = image.pivot_x;
+                    sprite.pivot_y = image.pivot_y;
+                    sprite.user_data = image.data.clone();
+                    sprite.extrude = opt.extrude as i32;
+                    sprite.priority = crate::pattern_priority(opt, &sprite.name);
+                    sprite
+                });
+                images.par_extend(sprites);
+            }
+        }
+        log::info!("loaded {} sprites.", images.len());
+
+        let output_dir = self
+            .output
+            .parent()
+            .expect("could not retrieve output directory");
+        let output_name = self
+            .output
+            .file_name()
+            .expect("could not retrieve output filename");
+
+        let packers = crate::pack_images(opt, images)?;
+        if opt.print_plan {
+            crate::print_plan(opt, &packers);
+        }
+        crate::report_stats(opt, &packers)?;
+        let indices = if opt.stable_indices {
+            Some(crate::assign_indices(&indices, &packers))
+        } else {
+            None
+        };
+        if opt.dry_run {
+            return Ok(());
+        }
+
+        crate::remove_old_files(opt, output_dir, output_name)?;
+        let dirty = vec![true; packers.len()];
+        crate::save_pages(opt, &packers, &dirty, output_dir, output_name)?;
+        crate::write_metadata(
+            opt,
+            &packers,
+            &animations,
+            indices.as_ref(),
+            output_dir,
+            output_name,
+        )
+    }
+}
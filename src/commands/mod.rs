@@ -0,0 +1,116 @@
+//! Subcommands for working with atlases that have already been packed.
+
+use crate::error::{ImpactError, Result};
+use crate::serial::{Atlas, Texture};
+use crate::Opt;
+use image::RgbaImage;
+use impact::formats::bundle::Bundle;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+mod append;
+mod bench;
+mod diff;
+mod merge;
+mod serve;
+mod tileset;
+mod unpack;
+mod verify;
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub enum Command {
+    /// Extract every sprite of an atlas back into individual images
+    Unpack(unpack::Unpack),
+    /// Combine several atlases into one, repacking their sprites with the global options
+    Merge(merge::Merge),
+    /// Pack new sprites into the free space of an existing atlas, without moving the ones
+    /// already in it
+    Append(append::Append),
+    /// Report the sprites added, removed, resized or moved between two atlases
+    Diff(diff::Diff),
+    /// Check that an atlas's metadata is consistent with itself and its page images
+    Verify(verify::Verify),
+    /// Pack the inputs with every heuristic, with and without rotation, and compare the results
+    Bench(bench::Bench),
+    /// Lay out same-sized tiles on a uniform grid in input order, with a Tiled tileset
+    Tileset(tileset::Tileset),
+    /// Repack the atlases whenever their inputs change, and serve their pages, atlas data and a
+    /// preview page that reloads itself over HTTP
+    Serve(serve::Serve),
+}
+
+impl Command {
+    pub fn run(self, opt: &Opt) -> Result<()> {
+        match self {
+            Command::Unpack(unpack) => unpack.run(),
+            Command::Merge(merge) => merge.run(opt),
+            Command::Append(append) => append.run(opt),
+            Command::Diff(diff) => diff.run(),
+            Command::Verify(verify) => verify.run(),
+            Command::Bench(bench) => bench.run(opt),
+            Command::Tileset(tileset) => tileset.run(opt),
+            Command::Serve(serve) => serve.run(opt, vec![]),
+        }
+    }
+}
+
+/// Reads atlas metadata, in any of the formats that describe every sprite (`.json`, `.xml`,
+/// `.bin`, `.cbor`, `.pb` or an `.impact` bundle).
+///
+/// Aliases are listed as sprites of their own, so that commands don't need to handle them.
+pub fn read_atlas(path: &Path) -> Result<Atlas> {
+    let bytes = std::fs::read(path)?;
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    let mut atlas = match extension.as_deref() {
+        Some("json") => serde_json::from_slice(&bytes)?,
+        Some("xml") => Atlas::from_xml(bytes.as_slice())?,
+        Some("bin") => Atlas::from_binary(&bytes)?,
+        Some("cbor") => serde_cbor::from_slice(&bytes)?,
+        Some("pb") => Atlas::from_proto(&bytes)?,
+        Some("impact") => Bundle::new(&bytes)?.atlas()?,
+        _ => {
+            return Err(ImpactError::InvalidAtlas {
+                reason: format!("unsupported metadata format: {}", path.display()),
+            })
+        }
+    };
+    atlas.expand_aliases();
+    Ok(atlas)
+}
+
+/// Finds the image of a page: one of `pages` named after the texture, or otherwise a PNG
+/// next to the metadata file
+pub fn page_path(metadata: &Path, texture: &Texture, pages: &[PathBuf]) -> PathBuf {
+    pages
+        .iter()
+        .find(|page| {
+            page.file_stem()
+                .map_or(false, |stem| stem.to_string_lossy() == texture.name)
+        })
+        .cloned()
+        .unwrap_or_else(|| metadata.with_file_name(&texture.name).with_extension("png"))
+}
+
+/// Reads the page of `texture`, from the bundle if the metadata is one, or otherwise from the
+/// file found by `page_path`
+pub fn read_page(metadata: &Path, texture: &Texture, pages: &[PathBuf]) -> Result<RgbaImage> {
+    let is_bundle = metadata
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("impact"));
+    if is_bundle && pages.is_empty() {
+        let bytes = std::fs::read(metadata)?;
+        let bundle = Bundle::new(&bytes)?;
+        let (name, data) = bundle
+            .page(texture)
+            .ok_or_else(|| ImpactError::InvalidAtlas {
+                reason: format!("bundle has no page for texture {}", texture.name),
+            })?;
+        log::info!("reading page {} from {}", name, metadata.display());
+        return Ok(image::load_from_memory(data)?.to_rgba8());
+    }
+    let page_path = page_path(metadata, texture, pages);
+    log::info!("reading page {}", page_path.display());
+    Ok(image::open(&page_path)?.to_rgba8())
+}
@@ -0,0 +1,248 @@
+use crate::error::Result;
+use crate::Opt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use structopt::StructOpt;
+
+/// How often the preview page asks whether the atlas was packed again, in milliseconds
+const POLL_INTERVAL: u32 = 500;
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub struct Serve {
+    /// Files or folders to pack (by default, the atlases of the config file)
+    #[structopt(parse(from_os_str))]
+    inputs: Vec<PathBuf>,
+
+    /// The output file name, when packing the inputs given to `serve`
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// The port to listen on
+    #[structopt(long, default_value = "8000")]
+    port: u16,
+
+    /// The address to listen on. Anything but a local address makes the atlas readable from
+    /// other machines.
+    #[structopt(long, default_value = "127.0.0.1")]
+    host: String,
+}
+
+impl Serve {
+    /// Packs `atlases` whenever their inputs change, like `--watch`, and serves their files
+    pub fn run(self, opt: &Opt, mut atlases: Vec<Opt>) -> Result<()> {
+        if !self.inputs.is_empty() {
+            atlases = vec![Opt {
+                inputs: self.inputs.clone(),
+                output: self.output.clone().or_else(|| opt.output.clone()),
+                ..opt.clone()
+            }];
+        } else if atlases.is_empty() {
+            atlases.push(opt.clone());
+        }
+        for atlas in &atlases {
+            crate::validate(atlas)?;
+        }
+
+        let listener = TcpListener::bind((self.host.as_str(), self.port))?;
+        log::info!("serving the atlas at http://{}", listener.local_addr()?);
+        let state = Arc::new(Mutex::new(State::default()));
+        {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            log::warn!("failed to accept connection: {}", err);
+                            continue;
+                        }
+                    };
+                    // Browsers can open connections they don't use, so each gets its own thread
+                    let state = state.lock().expect("server state was poisoned").clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) = respond(stream, &state) {
+                            log::warn!("failed to answer request: {}", err);
+                        }
+                    });
+                }
+            });
+        }
+
+        let inputs: Vec<PathBuf> = atlases
+            .iter()
+            .flat_map(|atlas| atlas.inputs.iter().cloned())
+            .collect();
+        crate::watch::watch(&inputs, &crate::written_dirs(&atlases), || {
+            let result = crate::run_all(&atlases).map(|_| ());
+            let mut files = vec![];
+            for atlas in &atlases {
+                match crate::existing_output_files(atlas) {
+                    Ok(outputs) => files.extend(outputs),
+                    Err(err) => log::warn!("failed to list the files of the atlas: {}", err),
+                }
+            }
+
+            let mut state = state.lock().expect("server state was poisoned");
+            state.version += 1;
+            state.files = files;
+            state.error = result.as_ref().err().map(ToString::to_string);
+            result
+        })
+    }
+}
+
+/// What the server knows about the last pack
+#[derive(Default, Clone)]
+struct State {
+    /// Goes up every time the atlas is packed, so that the preview knows to reload
+    version: u64,
+    /// The atlas data and page files, served by file name
+    files: Vec<PathBuf>,
+    /// Why the last pack failed, shown on the preview
+    error: Option<String>,
+}
+
+impl State {
+    fn file(&self, name: &str) -> Option<&PathBuf> {
+        self.files
+            .iter()
+            .find(|file| file.file_name().map_or(false, |file| file == name))
+    }
+}
+
+/// Answers a single request. Only files of the atlas can be read, so that the server doesn't
+/// expose anything else on disk.
+fn respond(mut stream: TcpStream, state: &State) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers don't matter, but have to be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default();
+    log::debug!("{} {}", method, target);
+    if method != "GET" {
+        return reply(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+
+    match path {
+        "/" => reply(
+            &mut stream,
+            "200 OK",
+            "text/html",
+            preview(state).as_bytes(),
+        ),
+        "/version" => reply(
+            &mut stream,
+            "200 OK",
+            "text/plain",
+            state.version.to_string().as_bytes(),
+        ),
+        _ => match state.file(path.trim_start_matches('/')) {
+            Some(file) => match std::fs::read(file) {
+                Ok(bytes) => reply(&mut stream, "200 OK", content_type(file), &bytes),
+                Err(_) => reply(&mut stream, "404 Not Found", "text/plain", b"not found"),
+            },
+            None => reply(&mut stream, "404 Not Found", "text/plain", b"not found"),
+        },
+    }
+}
+
+fn reply(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
+
+fn content_type(path: &std::path::Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("ron") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The preview page, showing every page on a checkerboard and linking the atlas data. It
+/// reloads itself when the atlas is packed again.
+fn preview(state: &State) -> String {
+    let mut pages = String::new();
+    let mut data = String::new();
+    for file in &state.files {
+        let name = escape(&file.file_name().unwrap_or_default().to_string_lossy());
+        if content_type(file).starts_with("image/") {
+            pages.push_str(&format!(
+                "<figure><img src=\"/{0}\"><figcaption>{0}</figcaption></figure>\n",
+                name
+            ));
+        } else {
+            data.push_str(&format!("<li><a href=\"/{0}\">{0}</a></li>\n", name));
+        }
+    }
+    let error = state.error.as_ref().map_or_else(String::new, |err| {
+        format!("<p class=\"error\">{}</p>\n", escape(err))
+    });
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>impact</title>
+<style>
+body {{ font-family: sans-serif; background: #222; color: #ddd; }}
+a {{ color: #8cf; }}
+.error {{ color: #f66; white-space: pre-wrap; }}
+figure {{ display: inline-block; margin: 1em; }}
+img {{
+  image-rendering: pixelated;
+  background: repeating-conic-gradient(#999 0 25%, #666 0 50%) 0 0 / 16px 16px;
+}}
+</style>
+</head>
+<body>
+{}<ul>
+{}</ul>
+{}<script>
+const version = "{}";
+setInterval(async () => {{
+  try {{
+    const response = await fetch("/version");
+    if ((await response.text()) !== version) location.reload();
+  }} catch (err) {{}}
+}}, {});
+</script>
+</body>
+</html>
+"#,
+        error, data, pages, state.version, POLL_INTERVAL
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,221 @@
+use crate::error::{ImpactError, Result};
+use crate::image_wrapper::ImageWrapper;
+use crate::Opt;
+use image::RgbaImage;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// The version of the Tiled map format the tilesets are written in
+const TILED_VERSION: &str = "1.10";
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub struct Tileset {
+    /// Folders of tiles, or sheets to cut into tiles with --slice
+    #[structopt(parse(from_os_str), required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// The output file name, without an extension (the image is always a PNG)
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// The number of tiles in every row (by default, enough to make the tileset about square)
+    #[structopt(long)]
+    columns: Option<u32>,
+
+    /// The space between tiles, in pixels
+    #[structopt(long, default_value = "0")]
+    spacing: u32,
+
+    /// The space around the tiles, in pixels
+    #[structopt(long, default_value = "0")]
+    margin: u32,
+
+    /// Write a Tiled JSON tileset (`.json`) instead of a TSX file
+    #[structopt(long)]
+    json: bool,
+}
+
+impl Tileset {
+    pub fn run(self, opt: &Opt) -> Result<()> {
+        // Tiles keep their full size, since their position in the grid is all there is to them
+        let opt = Opt {
+            inputs: self.inputs.clone(),
+            trim: false,
+            crop: false,
+            ..opt.clone()
+        };
+        let opt = &Opt {
+            folders: crate::config::load_folder_overrides(&opt)?,
+            ..opt
+        };
+        let (tiles, _) = crate::load_inputs(opt, &crate::FileHashes::new())?;
+        let first = tiles.first().ok_or_else(|| ImpactError::InvalidTileset {
+            reason: "there are no tiles".to_owned(),
+        })?;
+        let (tile_width, tile_height) = (first.width as u32, first.height as u32);
+        if let Some(tile) = tiles
+            .iter()
+            .find(|tile| tile.width as u32 != tile_width || tile.height as u32 != tile_height)
+        {
+            return Err(ImpactError::InvalidTileset {
+                reason: format!(
+                    "tile {} is {}x{}, but the first tile is {}x{}",
+                    tile.name, tile.width, tile.height, tile_width, tile_height
+                ),
+            });
+        }
+
+        let count = tiles.len() as u32;
+        let columns = match self.columns {
+            Some(columns) => std::cmp::max(columns, 1),
+            None => (f64::from(count).sqrt().ceil() as u32).max(1),
+        };
+        let rows = (count + columns - 1) / columns;
+        let width = 2 * self.margin + columns * tile_width + (columns - 1) * self.spacing;
+        let height = 2 * self.margin + rows * tile_height + (rows - 1) * self.spacing;
+        log::info!(
+            "packing {} tiles of {}x{} into {} columns ({}x{})",
+            count,
+            tile_width,
+            tile_height,
+            columns,
+            width,
+            height
+        );
+
+        let mut sheet = RgbaImage::new(width, height);
+        for (index, tile) in tiles.iter().enumerate() {
+            let (x, y) = self.position(index as u32, columns, tile_width, tile_height);
+            image::imageops::replace(&mut sheet, &tile.get_image(), i64::from(x), i64::from(y));
+        }
+
+        let image_path = self.output.with_extension("png");
+        log::info!("writing tileset image {}", image_path.display());
+        sheet.save(&image_path)?;
+
+        let image_name = image_path
+            .file_name()
+            .expect("could not retrieve output filename")
+            .to_string_lossy()
+            .into_owned();
+        let layout = Layout {
+            name: self
+                .output
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            image: image_name,
+            width,
+            height,
+            tile_width,
+            tile_height,
+            columns,
+            spacing: self.spacing,
+            margin: self.margin,
+        };
+        if self.json {
+            let path = self.output.with_extension("json");
+            log::info!("writing tileset {}", path.display());
+            std::fs::write(&path, serde_json::to_vec_pretty(&layout.to_json(&tiles))?)?;
+        } else {
+            let path = self.output.with_extension("tsx");
+            log::info!("writing tileset {}", path.display());
+            std::fs::write(&path, layout.to_tsx(&tiles)?)?;
+        }
+        Ok(())
+    }
+
+    /// The top-left corner of the tile `index` in the tileset image
+    fn position(&self, index: u32, columns: u32, tile_width: u32, tile_height: u32) -> (u32, u32) {
+        let (column, row) = (index % columns, index / columns);
+        (
+            self.margin + column * (tile_width + self.spacing),
+            self.margin + row * (tile_height + self.spacing),
+        )
+    }
+}
+
+/// Everything Tiled needs to know about the tileset image
+struct Layout {
+    name: String,
+    image: String,
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    columns: u32,
+    spacing: u32,
+    margin: u32,
+}
+
+impl Layout {
+    /// A Tiled JSON tileset, with the sprite name of every tile as its `name` property
+    fn to_json(&self, tiles: &[ImageWrapper]) -> serde_json::Value {
+        let properties: Vec<_> = tiles
+            .iter()
+            .enumerate()
+            .map(|(id, tile)| {
+                serde_json::json!({
+                    "id": id,
+                    "properties": [{ "name": "name", "type": "string", "value": tile.name }],
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "type": "tileset",
+            "version": TILED_VERSION,
+            "name": self.name,
+            "image": self.image,
+            "imagewidth": self.width,
+            "imageheight": self.height,
+            "tilewidth": self.tile_width,
+            "tileheight": self.tile_height,
+            "tilecount": tiles.len(),
+            "columns": self.columns,
+            "spacing": self.spacing,
+            "margin": self.margin,
+            "tiles": properties,
+        })
+    }
+
+    /// A Tiled TSX tileset, with the sprite name of every tile as its `name` property
+    fn to_tsx(&self, tiles: &[ImageWrapper]) -> Result<Vec<u8>> {
+        use xml::writer::XmlEvent;
+
+        let mut bytes = vec![];
+        let mut writer = xml::writer::EmitterConfig::new()
+            .perform_indent(true)
+            .create_writer(&mut bytes);
+        writer.write(
+            XmlEvent::start_element("tileset")
+                .attr("version", TILED_VERSION)
+                .attr("name", &self.name)
+                .attr("tilewidth", &self.tile_width.to_string())
+                .attr("tileheight", &self.tile_height.to_string())
+                .attr("spacing", &self.spacing.to_string())
+                .attr("margin", &self.margin.to_string())
+                .attr("tilecount", &tiles.len().to_string())
+                .attr("columns", &self.columns.to_string()),
+        )?;
+        writer.write(
+            XmlEvent::start_element("image")
+                .attr("source", &self.image)
+                .attr("width", &self.width.to_string())
+                .attr("height", &self.height.to_string()),
+        )?;
+        writer.write(XmlEvent::end_element())?;
+        for (id, tile) in tiles.iter().enumerate() {
+            writer.write(XmlEvent::start_element("tile").attr("id", &id.to_string()))?;
+            writer.write(XmlEvent::start_element("properties"))?;
+            writer.write(
+                XmlEvent::start_element("property")
+                    .attr("name", "name")
+                    .attr("value", &tile.name),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+            writer.write(XmlEvent::end_element())?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        writer.write(XmlEvent::end_element())?;
+        Ok(bytes)
+    }
+}
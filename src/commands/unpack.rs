@@ -0,0 +1,81 @@
+use crate::error::Result;
+use crate::packer::Rotation;
+use crate::serial::Image;
+use image::{imageops, RgbaImage};
+use std::path::{Component, Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub struct Unpack {
+    /// The atlas metadata
+    #[structopt(parse(from_os_str))]
+    metadata: PathBuf,
+
+    /// The page images (by default, `<texture name>.png` next to the metadata)
+    #[structopt(parse(from_os_str))]
+    pages: Vec<PathBuf>,
+
+    /// The directory to write the sprites to
+    #[structopt(short, long, default_value = ".", parse(from_os_str))]
+    output: PathBuf,
+}
+
+impl Unpack {
+    pub fn run(self) -> Result<()> {
+        let atlas = super::read_atlas(&self.metadata)?;
+
+        for texture in atlas.textures.iter() {
+            let page = super::read_page(&self.metadata, texture, &self.pages)?;
+
+            for image in texture.images.iter() {
+                let out_path = self.output.join(sprite_path(&image.name));
+                log::info!("writing sprite {}", out_path.display());
+                if let Some(parent) = out_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                extract(&page, image, atlas.rotation).save(out_path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Cuts a sprite out of its page, undoing rotation and trimming
+pub fn extract(page: &RgbaImage, image: &Image, rotation: Rotation) -> RgbaImage {
+    let sprite = extract_trimmed(page, image, rotation);
+    let mut frame = RgbaImage::new(image.frame_width as u32, image.frame_height as u32);
+    imageops::replace(
+        &mut frame,
+        &sprite,
+        -image.frame_x as i64,
+        -image.frame_y as i64,
+    );
+    frame
+}
+
+/// Cuts a sprite out of its page, undoing rotation but keeping it trimmed
+pub fn extract_trimmed(page: &RgbaImage, image: &Image, rotation: Rotation) -> RgbaImage {
+    let (w, h) = if image.rotated {
+        (image.height, image.width)
+    } else {
+        (image.width, image.height)
+    };
+    let region = imageops::crop_imm(page, image.x as u32, image.y as u32, w as u32, h as u32);
+    match (image.rotated, rotation) {
+        (true, Rotation::Cw) => imageops::rotate270(&region.to_image()),
+        (true, Rotation::Ccw) => imageops::rotate90(&region.to_image()),
+        (false, _) => region.to_image(),
+    }
+}
+
+/// The relative path of a sprite's file, ignoring any root or `..` in its name
+fn sprite_path(name: &str) -> PathBuf {
+    let path: PathBuf = Path::new(name)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+    let mut path = path.into_os_string();
+    path.push(".png");
+    path.into()
+}
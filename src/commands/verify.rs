@@ -0,0 +1,146 @@
+use crate::error::{ImpactError, Result};
+use crate::serial::{Image, Texture};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone, Hash)]
+pub struct Verify {
+    /// The atlas metadata
+    #[structopt(parse(from_os_str))]
+    metadata: PathBuf,
+
+    /// The page images (by default, `<texture name>.png` next to the metadata)
+    #[structopt(parse(from_os_str))]
+    pages: Vec<PathBuf>,
+}
+
+impl Verify {
+    pub fn run(self) -> Result<()> {
+        let atlas = super::read_atlas(&self.metadata)?;
+
+        let mut problems = vec![];
+        for texture in atlas.textures.iter() {
+            check_page_image(
+                &super::page_path(&self.metadata, texture, &self.pages),
+                texture,
+                &mut problems,
+            );
+            for image in texture.images.iter() {
+                check_image(texture, image, &mut problems);
+            }
+            check_overlaps(texture, &mut problems);
+        }
+
+        if problems.is_empty() {
+            println!("{} is valid", self.metadata.display());
+            return Ok(());
+        }
+        for problem in problems.iter() {
+            log::error!("{}", problem);
+        }
+        Err(ImpactError::InvalidAtlas {
+            reason: format!("{} problem(s) found", problems.len()),
+        })
+    }
+}
+
+/// The rectangle a sprite covers in its page
+fn placed_rect(image: &Image) -> (i32, i32, i32, i32) {
+    if image.rotated {
+        (image.x, image.y, image.height, image.width)
+    } else {
+        (image.x, image.y, image.width, image.height)
+    }
+}
+
+fn check_page_image(path: &std::path::Path, texture: &Texture, problems: &mut Vec<String>) {
+    if let Some(expected) = &texture.checksum {
+        match crate::checksum::of_file(path) {
+            Ok(checksum) if checksum != *expected => problems.push(format!(
+                "the checksum of page {} doesn't match {}, which may be corrupted or from another \
+                 build",
+                texture.name,
+                path.display()
+            )),
+            _ => {}
+        }
+    }
+    match image::image_dimensions(path) {
+        Ok((width, height)) => {
+            if (width as i32, height as i32) != (texture.width, texture.height) {
+                problems.push(format!(
+                    "page {} is {}x{}, but {} is {}x{}",
+                    texture.name,
+                    texture.width,
+                    texture.height,
+                    path.display(),
+                    width,
+                    height
+                ));
+            }
+        }
+        Err(err) => problems.push(format!(
+            "can't read the image of page {} ({}): {}",
+            texture.name,
+            path.display(),
+            err
+        )),
+    }
+}
+
+fn check_image(texture: &Texture, image: &Image, problems: &mut Vec<String>) {
+    let (x, y, w, h) = placed_rect(image);
+    if w <= 0 || h <= 0 {
+        problems.push(format!("{} has an empty size", image.name));
+    }
+    if x < 0 || y < 0 || x + w > texture.width || y + h > texture.height {
+        problems.push(format!(
+            "{} ({}x{} at {}, {}) is outside of page {} ({}x{})",
+            image.name, w, h, x, y, texture.name, texture.width, texture.height
+        ));
+    }
+    if image.frame_x > 0
+        || image.frame_y > 0
+        || image.width - image.frame_x > image.frame_width
+        || image.height - image.frame_y > image.frame_height
+    {
+        problems.push(format!(
+            "{} ({}x{}) doesn't fit in its frame ({}x{} at {}, {})",
+            image.name,
+            image.width,
+            image.height,
+            image.frame_width,
+            image.frame_height,
+            image.frame_x,
+            image.frame_y
+        ));
+    }
+}
+
+/// Duplicates share the exact same rectangle; any other overlap is an error
+fn check_overlaps(texture: &Texture, problems: &mut Vec<String>) {
+    let mut images: Vec<&Image> = texture.images.iter().collect();
+    images.sort_by_key(|image| image.y);
+
+    for (idx, a) in images.iter().enumerate() {
+        let (ax, ay, aw, ah) = placed_rect(a);
+        for b in images[idx + 1..].iter() {
+            let (bx, by, bw, bh) = placed_rect(b);
+            if by >= ay + ah {
+                break;
+            }
+            if bx >= ax + aw || ax >= bx + bw {
+                continue;
+            }
+
+            if (ax, ay, aw, ah) != (bx, by, bw, bh) {
+                problems.push(format!("{} overlaps {}", a.name, b.name));
+            } else if a.rotated != b.rotated || (a.width, a.height) != (b.width, b.height) {
+                problems.push(format!(
+                    "{} and {} share a rectangle but not a rotation",
+                    a.name, b.name
+                ));
+            }
+        }
+    }
+}
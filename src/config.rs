@@ -0,0 +1,540 @@
+use crate::error::{ImpactError, Result};
+use crate::hdr::Tonemap;
+use crate::packer::{PadFill, Rotation};
+use crate::reserve::Reserve;
+use crate::scale::Scale;
+use crate::slice::{Slice, Strip};
+use crate::{naming, walk, Format, FreeRectChoiceHeuristic, LogFormat, Opt, RetryOverflow, Shrink};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use structopt::clap::ArgMatches;
+
+/// Project settings loaded from an `impact.toml` file.
+///
+/// Every field is optional; anything given on the command line takes precedence.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub output: Option<PathBuf>,
+    pub archive: Option<PathBuf>,
+    pub inputs: Vec<PathBuf>,
+    pub include: Vec<String>,
+    pub follow_symlinks: Option<bool>,
+    pub hidden: Option<bool>,
+    pub max_depth: Option<usize>,
+    pub split_by_folder: Option<bool>,
+
+    pub default: Option<bool>,
+    pub xml: Option<bool>,
+    pub binary: Option<bool>,
+    pub json: Option<bool>,
+    pub format: Vec<String>,
+    pub stdout: Option<String>,
+    pub long_keys: Option<bool>,
+    pub meta: Option<bool>,
+    pub checksums: Option<bool>,
+    pub sources: Option<bool>,
+    pub stable_indices: Option<bool>,
+
+    pub premultiply: Option<bool>,
+    pub alpha_variants: Option<bool>,
+    pub trim: Option<bool>,
+    pub crop: Option<bool>,
+    pub trim_margin: Option<u8>,
+    pub verbose: Option<u8>,
+    pub quiet: Option<bool>,
+    pub log_file: Option<PathBuf>,
+    pub no_log_file: Option<bool>,
+    pub log_format: Option<String>,
+    pub cache_dir: Option<PathBuf>,
+    pub paranoid: Option<bool>,
+    pub unique: Option<bool>,
+    pub unique_fuzzy: Option<u8>,
+    pub aliases: Option<bool>,
+    pub rotate: Option<bool>,
+    pub rotation: Option<Rotation>,
+    pub rotation_penalty: Option<u16>,
+    pub size: Option<u16>,
+    pub pad: Option<u8>,
+    pub pad_fill: Option<PadFill>,
+    pub extrude: Option<u8>,
+    pub align: Option<u16>,
+    pub reserve: Vec<Reserve>,
+    pub scale: Option<Scale>,
+    pub input_scale_suffix: Option<bool>,
+    pub keep_together: Vec<String>,
+    pub priority: Vec<String>,
+    pub heuristic: Option<String>,
+    pub retry_overflow: Option<String>,
+    pub shrink: Option<String>,
+    pub fixed_size: Option<bool>,
+    pub pot: Option<bool>,
+    pub multiple_of: Option<u16>,
+    pub tile_oversized: Option<bool>,
+    pub min_sprite_size: Option<u16>,
+    pub max_sprite_size: Option<u16>,
+    pub skip_bad_inputs: Option<bool>,
+    pub extension: Option<String>,
+    pub quality: Option<u8>,
+    pub tonemap: Option<Tonemap>,
+    pub page_name: Option<String>,
+    pub animations: Option<bool>,
+    pub animation_suffix: Option<String>,
+    pub animations_from_folders: Option<bool>,
+    pub aseprite_layers: Option<bool>,
+    pub aseprite_tags: Option<bool>,
+    pub gif_animations: Option<bool>,
+    pub psd_layers: Option<bool>,
+    pub slice: Vec<Slice>,
+    pub strip: Vec<Strip>,
+    pub flatten: Option<bool>,
+    pub basepath: Option<PathBuf>,
+    pub strip_prefix: Option<PathBuf>,
+    pub rename_duplicates: Option<bool>,
+    pub name_separator: Option<String>,
+    pub trim_numbers: Option<bool>,
+
+    pub folders: Vec<FolderOverride>,
+    /// Atlases packed in the same run, each starting from the options above
+    pub atlases: Vec<Config>,
+}
+
+/// The name of the file that overrides the settings of the folder it's in
+pub const FOLDER_CONFIG: &str = "folder.impact.toml";
+
+/// Settings that apply to every image inside a folder, overriding the global ones.
+///
+/// These come from the `[[folders]]` of the config file, or from a `folder.impact.toml`
+/// file inside the folder itself (without a `path`).
+#[derive(Deserialize, Debug, Clone, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct FolderOverride {
+    #[serde(default)]
+    pub path: PathBuf,
+    pub trim: Option<bool>,
+    pub premultiply: Option<bool>,
+    pub padding: Option<u8>,
+    pub extrude: Option<u8>,
+    pub scale: Option<Scale>,
+}
+
+impl FolderOverride {
+    /// Loads the `folder.impact.toml` inside `folder`, if there is one
+    fn load(folder: &Path) -> Result<Option<Self>> {
+        let path = folder.join(FOLDER_CONFIG);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        log::info!("Reading folder settings {}", path.display());
+        let contents = std::fs::read_to_string(&path)?;
+        let mut folder_override: FolderOverride = toml::from_str(&contents)?;
+        if !folder_override.path.as_os_str().is_empty() {
+            return Err(invalid(format!(
+                "{} can't have a path, it applies to its own folder",
+                path.display()
+            )));
+        }
+        folder_override.path = folder.canonicalize()?;
+        folder_override.validate()?;
+        Ok(Some(folder_override))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.padding.map_or(false, |padding| padding > 16) {
+            return Err(invalid(format!(
+                "padding of {} can be from 0 to 16",
+                self.path.display()
+            )));
+        }
+        if self.extrude.map_or(false, |extrude| extrude > 16) {
+            return Err(invalid(format!(
+                "extrude of {} can be from 0 to 16",
+                self.path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Adds the `folder.impact.toml` files found in the input folders to the folder overrides,
+/// ordered so that more deeply nested folders are applied last
+pub fn load_folder_overrides(opt: &Opt) -> Result<Vec<FolderOverride>> {
+    let mut folders = opt.folders.clone();
+    for input in opt.inputs.iter().filter(|input| input.is_dir()) {
+        walk::walk_folders(input, opt, &mut |folder| {
+            if let Some(folder_override) = FolderOverride::load(folder)? {
+                folders.push(folder_override);
+            }
+            Ok(())
+        })?;
+    }
+    // Sorting is stable, so the config file still wins over a file in the same folder
+    folders.sort_by_key(|folder| folder.path.components().count());
+    Ok(folders)
+}
+
+/// The settings used when loading a single image
+#[derive(Debug, Copy, Clone)]
+pub struct ImageSettings {
+    pub premultiply: bool,
+    pub trim: bool,
+    /// The padding after the image, if it overrides `--pad`
+    pub pad: Option<i32>,
+    pub extrude: i32,
+    pub scale: Scale,
+}
+
+impl ImageSettings {
+    /// Resolves the settings for the image at `path`, applying any folder overrides
+    /// that contain it (more deeply nested folders win)
+    pub fn for_path(path: &Path, opt: &Opt) -> Result<Self> {
+        // With --alpha-variants, the premultiplied pages are made from the straight ones as
+        // they're saved
+        let mut settings = Self {
+            premultiply: opt.premultiply && !opt.alpha_variants,
+            trim: opt.trim || opt.crop,
+            pad: None,
+            extrude: opt.extrude as i32,
+            scale: opt.scale,
+        };
+
+        if opt.input_scale_suffix {
+            if let Some(density) = naming::density(path) {
+                settings.scale = settings.scale.for_density(density);
+            }
+        }
+
+        if opt.folders.is_empty() {
+            return Ok(settings);
+        }
+
+        let path = path.canonicalize()?;
+        for folder in opt.folders.iter() {
+            if path.starts_with(&folder.path) {
+                if let Some(trim) = folder.trim {
+                    settings.trim = trim;
+                }
+                if let Some(premultiply) = folder.premultiply {
+                    settings.premultiply = premultiply && !opt.alpha_variants;
+                }
+                if let Some(padding) = folder.padding {
+                    settings.pad = Some(padding as i32);
+                }
+                if let Some(extrude) = folder.extrude {
+                    settings.extrude = extrude as i32;
+                }
+                if let Some(scale) = folder.scale {
+                    settings.scale = scale;
+                }
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+        let mut config: Config = toml::from_str(&contents)?;
+
+        // Paths in the config file are relative to the file itself
+        let base = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        config.resolve_paths(base)?;
+        for atlas in config.atlases.iter_mut() {
+            if !atlas.atlases.is_empty() {
+                return Err(invalid("[[atlases]] entries can't have atlases".to_owned()));
+            }
+            if atlas.output.is_none() {
+                return Err(invalid(
+                    "every [[atlases]] entry needs an output".to_owned(),
+                ));
+            }
+            atlas.resolve_paths(base)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Makes the paths in the config relative to `base`, the folder of the file
+    fn resolve_paths(&mut self, base: &Path) -> Result<()> {
+        self.output = self.output.take().map(|output| base.join(output));
+        self.archive = self.archive.take().map(|archive| base.join(archive));
+        self.log_file = self.log_file.take().map(|log_file| base.join(log_file));
+        self.cache_dir = self.cache_dir.take().map(|cache_dir| base.join(cache_dir));
+        self.basepath = self.basepath.take().map(|basepath| base.join(basepath));
+        self.strip_prefix = self.strip_prefix.take().map(|prefix| base.join(prefix));
+        for input in self.inputs.iter_mut() {
+            *input = base.join(&input);
+        }
+        for slice in self.slice.iter_mut() {
+            slice.path = base.join(&slice.path);
+        }
+        for strip in self.strip.iter_mut() {
+            strip.path = base.join(&strip.path);
+        }
+        for folder in self.folders.iter_mut() {
+            if folder.path.as_os_str().is_empty() {
+                return Err(invalid("every [[folders]] entry needs a path".to_owned()));
+            }
+            folder.path = base.join(&folder.path).canonicalize()?;
+            folder.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Fills in any options that weren't given on the command line
+    pub fn apply(self, opt: &mut Opt, matches: &ArgMatches) -> Result<()> {
+        let from_cli = |name: &str| matches.occurrences_of(name) > 0;
+
+        macro_rules! apply {
+            ($($field:ident => $name:expr),* $(,)?) => {
+                $(
+                    if let Some(value) = self.$field {
+                        if !from_cli($name) {
+                            opt.$field = value;
+                        }
+                    }
+                )*
+            };
+        }
+
+        apply! {
+            default => "default",
+            xml => "xml",
+            binary => "binary",
+            json => "json",
+            long_keys => "long-keys",
+            meta => "meta",
+            checksums => "checksums",
+            sources => "sources",
+            stable_indices => "stable-indices",
+            follow_symlinks => "follow-symlinks",
+            hidden => "hidden",
+            split_by_folder => "split-by-folder",
+            premultiply => "premultiply",
+            alpha_variants => "alpha-variants",
+            trim => "trim",
+            crop => "crop",
+            trim_margin => "trim-margin",
+            verbose => "verbose",
+            quiet => "quiet",
+            log_file => "log-file",
+            no_log_file => "no-log-file",
+            cache_dir => "cache-dir",
+            paranoid => "paranoid",
+            unique => "unique",
+            aliases => "aliases",
+            rotate => "rotate",
+            rotation => "rotation",
+            rotation_penalty => "rotation-penalty",
+            pad => "pad",
+            pad_fill => "pad-fill",
+            extrude => "extrude",
+            align => "align",
+            fixed_size => "fixed-size",
+            tile_oversized => "tile-oversized",
+            pot => "pot",
+            scale => "scale",
+            input_scale_suffix => "input-scale-suffix",
+            extension => "extension",
+            tonemap => "tonemap",
+            animations => "animations",
+            animations_from_folders => "animations-from-folders",
+            aseprite_layers => "aseprite-layers",
+            aseprite_tags => "aseprite-tags",
+            gif_animations => "gif-animations",
+            psd_layers => "psd-layers",
+            flatten => "flatten",
+            rename_duplicates => "rename-duplicates",
+            name_separator => "name-separator",
+            trim_numbers => "trim-numbers",
+        }
+
+        if let Some(size) = self.size {
+            if !from_cli("size") {
+                if !(crate::MIN_SIZE..=crate::MAX_SIZE).contains(&size) {
+                    return Err(invalid(format!("invalid size: {}", size)));
+                }
+                opt.size = size;
+            }
+        }
+        if let Some(heuristic) = self.heuristic {
+            if !from_cli("heuristic") {
+                opt.heuristic = heuristic
+                    .parse::<FreeRectChoiceHeuristic>()
+                    .map_err(invalid)?;
+            }
+        }
+        if let Some(retry_overflow) = self.retry_overflow {
+            if !from_cli("retry-overflow") {
+                opt.retry_overflow =
+                    Some(retry_overflow.parse::<RetryOverflow>().map_err(invalid)?);
+            }
+        }
+        if let Some(shrink) = self.shrink {
+            if !from_cli("shrink") {
+                opt.shrink = shrink.parse::<Shrink>().map_err(invalid)?;
+            }
+        }
+        if let Some(multiple_of) = self.multiple_of {
+            if !from_cli("multiple-of") {
+                opt.multiple_of = Some(multiple_of);
+            }
+        }
+        if let Some(min_sprite_size) = self.min_sprite_size {
+            if !from_cli("min-sprite-size") {
+                opt.min_sprite_size = Some(min_sprite_size);
+            }
+        }
+        if let Some(max_sprite_size) = self.max_sprite_size {
+            if !from_cli("max-sprite-size") {
+                opt.max_sprite_size = Some(max_sprite_size);
+            }
+        }
+        if let Some(skip_bad_inputs) = self.skip_bad_inputs {
+            // --fail-fast turns off skipping bad inputs from the config file
+            if !from_cli("skip-bad-inputs") && !from_cli("fail-fast") {
+                opt.skip_bad_inputs = skip_bad_inputs;
+            }
+        }
+        if let Some(unique_fuzzy) = self.unique_fuzzy {
+            if !from_cli("unique-fuzzy") {
+                opt.unique_fuzzy = Some(unique_fuzzy);
+            }
+        }
+        if let Some(quality) = self.quality {
+            if !from_cli("quality") {
+                opt.quality = Some(quality);
+            }
+        }
+        if let Some(log_format) = self.log_format {
+            if !from_cli("log-format") {
+                opt.log_format = log_format.parse::<LogFormat>().map_err(invalid)?;
+            }
+        }
+        if let Some(stdout) = self.stdout {
+            if !from_cli("stdout") {
+                opt.stdout = Some(stdout.parse::<Format>().map_err(invalid)?);
+            }
+        }
+        if !self.format.is_empty() && !from_cli("format") {
+            opt.format = self
+                .format
+                .iter()
+                .map(|format| format.parse::<Format>())
+                .collect::<std::result::Result<_, _>>()
+                .map_err(invalid)?;
+        }
+
+        if !self.include.is_empty() && !from_cli("include") {
+            opt.include = self
+                .include
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|err| invalid(format!("invalid include pattern: {}", err)))?;
+        }
+
+        if !self.keep_together.is_empty() && !from_cli("keep-together") {
+            opt.keep_together = self
+                .keep_together
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|err| invalid(format!("invalid keep-together pattern: {}", err)))?;
+        }
+
+        if !self.priority.is_empty() && !from_cli("priority") {
+            opt.priority = self
+                .priority
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|err| invalid(format!("invalid priority pattern: {}", err)))?;
+        }
+
+        if !self.slice.is_empty() && !from_cli("slice") {
+            opt.slice = self.slice;
+        }
+
+        if !self.strip.is_empty() && !from_cli("strip") {
+            opt.strip = self.strip;
+        }
+
+        if !self.reserve.is_empty() && !from_cli("reserve") {
+            opt.reserve = self.reserve;
+        }
+
+        if let Some(page_name) = self.page_name {
+            if !from_cli("page-name") {
+                opt.page_name = page_name.parse().map_err(invalid)?;
+            }
+        }
+        if let Some(animation_suffix) = self.animation_suffix {
+            if !from_cli("animation-suffix") {
+                opt.animation_suffix = animation_suffix.parse().map_err(invalid)?;
+            }
+        }
+        if self.max_depth.is_some() && !from_cli("max-depth") {
+            opt.max_depth = self.max_depth;
+        }
+        if self.basepath.is_some() && !from_cli("basepath") {
+            opt.basepath = self.basepath;
+        }
+        if self.strip_prefix.is_some() && !from_cli("strip-prefix") {
+            opt.strip_prefix = self.strip_prefix;
+        }
+
+        if opt.output.is_none() {
+            opt.output = self.output;
+        }
+        if opt.archive.is_none() {
+            opt.archive = self.archive;
+        }
+        if opt.inputs.is_empty() {
+            opt.inputs = self.inputs;
+        }
+        opt.folders.extend(self.folders);
+        // Overrides are applied in order, so more deeply nested folders go last to win. Sorting
+        // is stable, so a later entry for the same folder still wins over an earlier one.
+        opt.folders
+            .sort_by_key(|folder| folder.path.components().count());
+
+        Ok(())
+    }
+}
+
+/// The options of every `[[atlases]]` entry, each filled in from `opt` (the options shared by
+/// all of them) and then its own settings. Options given on the command line still take
+/// precedence, except for the output and inputs.
+pub fn atlas_options(atlases: Vec<Config>, opt: &Opt, matches: &ArgMatches) -> Result<Vec<Opt>> {
+    let mut options: Vec<Opt> = vec![];
+    for atlas in atlases {
+        let mut atlas_opt = Opt {
+            output: None,
+            inputs: vec![],
+            ..opt.clone()
+        };
+        atlas.apply(&mut atlas_opt, matches)?;
+
+        // Atlases are cached by the name of their output
+        let output = atlas_opt.output.clone().unwrap_or_default();
+        let clash = options.iter().find(|other| {
+            other.output.as_ref().and_then(|other| other.file_name()) == output.file_name()
+        });
+        if let Some(other) = clash.and_then(|other| other.output.as_ref()) {
+            return Err(invalid(format!(
+                "atlases {} and {} need different file names",
+                other.display(),
+                output.display()
+            )));
+        }
+        options.push(atlas_opt);
+    }
+    Ok(options)
+}
+
+fn invalid(reason: String) -> ImpactError {
+    ImpactError::InvalidConfig { reason }
+}
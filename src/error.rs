@@ -1,11 +1,46 @@
 use thiserror::Error;
 
+/// Everything that can go wrong while packing an atlas
 #[derive(Debug, Error)]
 pub enum ImpactError {
     #[error("invalid padding size: {}", size)]
     InvalidPadding {
         size: u8,
     },
+    #[error("invalid extrude size: {}", size)]
+    InvalidExtrude {
+        size: u8,
+    },
+    #[error("invalid alignment: {}", align)]
+    InvalidAlign {
+        align: u16,
+    },
+    #[error("{} atlas data can't be written to stdout", format)]
+    UnsupportedStdout {
+        format: String,
+    },
+    #[error("page size {} isn't between 64 and 16384", size)]
+    InvalidSize {
+        size: u16,
+    },
+    #[error("page size {} isn't a multiple of {}", size, multiple)]
+    NotMultipleOf {
+        size: u16,
+        multiple: u16,
+    },
+    #[error("reserved area {} doesn't fit in a {}x{} page", area, size, size)]
+    ReserveOutOfBounds {
+        area: String,
+        size: u16,
+    },
+    #[error("invalid quality: {}", quality)]
+    InvalidQuality {
+        quality: u8,
+    },
+    #[error("invalid similarity: {}%", similarity)]
+    InvalidSimilarity {
+        similarity: u8,
+    },
     #[error("I/O error: {}", err)]
     IoError {
         err: std::io::Error,
@@ -16,10 +51,115 @@ pub enum ImpactError {
     },
     #[error("can't fit image in atlas")]
     CantFitError,
+    #[error("{} sprite(s) too large for a page, use a --size of at least {}", count, min_size)]
+    TooLarge {
+        count: usize,
+        min_size: u32,
+    },
+    #[error("sprites kept together in {} don't fit in one page", group)]
+    CantKeepTogether {
+        group: String,
+    },
+    #[error("png error: {}", err)]
+    PngError {
+        err: png::EncodingError
+    },
     #[error("xml error: {}", err)]
     XmlError {
         err: xml::writer::Error
     },
+    #[error("xml error: {}", err)]
+    XmlReadError {
+        err: xml::reader::Error
+    },
+    #[error("json error: {}", err)]
+    JsonError {
+        err: serde_json::Error
+    },
+    #[cfg(feature = "cli")]
+    #[error("cbor error: {}", err)]
+    CborError {
+        err: serde_cbor::Error
+    },
+    #[error("protobuf error: {}", err)]
+    ProtoError {
+        err: prost::DecodeError
+    },
+    #[error("invalid atlas: {}", reason)]
+    InvalidAtlas {
+        reason: String
+    },
+    #[error("invalid tileset: {}", reason)]
+    InvalidTileset {
+        reason: String
+    },
+    #[error("ron error: {}", err)]
+    RonError {
+        err: ron::Error
+    },
+    #[error("plist error: {}", err)]
+    PlistError {
+        err: plist::Error
+    },
+    #[cfg(feature = "cli")]
+    #[error("toml error: {}", err)]
+    TomlError {
+        err: toml::de::Error
+    },
+    #[error("invalid config: {}", reason)]
+    InvalidConfig {
+        reason: String
+    },
+    #[error("invalid sidecar {}: {}", path, reason)]
+    InvalidSidecar {
+        path: String,
+        reason: String
+    },
+    #[error("invalid aseprite file {}: {}", path, reason)]
+    InvalidAseprite {
+        path: String,
+        reason: String
+    },
+    #[error("invalid psd file {}: {}", path, reason)]
+    InvalidPsd {
+        path: String,
+        reason: String
+    },
+    #[error("no output file given")]
+    MissingOutput,
+    #[error("sprite {} comes from both {} and {}", name, first, second)]
+    DuplicateName {
+        name: String,
+        first: String,
+        second: String
+    },
+    #[error("more than one atlas would be called {} with --split-by-folder", name)]
+    SplitNameClash {
+        name: String
+    },
+    #[error("sprite {} is already in the atlas", name)]
+    AlreadyInAtlas {
+        name: String
+    },
+    #[error("atlas is out of date: {}", name)]
+    OutOfDate {
+        name: String
+    },
+    #[cfg(feature = "cli")]
+    #[error("watch error: {}", err)]
+    WatchError {
+        err: notify::Error
+    },
+    #[cfg(feature = "cli")]
+    #[error("zip error: {}", err)]
+    ZipError {
+        err: zip::result::ZipError
+    },
+    #[error("--{} can't be used with --{}", first, second)]
+    IncompatibleOptions {
+        first: String,
+        second: String
+    },
     #[error("log error: {}", err)]
     LoggerError {
         err: log::SetLoggerError
@@ -38,12 +178,76 @@ impl From<image::ImageError> for ImpactError {
     }
 }
 
+impl From<png::EncodingError> for ImpactError {
+    fn from(err: png::EncodingError) -> ImpactError {
+        ImpactError::PngError { err }
+    }
+}
+
 impl From<xml::writer::Error> for ImpactError {
     fn from(err: xml::writer::Error) -> ImpactError {
         ImpactError::XmlError { err }
     }
 }
 
+impl From<xml::reader::Error> for ImpactError {
+    fn from(err: xml::reader::Error) -> ImpactError {
+        ImpactError::XmlReadError { err }
+    }
+}
+
+impl From<serde_json::Error> for ImpactError {
+    fn from(err: serde_json::Error) -> ImpactError {
+        ImpactError::JsonError { err }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<serde_cbor::Error> for ImpactError {
+    fn from(err: serde_cbor::Error) -> ImpactError {
+        ImpactError::CborError { err }
+    }
+}
+
+impl From<prost::DecodeError> for ImpactError {
+    fn from(err: prost::DecodeError) -> ImpactError {
+        ImpactError::ProtoError { err }
+    }
+}
+
+impl From<ron::Error> for ImpactError {
+    fn from(err: ron::Error) -> ImpactError {
+        ImpactError::RonError { err }
+    }
+}
+
+impl From<plist::Error> for ImpactError {
+    fn from(err: plist::Error) -> ImpactError {
+        ImpactError::PlistError { err }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<toml::de::Error> for ImpactError {
+    fn from(err: toml::de::Error) -> ImpactError {
+        ImpactError::TomlError { err }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<notify::Error> for ImpactError {
+    fn from(err: notify::Error) -> ImpactError {
+        ImpactError::WatchError { err }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<zip::result::ZipError> for ImpactError {
+    fn from(err: zip::result::ZipError) -> ImpactError {
+        ImpactError::ZipError { err }
+    }
+}
+
 impl From<log::SetLoggerError> for ImpactError {
     fn from(err: log::SetLoggerError) -> ImpactError {
         ImpactError::LoggerError { err }
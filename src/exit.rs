@@ -0,0 +1,92 @@
+//! Exit codes, so that build scripts can tell what a run did, and the summary of the warnings and
+//! errors logged along the way.
+
+use crate::error::ImpactError;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The atlas was packed and written, or the subcommand succeeded
+pub const SUCCESS: i32 = 0;
+/// An error without a more specific code
+pub const FAILURE: i32 = 1;
+/// The atlas was already up to date, so nothing was written
+pub const UNCHANGED: i32 = 2;
+/// Some sprites don't fit in a page
+pub const CANT_FIT: i32 = 3;
+/// A file couldn't be read or written
+pub const IO_ERROR: i32 = 4;
+/// An input image couldn't be decoded
+pub const BAD_INPUT: i32 = 5;
+
+/// What a successful run did
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Outcome {
+    Packed,
+    Unchanged,
+}
+
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+/// The inputs left out with `--skip-bad-inputs`, and why
+static SKIPPED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// The exit code for a run that failed with `err`
+pub fn for_error(err: &ImpactError) -> i32 {
+    match err {
+        ImpactError::CantFitError
+        | ImpactError::TooLarge { .. }
+        | ImpactError::CantKeepTogether { .. } => CANT_FIT,
+        ImpactError::IoError { .. } => IO_ERROR,
+        ImpactError::ImageError {
+            err: image::ImageError::IoError(_),
+        } => IO_ERROR,
+        ImpactError::ImageError {
+            err: image::ImageError::Decoding(_),
+        }
+        | ImpactError::ImageError {
+            err: image::ImageError::Unsupported(_),
+        }
+        | ImpactError::InvalidAseprite { .. }
+        | ImpactError::InvalidPsd { .. } => BAD_INPUT,
+        _ => FAILURE,
+    }
+}
+
+/// Counts a log record for the summary, if it's a warning or an error
+pub fn count(record: &log::Record) {
+    match record.level() {
+        log::Level::Error => ERRORS.fetch_add(1, Ordering::Relaxed),
+        log::Level::Warn => WARNINGS.fetch_add(1, Ordering::Relaxed),
+        _ => return,
+    };
+}
+
+/// Records that the input at `path` was left out because of `err`, for the summary
+pub fn skip_input(path: &Path, err: &ImpactError) {
+    let mut skipped = SKIPPED.lock().unwrap_or_else(|err| err.into_inner());
+    skipped.push(format!("{}: {}", path.display(), err));
+}
+
+/// Prints the inputs that were skipped and how many warnings and errors were logged, if any. A run that `failed` counts as at
+/// least one error, even if it wasn't logged.
+pub fn print_summary(failed: bool) {
+    let skipped = SKIPPED.lock().unwrap_or_else(|err| err.into_inner());
+    if !skipped.is_empty() {
+        eprintln!("skipped {} input(s) that couldn't be read:", skipped.len());
+        for input in skipped.iter() {
+            eprintln!("  {}", input);
+        }
+    }
+    let warnings = WARNINGS.load(Ordering::Relaxed);
+    let mut errors = ERRORS.load(Ordering::Relaxed);
+    if failed {
+        errors = std::cmp::max(errors, 1);
+    }
+    if warnings > 0 || errors > 0 {
+        eprintln!(
+            "finished with {} warning(s) and {} error(s)",
+            warnings, errors
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! The size and modification time of every input file along with its hash, kept in the cache
+//! directory so that files that haven't changed since the last run don't have to be read again
+//! just to find that out.
+
+use crate::error::Result;
+use crate::sidecar;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The file inside the cache directory that holds the index
+const FILE: &str = "files.cbor";
+
+/// The size of a file and when it was last modified
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+struct Stamp {
+    size: u64,
+    secs: u64,
+    nanos: u32,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: metadata.len(),
+            secs: modified.as_secs(),
+            nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+/// The stamps of an input file and of its sidecar file, if it has one
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Stamps {
+    file: Stamp,
+    sidecar: Option<Stamp>,
+}
+
+impl Stamps {
+    /// The current stamps of the input file at `path`, if the file system has them
+    pub fn of(path: &Path) -> Option<Self> {
+        let sidecar = match sidecar::path(path) {
+            Some(sidecar) => Some(Stamp::of(&sidecar)?),
+            None => None,
+        };
+        Some(Self {
+            file: Stamp::of(path)?,
+            sidecar,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    stamps: Stamps,
+    hash: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Contents {
+    version: String,
+    files: HashMap<PathBuf, Entry>,
+}
+
+/// The files hashed by the last run, and the ones checked or hashed again by this one
+#[derive(Default)]
+pub struct FileIndex {
+    previous: HashMap<PathBuf, Entry>,
+    current: HashMap<PathBuf, Entry>,
+}
+
+impl FileIndex {
+    /// The index kept in `cache_dir`, or an empty one if there's no readable index there
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(FILE);
+        let contents = match std::fs::read(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+        match serde_cbor::from_slice::<Contents>(&contents) {
+            Ok(contents) if contents.version == env!("CARGO_PKG_VERSION") => Self {
+                previous: contents.files,
+                current: HashMap::new(),
+            },
+            Ok(_) => Self::default(),
+            Err(err) => {
+                log::warn!("ignoring invalid file index {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    /// The hash of the file at `path` from the last run, as long as its stamps are still `stamps`
+    pub fn lookup(&mut self, path: &Path, stamps: Stamps) -> Option<u64> {
+        let entry = self
+            .previous
+            .remove(path)
+            .filter(|entry| entry.stamps == stamps)?;
+        let hash = entry.hash;
+        self.current.insert(path.to_path_buf(), entry);
+        Some(hash)
+    }
+
+    /// Records the hash of the file at `path`, read when its stamps were `stamps`
+    pub fn insert(&mut self, path: &Path, stamps: Stamps, hash: u64) {
+        self.current
+            .insert(path.to_path_buf(), Entry { stamps, hash });
+    }
+
+    /// Saves the files checked or hashed by this run into `cache_dir`, dropping any others
+    pub fn save(&self, cache_dir: &Path) -> Result<()> {
+        let contents = Contents {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            files: self.current.clone(),
+        };
+        std::fs::create_dir_all(cache_dir)?;
+        std::fs::write(cache_dir.join(FILE), serde_cbor::to_vec(&contents)?)?;
+        Ok(())
+    }
+}
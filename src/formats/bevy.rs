@@ -0,0 +1,94 @@
+//! RON output for loading the atlas into Bevy.
+//!
+//! Each page carries the path of its texture and a layout matching Bevy's
+//! `TextureAtlasLayout`, along with a map from sprite names to their index in
+//! the layout, so that a custom asset loader can build the atlas handles directly.
+
+use crate::error::Result;
+use crate::serial::{Atlas, Texture};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct BevyAtlas {
+    pages: Vec<BevyPage>,
+}
+
+#[derive(Serialize)]
+struct BevyPage {
+    texture: String,
+    layout: TextureAtlasLayout,
+    names: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize)]
+struct TextureAtlasLayout {
+    size: (u32, u32),
+    textures: Vec<URect>,
+}
+
+#[derive(Serialize)]
+struct URect {
+    min: (u32, u32),
+    max: (u32, u32),
+}
+
+impl Atlas {
+    pub fn write_to_bevy_ron_file<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        extension: &str,
+    ) -> Result<()> {
+        std::fs::write(path, self.to_bevy_ron(extension)?)?;
+        Ok(())
+    }
+
+    pub fn to_bevy_ron(&self, extension: &str) -> Result<String> {
+        let atlas = BevyAtlas {
+            pages: self
+                .textures
+                .iter()
+                .map(|texture| build_page(texture, extension))
+                .collect(),
+        };
+
+        Ok(ron::ser::to_string_pretty(
+            &atlas,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+}
+
+fn build_page(texture: &Texture, extension: &str) -> BevyPage {
+    let mut textures = vec![];
+    let mut names = BTreeMap::new();
+
+    for image in texture.images.iter() {
+        // Bevy has no notion of rotated sprites, so the rect covers the rotated region
+        if image.rotated {
+            log::warn!("rotated image in bevy atlas: {}", &image.name);
+        }
+        let (width, height) = if image.rotated {
+            (image.height, image.width)
+        } else {
+            (image.width, image.height)
+        };
+
+        for name in image.names() {
+            names.insert(name.to_owned(), textures.len());
+        }
+        textures.push(URect {
+            min: (image.x as u32, image.y as u32),
+            max: ((image.x + width) as u32, (image.y + height) as u32),
+        });
+    }
+
+    BevyPage {
+        texture: format!("{}.{}", texture.name, extension),
+        layout: TextureAtlasLayout {
+            size: (texture.width as u32, texture.height as u32),
+            textures,
+        },
+        names,
+    }
+}
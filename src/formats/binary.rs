@@ -0,0 +1,437 @@
+//! The `.bin` atlas format.
+//!
+//! All integers are little-endian. A file is laid out as:
+//!
+//! | Field         | Type                | Notes                                  |
+//! |---------------|---------------------|----------------------------------------|
+//! | magic         | `[u8; 4]`           | always `IMPB`                          |
+//! | version       | `u32`               | currently `10`                         |
+//! | string count  | `u32`               |                                        |
+//! | strings       | `String[count]`     | the string table                       |
+//! | texture count | `u32`               |                                        |
+//! | textures      | `Texture[count]`    |                                        |
+//! | anim. count   | `u32`               | since version 2                        |
+//! | animations    | `Animation[count]`  | since version 2                        |
+//! | rotation      | `u8`                | `1` if rotated images were turned      |
+//! |               |                     | counter-clockwise, since version 5     |
+//! | tiled count   | `u32`               | since version 9                        |
+//! | tiled         | `TiledImage[count]` | images split into tiles by             |
+//! |               |                     | `--tile-oversized`, since version 9    |
+//! | source count  | `u32`               | since version 10                       |
+//! | sources       | `Source[count]`     | the file every sprite was read from    |
+//! |               |                     | with `--sources`, since version 10     |
+//!
+//! A `String` is a `u32` byte length followed by that many bytes of UTF-8. Names
+//! elsewhere in the file are stored as `u32` indices into the string table.
+//!
+//! A `Texture` is:
+//!
+//! | Field       | Type           | Notes                                          |
+//! |-------------|----------------|------------------------------------------------|
+//! | name        | `u32`          |                                                |
+//! | width       | `i32`          |                                                |
+//! | height      | `i32`          |                                                |
+//! | checksum    | `u32`          | the BLAKE3 hash of the page image in hex, as a |
+//! |             |                | string index plus one, or `0` if there's none, |
+//! |             |                | since version 6                                |
+//! | image count | `u32`          |                                                |
+//! | images      | `Image[count]` |                                                |
+//!
+//! An `Image` is:
+//!
+//! | Field        | Type         | Notes                                       |
+//! |--------------|--------------|---------------------------------------------|
+//! | name         | `u32`        |                                             |
+//! | x            | `i32`        |                                             |
+//! | y            | `i32`        |                                             |
+//! | width        | `i32`        |                                             |
+//! | height       | `i32`        |                                             |
+//! | frame x      | `i32`        |                                             |
+//! | frame y      | `i32`        |                                             |
+//! | frame width  | `i32`        |                                             |
+//! | frame height | `i32`        |                                             |
+//! | rotated      | `u8`         | `1` if the image is rotated, `0` otherwise  |
+//! | pivot x      | `f32`        | since version 3                             |
+//! | pivot y      | `f32`        | since version 3                             |
+//! | alias count  | `u32`        | since version 4                             |
+//! | aliases      | `u32[count]` | names of identical sprites, since version 4 |
+//! | index        | `u32`        | the stable index plus one, or `0` without   |
+//! |              |              | `--stable-indices`, since version 7         |
+//! | data         | `u32`        | the custom data from the sprite's sidecar   |
+//! |              |              | file as a JSON object, as a string index    |
+//! |              |              | plus one, or `0` if there's none, since     |
+//! |              |              | version 8                                   |
+//!
+//! An `Animation` is:
+//!
+//! | Field       | Type         | Notes                  |
+//! |-------------|--------------|------------------------|
+//! | name        | `u32`        |                        |
+//! | frame count | `u32`        |                        |
+//! | frames      | `u32[count]` | sprite names, in order |
+//!
+//! A `TiledImage` is:
+//!
+//! | Field      | Type          | Notes                           |
+//! |------------|---------------|---------------------------------|
+//! | name       | `u32`         |                                 |
+//! | width      | `i32`         | the size of the whole image     |
+//! | height     | `i32`         |                                 |
+//! | tile count | `u32`         |                                 |
+//! | tiles      | `Tile[count]` |                                 |
+//!
+//! A `Tile` is:
+//!
+//! | Field | Type  | Notes                                      |
+//! |-------|-------|--------------------------------------------|
+//! | name  | `u32` | the sprite name of the tile                |
+//! | x     | `i32` | where the tile goes in the whole image     |
+//! | y     | `i32` |                                            |
+//!
+//! A `Source` is:
+//!
+//! | Field    | Type  | Notes                                             |
+//! |----------|-------|---------------------------------------------------|
+//! | name     | `u32` | the sprite name                                   |
+//! | path     | `u32` | the path of the file, as found from the inputs    |
+//! | modified | `u64` | when the file was last modified, in seconds since |
+//! |          |       | the Unix epoch                                    |
+//! | hash     | `u32` | the BLAKE3 hash of the file in hex                |
+//!
+//! Any change to this layout must bump `VERSION`.
+
+use crate::error::{ImpactError, Result};
+use crate::packer::Rotation;
+use crate::serial::{Animation, Atlas, Image, Source, Texture, Tile, TiledImage};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+pub const MAGIC: &[u8; 4] = b"IMPB";
+pub const VERSION: u32 = 10;
+
+/// Collects every string in the atlas so that each is written only once
+#[derive(Default)]
+struct StringTable<'a> {
+    strings: Vec<Cow<'a, str>>,
+    lookup: HashMap<Cow<'a, str>, u32>,
+}
+
+impl<'a> StringTable<'a> {
+    fn index<S: Into<Cow<'a, str>>>(&mut self, s: S) -> u32 {
+        let s = s.into();
+        if let Some(&idx) = self.lookup.get(&*s) {
+            return idx;
+        }
+        let idx = self.strings.len() as u32;
+        self.strings.push(s.clone());
+        self.lookup.insert(s, idx);
+        idx
+    }
+}
+
+impl Atlas {
+    pub fn write_to_binary_file<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_binary())?;
+        Ok(())
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut strings = StringTable::default();
+        let mut body = vec![];
+
+        write_u32(&mut body, self.textures.len() as u32);
+        for texture in self.textures.iter() {
+            write_u32(&mut body, strings.index(&texture.name));
+            write_i32(&mut body, texture.width);
+            write_i32(&mut body, texture.height);
+            let checksum = texture
+                .checksum
+                .as_ref()
+                .map_or(0, |checksum| strings.index(checksum) + 1);
+            write_u32(&mut body, checksum);
+            write_u32(&mut body, texture.images.len() as u32);
+
+            for image in texture.images.iter() {
+                write_u32(&mut body, strings.index(&image.name));
+                write_i32(&mut body, image.x);
+                write_i32(&mut body, image.y);
+                write_i32(&mut body, image.width);
+                write_i32(&mut body, image.height);
+                write_i32(&mut body, image.frame_x);
+                write_i32(&mut body, image.frame_y);
+                write_i32(&mut body, image.frame_width);
+                write_i32(&mut body, image.frame_height);
+                body.push(image.rotated as u8);
+                write_f32(&mut body, image.pivot_x);
+                write_f32(&mut body, image.pivot_y);
+                write_u32(&mut body, image.aliases.len() as u32);
+                for alias in image.aliases.iter() {
+                    write_u32(&mut body, strings.index(alias));
+                }
+                write_u32(&mut body, image.index.map_or(0, |index| index + 1));
+                let data = if image.data.is_empty() {
+                    0
+                } else {
+                    let json = serde_json::to_string(&image.data).expect("JSON values serialize");
+                    strings.index(json) + 1
+                };
+                write_u32(&mut body, data);
+            }
+        }
+
+        write_u32(&mut body, self.animations.len() as u32);
+        for animation in self.animations.iter() {
+            write_u32(&mut body, strings.index(&animation.name));
+            write_u32(&mut body, animation.frames.len() as u32);
+            for frame in animation.frames.iter() {
+                write_u32(&mut body, strings.index(frame));
+            }
+        }
+        body.push((self.rotation == Rotation::Ccw) as u8);
+
+        write_u32(&mut body, self.tiled.len() as u32);
+        for tiled in self.tiled.iter() {
+            write_u32(&mut body, strings.index(&tiled.name));
+            write_i32(&mut body, tiled.width);
+            write_i32(&mut body, tiled.height);
+            write_u32(&mut body, tiled.tiles.len() as u32);
+            for tile in tiled.tiles.iter() {
+                write_u32(&mut body, strings.index(&tile.name));
+                write_i32(&mut body, tile.x);
+                write_i32(&mut body, tile.y);
+            }
+        }
+
+        write_u32(&mut body, self.sources.len() as u32);
+        for source in self.sources.iter() {
+            write_u32(&mut body, strings.index(&source.name));
+            write_u32(&mut body, strings.index(&source.path));
+            write_u64(&mut body, source.modified);
+            write_u32(&mut body, strings.index(&source.hash));
+        }
+
+        let mut res = vec![];
+        res.extend_from_slice(MAGIC);
+        write_u32(&mut res, VERSION);
+        write_u32(&mut res, strings.strings.len() as u32);
+        for s in strings.strings.iter() {
+            write_u32(&mut res, s.len() as u32);
+            res.extend_from_slice(s.as_bytes());
+        }
+        res.extend_from_slice(&body);
+        res
+    }
+}
+
+impl Atlas {
+    pub fn from_binary(bytes: &[u8]) -> Result<Atlas> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not an impact binary atlas"));
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+
+        let mut strings = vec![];
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            let s = std::str::from_utf8(reader.take(len)?)
+                .map_err(|_| invalid("string is not valid UTF-8"))?;
+            strings.push(s.to_owned());
+        }
+        let string = |idx: u32| {
+            strings
+                .get(idx as usize)
+                .cloned()
+                .ok_or_else(|| invalid("string index out of range"))
+        };
+
+        let mut textures = vec![];
+        for _ in 0..reader.u32()? {
+            let name = string(reader.u32()?)?;
+            let width = reader.i32()?;
+            let height = reader.i32()?;
+            let mut checksum = None;
+            if version >= 6 {
+                checksum = match reader.u32()? {
+                    0 => None,
+                    idx => Some(string(idx - 1)?),
+                };
+            }
+
+            let mut images = vec![];
+            for _ in 0..reader.u32()? {
+                let mut image = Image {
+                    name: string(reader.u32()?)?,
+                    x: reader.i32()?,
+                    y: reader.i32()?,
+                    width: reader.i32()?,
+                    height: reader.i32()?,
+                    frame_x: reader.i32()?,
+                    frame_y: reader.i32()?,
+                    frame_width: reader.i32()?,
+                    frame_height: reader.i32()?,
+                    rotated: reader.take(1)?[0] != 0,
+                    pivot_x: 0.0,
+                    pivot_y: 0.0,
+                    data: Default::default(),
+                    aliases: vec![],
+                    index: None,
+                };
+                if version >= 3 {
+                    image.pivot_x = reader.f32()?;
+                    image.pivot_y = reader.f32()?;
+                }
+                if version >= 4 {
+                    for _ in 0..reader.u32()? {
+                        image.aliases.push(string(reader.u32()?)?);
+                    }
+                }
+                if version >= 7 {
+                    image.index = reader.u32()?.checked_sub(1);
+                }
+                if version >= 8 {
+                    if let Some(idx) = reader.u32()?.checked_sub(1) {
+                        image.data = serde_json::from_str(&string(idx)?)
+                            .map_err(|_| invalid("image data is not a JSON object"))?;
+                    }
+                }
+                images.push(image);
+            }
+
+            textures.push(Texture {
+                name,
+                width,
+                height,
+                images,
+                checksum,
+            });
+        }
+
+        let mut animations = vec![];
+        if version >= 2 {
+            for _ in 0..reader.u32()? {
+                let name = string(reader.u32()?)?;
+                let mut frames = vec![];
+                for _ in 0..reader.u32()? {
+                    frames.push(string(reader.u32()?)?);
+                }
+                animations.push(Animation { name, frames });
+            }
+        }
+
+        let mut rotation = Rotation::Cw;
+        if version >= 5 && reader.take(1)?[0] != 0 {
+            rotation = Rotation::Ccw;
+        }
+
+        let mut tiled = vec![];
+        if version >= 9 {
+            for _ in 0..reader.u32()? {
+                let name = string(reader.u32()?)?;
+                let width = reader.i32()?;
+                let height = reader.i32()?;
+                let mut tiles = vec![];
+                for _ in 0..reader.u32()? {
+                    tiles.push(Tile {
+                        name: string(reader.u32()?)?,
+                        x: reader.i32()?,
+                        y: reader.i32()?,
+                    });
+                }
+                tiled.push(TiledImage {
+                    name,
+                    width,
+                    height,
+                    tiles,
+                });
+            }
+        }
+
+        let mut sources = vec![];
+        if version >= 10 {
+            for _ in 0..reader.u32()? {
+                sources.push(Source {
+                    name: string(reader.u32()?)?,
+                    path: string(reader.u32()?)?,
+                    modified: reader.u64()?,
+                    hash: string(reader.u32()?)?,
+                });
+            }
+        }
+
+        Ok(Atlas {
+            textures,
+            animations,
+            rotation,
+            tiled,
+            sources,
+            ..Atlas::default()
+        })
+    }
+}
+
+pub(super) struct Reader<'a> {
+    pub(super) bytes: &'a [u8],
+    pub(super) pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err(invalid("unexpected end of file"));
+        }
+        let res = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    pub(super) fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub(super) fn u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(f32::from_le_bytes(buf))
+    }
+}
+
+pub(super) fn invalid(reason: &str) -> ImpactError {
+    ImpactError::InvalidAtlas {
+        reason: reason.to_owned(),
+    }
+}
+
+pub(super) fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(super) fn write_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_i32(buf: &mut Vec<u8>, value: i32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_f32(buf: &mut Vec<u8>, value: f32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,238 @@
+//! The `.impact` bundle, which holds the `.bin` atlas data and the encoded page images in a
+//! single file, so that they can't get out of step.
+//!
+//! All integers are little-endian. A file is laid out as:
+//!
+//! | Field       | Type           | Notes                                    |
+//! |-------------|----------------|------------------------------------------|
+//! | magic       | `[u8; 4]`      | always `IMPK`                            |
+//! | version     | `u32`          | currently `2`                            |
+//! | entry count | `u32`          |                                          |
+//! | entries     | `Entry[count]` | the index                                |
+//! | data        | `u8[]`         | the contents of every entry, in order    |
+//!
+//! An `Entry` is:
+//!
+//! | Field  | Type     | Notes                                           |
+//! |--------|----------|-------------------------------------------------|
+//! | name   | `String` | a `u32` byte length followed by UTF-8           |
+//! | kind   | `u8`     | what the entry holds, see below (since v2)      |
+//! | offset | `u64`    | where the contents start, from the file's start |
+//! | length | `u64`    | the size of the contents in bytes               |
+//!
+//! The first entry is always `atlas.bin` (kind `0`), the atlas data in the `.bin` format. It's
+//! followed by one entry per page (kind `1`), named after its texture with the extension of its
+//! image format (e.g. `atlas0.png`). Atlases packed with `--alpha-variants` also have the
+//! premultiplied copy of every page after it (kind `2`, e.g. `atlas0.pm.png`).
+//!
+//! Version 1 bundles have no kind in their index. Reading them, entries named `*.pm.<ext>` are
+//! taken to be premultiplied pages.
+//!
+//! Any change to this layout must bump `VERSION`.
+
+use super::binary::{invalid, write_u32, write_u64, Reader};
+use crate::error::Result;
+use crate::serial::{Atlas, Texture};
+
+pub const MAGIC: &[u8; 4] = b"IMPK";
+pub const VERSION: u32 = 2;
+
+/// The name of the entry holding the atlas data
+pub const ATLAS_ENTRY: &str = "atlas.bin";
+
+/// What an entry of a bundle holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// The atlas data, in the `.bin` format
+    Atlas = 0,
+    /// The page of a texture, as it was packed
+    Page = 1,
+    /// The premultiplied copy of a page, with `--alpha-variants`
+    PremultipliedPage = 2,
+}
+
+impl EntryKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(EntryKind::Atlas),
+            1 => Some(EntryKind::Page),
+            2 => Some(EntryKind::PremultipliedPage),
+            _ => None,
+        }
+    }
+
+    /// The kind of the entry at `index`, for version 1 bundles which don't store it
+    fn guess(index: usize, name: &str) -> Self {
+        let is_premultiplied = std::path::Path::new(name)
+            .file_stem()
+            .map_or(false, |stem| stem.to_string_lossy().ends_with(".pm"));
+        if index == 0 {
+            EntryKind::Atlas
+        } else if is_premultiplied {
+            EntryKind::PremultipliedPage
+        } else {
+            EntryKind::Page
+        }
+    }
+}
+
+impl Atlas {
+    /// Bundles the atlas data with `pages`, the file name, kind and encoded image of every page
+    pub fn to_bundle(&self, pages: &[(String, EntryKind, Vec<u8>)]) -> Vec<u8> {
+        let atlas = self.to_binary();
+        let mut entries: Vec<(&str, EntryKind, &[u8])> =
+            vec![(ATLAS_ENTRY, EntryKind::Atlas, &atlas[..])];
+        entries.extend(
+            pages
+                .iter()
+                .map(|(name, kind, data)| (name.as_str(), *kind, &data[..])),
+        );
+
+        let index_len: usize = entries
+            .iter()
+            .map(|(name, _, _)| 4 + name.len() + 1 + 16)
+            .sum();
+        let mut offset = (MAGIC.len() + 4 + 4 + index_len) as u64;
+
+        let mut res = vec![];
+        res.extend_from_slice(MAGIC);
+        write_u32(&mut res, VERSION);
+        write_u32(&mut res, entries.len() as u32);
+        for (name, kind, data) in entries.iter() {
+            write_u32(&mut res, name.len() as u32);
+            res.extend_from_slice(name.as_bytes());
+            res.push(*kind as u8);
+            write_u64(&mut res, offset);
+            write_u64(&mut res, data.len() as u64);
+            offset += data.len() as u64;
+        }
+        for (_, _, data) in entries.iter() {
+            res.extend_from_slice(data);
+        }
+        res
+    }
+}
+
+/// Reads the entries of a bundle without copying them
+pub struct Bundle<'a> {
+    bytes: &'a [u8],
+    entries: Vec<Entry>,
+}
+
+/// Where the contents of an entry are in the bundle
+struct Entry {
+    name: String,
+    kind: EntryKind,
+    offset: usize,
+    len: usize,
+}
+
+impl<'a> Bundle<'a> {
+    /// Reads the index of the bundle in `bytes`
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not an impact bundle"));
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > VERSION {
+            return Err(invalid(&format!("unsupported bundle version {}", version)));
+        }
+
+        let mut entries = vec![];
+        for index in 0..reader.u32()? as usize {
+            let len = reader.u32()? as usize;
+            let name = std::str::from_utf8(reader.take(len)?)
+                .map_err(|_| invalid("entry name is not valid UTF-8"))?
+                .to_owned();
+            let kind = if version >= 2 {
+                let kind = reader.take(1)?[0];
+                EntryKind::from_u8(kind)
+                    .ok_or_else(|| invalid(&format!("entry {} has unknown kind {}", name, kind)))?
+            } else {
+                EntryKind::guess(index, &name)
+            };
+            let offset = reader.u64()? as usize;
+            let len = reader.u64()? as usize;
+            if offset
+                .checked_add(len)
+                .map_or(true, |end| end > bytes.len())
+            {
+                return Err(invalid(&format!("entry {} is out of bounds", name)));
+            }
+            entries.push(Entry {
+                name,
+                kind,
+                offset,
+                len,
+            });
+        }
+        Ok(Self { bytes, entries })
+    }
+
+    /// The names of every entry, in order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.name.as_str())
+    }
+
+    /// The kind of the entry called `name`
+    pub fn kind(&self, name: &str) -> Option<EntryKind> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.kind)
+    }
+
+    /// The contents of the entry called `name`
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        let bytes = self.bytes;
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| &bytes[entry.offset..entry.offset + entry.len])
+    }
+
+    /// Decodes the atlas data
+    pub fn atlas(&self) -> Result<Atlas> {
+        let bytes = self
+            .get(ATLAS_ENTRY)
+            .ok_or_else(|| invalid("bundle has no atlas data"))?;
+        Atlas::from_binary(bytes)
+    }
+
+    /// The file name and encoded image of the page of `texture`
+    pub fn page(&self, texture: &Texture) -> Option<(&str, &'a [u8])> {
+        self.find_page(texture, EntryKind::Page)
+    }
+
+    /// The file name and encoded image of the premultiplied copy of the page of `texture`, if
+    /// the atlas was packed with `--alpha-variants`
+    pub fn premultiplied_page(&self, texture: &Texture) -> Option<(&str, &'a [u8])> {
+        self.find_page(texture, EntryKind::PremultipliedPage)
+    }
+
+    fn find_page(&self, texture: &Texture, kind: EntryKind) -> Option<(&str, &'a [u8])> {
+        let bytes = self.bytes;
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .find(|entry| {
+                let stem = std::path::Path::new(&entry.name)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy());
+                let stem = match kind {
+                    EntryKind::PremultipliedPage => {
+                        stem.as_deref().and_then(|stem| stem.strip_suffix(".pm"))
+                    }
+                    _ => stem.as_deref(),
+                };
+                stem == Some(texture.name.as_str())
+            })
+            .map(|entry| {
+                (
+                    entry.name.as_str(),
+                    &bytes[entry.offset..entry.offset + entry.len],
+                )
+            })
+    }
+}
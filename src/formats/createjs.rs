@@ -0,0 +1,86 @@
+//! EaselJS/CreateJS `SpriteSheet` JSON output.
+//!
+//! Every sprite becomes a frame of the form `[x, y, width, height, imageIndex, regX, regY]`,
+//! and is exposed as a single-frame animation under its own name. Animations in the atlas
+//! become multi-frame animations.
+
+use crate::error::Result;
+use crate::serial::Atlas;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct SpriteSheet {
+    images: Vec<String>,
+    frames: Vec<[i32; 7]>,
+    animations: BTreeMap<String, Animation>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Animation {
+    Frame(usize),
+    Frames { frames: Vec<usize> },
+}
+
+impl Atlas {
+    pub fn write_to_createjs_file<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        extension: &str,
+    ) -> Result<()> {
+        std::fs::write(path, self.to_createjs(extension))?;
+        Ok(())
+    }
+
+    pub fn to_createjs(&self, extension: &str) -> Vec<u8> {
+        let mut sheet = SpriteSheet {
+            images: vec![],
+            frames: vec![],
+            animations: BTreeMap::new(),
+        };
+
+        for (idx, texture) in self.textures.iter().enumerate() {
+            sheet.images.push(format!("{}.{}", texture.name, extension));
+
+            for image in texture.images.iter() {
+                // CreateJS can't draw rotated frames
+                if image.rotated {
+                    log::warn!("rotated image in createjs sprite sheet: {}", &image.name);
+                }
+
+                for name in image.names() {
+                    sheet
+                        .animations
+                        .insert(name.to_owned(), Animation::Frame(sheet.frames.len()));
+                }
+                // The registration point is the pivot, relative to the frame
+                sheet.frames.push([
+                    image.x,
+                    image.y,
+                    image.width,
+                    image.height,
+                    idx as i32,
+                    image.frame_x + image.pivot_x.round() as i32,
+                    image.frame_y + image.pivot_y.round() as i32,
+                ]);
+            }
+        }
+
+        for animation in self.animations.iter() {
+            let frames = animation
+                .frames
+                .iter()
+                .filter_map(|frame| match sheet.animations.get(frame) {
+                    Some(Animation::Frame(idx)) => Some(*idx),
+                    _ => None,
+                })
+                .collect();
+            sheet
+                .animations
+                .insert(animation.name.clone(), Animation::Frames { frames });
+        }
+
+        serde_json::to_vec_pretty(&sheet).expect("failed to serialize into json")
+    }
+}
@@ -0,0 +1,200 @@
+//! FlatBuffers serialization of the atlas data, following `schema/atlas.fbs`.
+//!
+//! The tables are built directly with `FlatBufferBuilder` so that building impact
+//! doesn't require `flatc`. The field slots are generated from the schema by `build.rs`, so
+//! a field that's renamed or removed there no longer builds here.
+
+use crate::error::Result;
+use crate::packer::Rotation;
+use crate::serial::{Animation, Atlas, Image, Texture};
+use flatbuffers::{FlatBufferBuilder, WIPOffset};
+
+const FILE_IDENTIFIER: &str = "IMPA";
+
+// The vtable offsets of the fields of every table, generated by `build.rs` from the schema
+include!(concat!(env!("OUT_DIR"), "/fbs_slots.rs"));
+
+impl Atlas {
+    pub fn write_to_flatbuffers_file<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_flatbuffers())?;
+        Ok(())
+    }
+
+    pub fn to_flatbuffers(&self) -> Vec<u8> {
+        let mut builder = FlatBufferBuilder::new();
+
+        let textures: Vec<_> = self
+            .textures
+            .iter()
+            .map(|texture| build_texture(&mut builder, texture))
+            .collect();
+        let textures = builder.create_vector(&textures);
+        let animations: Vec<_> = self
+            .animations
+            .iter()
+            .map(|animation| build_animation(&mut builder, animation))
+            .collect();
+        let animations = builder.create_vector(&animations);
+
+        let start = builder.start_table();
+        builder.push_slot_always(atlas_slot::TEXTURES, textures);
+        builder.push_slot_always(atlas_slot::ANIMATIONS, animations);
+        builder.push_slot(
+            atlas_slot::COUNTER_CLOCKWISE,
+            self.rotation == Rotation::Ccw,
+            false,
+        );
+        let root = builder.end_table(start);
+
+        builder.finish(root, Some(FILE_IDENTIFIER));
+        builder.finished_data().to_vec()
+    }
+}
+
+fn build_texture<'a>(
+    builder: &mut FlatBufferBuilder<'a>,
+    texture: &Texture,
+) -> WIPOffset<flatbuffers::TableFinishedWIPOffset> {
+    let images: Vec<_> = texture
+        .images
+        .iter()
+        .map(|image| build_image(builder, image))
+        .collect();
+    let images = builder.create_vector(&images);
+    let name = builder.create_string(&texture.name);
+    let checksum = texture
+        .checksum
+        .as_ref()
+        .map(|checksum| builder.create_string(checksum));
+
+    let start = builder.start_table();
+    builder.push_slot_always(texture_slot::NAME, name);
+    builder.push_slot_always(texture_slot::IMAGES, images);
+    builder.push_slot(texture_slot::WIDTH, texture.width, 0);
+    builder.push_slot(texture_slot::HEIGHT, texture.height, 0);
+    if let Some(checksum) = checksum {
+        builder.push_slot_always(texture_slot::CHECKSUM, checksum);
+    }
+    builder.end_table(start)
+}
+
+fn build_image<'a>(
+    builder: &mut FlatBufferBuilder<'a>,
+    image: &Image,
+) -> WIPOffset<flatbuffers::TableFinishedWIPOffset> {
+    let aliases: Vec<_> = image
+        .aliases
+        .iter()
+        .map(|alias| builder.create_string(alias))
+        .collect();
+    let aliases = builder.create_vector(&aliases);
+    let name = builder.create_string(&image.name);
+
+    let start = builder.start_table();
+    builder.push_slot_always(image_slot::NAME, name);
+    builder.push_slot(image_slot::X, image.x, 0);
+    builder.push_slot(image_slot::Y, image.y, 0);
+    builder.push_slot(image_slot::WIDTH, image.width, 0);
+    builder.push_slot(image_slot::HEIGHT, image.height, 0);
+    builder.push_slot(image_slot::FRAME_X, image.frame_x, 0);
+    builder.push_slot(image_slot::FRAME_Y, image.frame_y, 0);
+    builder.push_slot(image_slot::FRAME_WIDTH, image.frame_width, 0);
+    builder.push_slot(image_slot::FRAME_HEIGHT, image.frame_height, 0);
+    builder.push_slot(image_slot::ROTATED, image.rotated, false);
+    builder.push_slot(image_slot::PIVOT_X, image.pivot_x, 0.0);
+    builder.push_slot(image_slot::PIVOT_Y, image.pivot_y, 0.0);
+    builder.push_slot_always(image_slot::ALIASES, aliases);
+    if let Some(index) = image.index {
+        builder.push_slot_always(image_slot::INDEX, index);
+    }
+    builder.end_table(start)
+}
+
+fn build_animation<'a>(
+    builder: &mut FlatBufferBuilder<'a>,
+    animation: &Animation,
+) -> WIPOffset<flatbuffers::TableFinishedWIPOffset> {
+    let frames: Vec<_> = animation
+        .frames
+        .iter()
+        .map(|frame| builder.create_string(frame))
+        .collect();
+    let frames = builder.create_vector(&frames);
+    let name = builder.create_string(&animation.name);
+
+    let start = builder.start_table();
+    builder.push_slot_always(animation_slot::NAME, name);
+    builder.push_slot_always(animation_slot::FRAMES, frames);
+    builder.end_table(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flatbuffers::{ForwardsUOffset, Table, Vector};
+
+    type Tables<'a> = ForwardsUOffset<Vector<'a, ForwardsUOffset<Table<'a>>>>;
+
+    fn image(name: &str, index: Option<u32>) -> Image {
+        Image {
+            name: name.to_owned(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            frame_x: 0,
+            frame_y: 0,
+            frame_width: 1,
+            frame_height: 1,
+            rotated: false,
+            pivot_x: 0.0,
+            pivot_y: 0.0,
+            data: Default::default(),
+            aliases: vec![],
+            index,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let atlas = Atlas {
+            textures: vec![Texture {
+                name: "atlas0".to_owned(),
+                width: 2,
+                height: 1,
+                images: vec![image("a", Some(3)), image("b", None)],
+                checksum: Some("af1349b9".to_owned()),
+            }],
+            ..Atlas::default()
+        };
+        let bytes = atlas.to_flatbuffers();
+        assert!(flatbuffers::buffer_has_identifier(
+            &bytes,
+            FILE_IDENTIFIER,
+            false
+        ));
+
+        // Safe, since the buffer was just built with these tables
+        unsafe {
+            let root = flatbuffers::root_unchecked::<Table>(&bytes);
+            let textures = root.get::<Tables>(atlas_slot::TEXTURES, None).unwrap();
+            let texture = textures.get(0);
+            assert_eq!(
+                texture.get::<ForwardsUOffset<&str>>(texture_slot::NAME, None),
+                Some("atlas0")
+            );
+            assert_eq!(
+                texture.get::<ForwardsUOffset<&str>>(texture_slot::CHECKSUM, None),
+                Some("af1349b9")
+            );
+            let images = texture.get::<Tables>(texture_slot::IMAGES, None).unwrap();
+            let names: Vec<_> = images
+                .iter()
+                .map(|image| image.get::<ForwardsUOffset<&str>>(image_slot::NAME, None))
+                .collect();
+            assert_eq!(names, vec![Some("a"), Some("b")]);
+            assert_eq!(images.get(0).get::<u32>(image_slot::INDEX, None), Some(3));
+            assert_eq!(images.get(1).get::<u32>(image_slot::INDEX, None), None);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod bevy;
+pub mod binary;
+pub mod bundle;
+pub mod createjs;
+pub mod fbs;
+pub mod proto;
+pub mod spritekit;
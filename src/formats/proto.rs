@@ -0,0 +1,211 @@
+//! Protocol Buffers serialization of the atlas data, following `schema/atlas.proto`.
+
+use crate::error::Result;
+use crate::packer::Rotation;
+use crate::serial;
+use prost::Message;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Atlas {
+    #[prost(message, repeated, tag = "1")]
+    pub textures: Vec<Texture>,
+    #[prost(message, repeated, tag = "2")]
+    pub animations: Vec<Animation>,
+    #[prost(bool, tag = "3")]
+    pub counter_clockwise: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Texture {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub images: Vec<Image>,
+    #[prost(int32, tag = "3")]
+    pub width: i32,
+    #[prost(int32, tag = "4")]
+    pub height: i32,
+    #[prost(string, tag = "5")]
+    pub checksum: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Image {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(int32, tag = "2")]
+    pub x: i32,
+    #[prost(int32, tag = "3")]
+    pub y: i32,
+    #[prost(int32, tag = "4")]
+    pub width: i32,
+    #[prost(int32, tag = "5")]
+    pub height: i32,
+
+    #[prost(int32, tag = "6")]
+    pub frame_x: i32,
+    #[prost(int32, tag = "7")]
+    pub frame_y: i32,
+    #[prost(int32, tag = "8")]
+    pub frame_width: i32,
+    #[prost(int32, tag = "9")]
+    pub frame_height: i32,
+
+    #[prost(bool, tag = "10")]
+    pub rotated: bool,
+
+    #[prost(float, tag = "11")]
+    pub pivot_x: f32,
+    #[prost(float, tag = "12")]
+    pub pivot_y: f32,
+
+    #[prost(string, repeated, tag = "13")]
+    pub aliases: Vec<String>,
+    #[prost(uint32, optional, tag = "14")]
+    pub index: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Animation {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, repeated, tag = "2")]
+    pub frames: Vec<String>,
+}
+
+impl From<&serial::Atlas> for Atlas {
+    fn from(atlas: &serial::Atlas) -> Atlas {
+        Atlas {
+            textures: atlas.textures.iter().map(Texture::from).collect(),
+            animations: atlas.animations.iter().map(Animation::from).collect(),
+            counter_clockwise: atlas.rotation == Rotation::Ccw,
+        }
+    }
+}
+
+impl From<&serial::Texture> for Texture {
+    fn from(texture: &serial::Texture) -> Texture {
+        Texture {
+            name: texture.name.clone(),
+            images: texture.images.iter().map(Image::from).collect(),
+            width: texture.width,
+            height: texture.height,
+            checksum: texture.checksum.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&serial::Image> for Image {
+    fn from(image: &serial::Image) -> Image {
+        Image {
+            name: image.name.clone(),
+            x: image.x,
+            y: image.y,
+            width: image.width,
+            height: image.height,
+            frame_x: image.frame_x,
+            frame_y: image.frame_y,
+            frame_width: image.frame_width,
+            frame_height: image.frame_height,
+            rotated: image.rotated,
+            pivot_x: image.pivot_x,
+            pivot_y: image.pivot_y,
+            aliases: image.aliases.clone(),
+            index: image.index,
+        }
+    }
+}
+
+impl From<&serial::Animation> for Animation {
+    fn from(animation: &serial::Animation) -> Animation {
+        Animation {
+            name: animation.name.clone(),
+            frames: animation.frames.clone(),
+        }
+    }
+}
+
+impl From<Atlas> for serial::Atlas {
+    fn from(atlas: Atlas) -> serial::Atlas {
+        serial::Atlas {
+            textures: atlas
+                .textures
+                .into_iter()
+                .map(serial::Texture::from)
+                .collect(),
+            animations: atlas
+                .animations
+                .into_iter()
+                .map(serial::Animation::from)
+                .collect(),
+            rotation: if atlas.counter_clockwise {
+                Rotation::Ccw
+            } else {
+                Rotation::Cw
+            },
+            ..serial::Atlas::default()
+        }
+    }
+}
+
+impl From<Texture> for serial::Texture {
+    fn from(texture: Texture) -> serial::Texture {
+        serial::Texture {
+            name: texture.name,
+            width: texture.width,
+            height: texture.height,
+            images: texture
+                .images
+                .into_iter()
+                .map(serial::Image::from)
+                .collect(),
+            checksum: Some(texture.checksum).filter(|checksum| !checksum.is_empty()),
+        }
+    }
+}
+
+impl From<Image> for serial::Image {
+    fn from(image: Image) -> serial::Image {
+        serial::Image {
+            name: image.name,
+            x: image.x,
+            y: image.y,
+            width: image.width,
+            height: image.height,
+            frame_x: image.frame_x,
+            frame_y: image.frame_y,
+            frame_width: image.frame_width,
+            frame_height: image.frame_height,
+            rotated: image.rotated,
+            pivot_x: image.pivot_x,
+            pivot_y: image.pivot_y,
+            data: Default::default(),
+            aliases: image.aliases,
+            index: image.index,
+        }
+    }
+}
+
+impl From<Animation> for serial::Animation {
+    fn from(animation: Animation) -> serial::Animation {
+        serial::Animation {
+            name: animation.name,
+            frames: animation.frames,
+        }
+    }
+}
+
+impl serial::Atlas {
+    pub fn from_proto(bytes: &[u8]) -> Result<serial::Atlas> {
+        Ok(Atlas::decode(bytes)?.into())
+    }
+
+    pub fn write_to_proto_file<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_proto())?;
+        Ok(())
+    }
+
+    pub fn to_proto(&self) -> Vec<u8> {
+        Atlas::from(self).encode_to_vec()
+    }
+}
@@ -0,0 +1,98 @@
+//! SpriteKit compiled texture atlas (`.atlasc`) output.
+//!
+//! The bundle is a directory holding a copy of every page image and a plist
+//! describing the pages and their subimages, using the key names Xcode emits.
+
+use crate::error::Result;
+use crate::serial::{Atlas, Image, Texture};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize)]
+struct SpriteKitAtlas {
+    format: &'static str,
+    images: Vec<SpriteKitPage>,
+    version: i32,
+}
+
+#[derive(Serialize)]
+struct SpriteKitPage {
+    path: String,
+    size: String,
+    subimages: Vec<SpriteKitImage>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpriteKitImage {
+    name: String,
+    is_fully_opaque: bool,
+    sprite_offset: String,
+    sprite_source_size: String,
+    texture_rect: String,
+    texture_rotated: bool,
+}
+
+impl Atlas {
+    /// Writes a `.atlasc` bundle to `path`, copying the page images out of `pages_dir`
+    pub fn write_to_spritekit_bundle<P: AsRef<Path>>(
+        &self,
+        path: P,
+        pages_dir: &Path,
+        extension: &str,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+
+        let mut atlas = SpriteKitAtlas {
+            format: "APPL",
+            images: vec![],
+            version: 1,
+        };
+        for texture in self.textures.iter() {
+            let page_name = format!("{}.{}", texture.name, extension);
+            std::fs::copy(pages_dir.join(&page_name), path.join(&page_name))?;
+            atlas.images.push(build_page(texture, page_name));
+        }
+
+        let plist_path = path
+            .join(path.file_stem().expect("could not retrieve bundle name"))
+            .with_extension("plist");
+        plist::to_file_xml(plist_path, &atlas)?;
+        Ok(())
+    }
+}
+
+fn build_page(texture: &Texture, path: String) -> SpriteKitPage {
+    SpriteKitPage {
+        path,
+        size: format!("{{{},{}}}", texture.width, texture.height),
+        // SpriteKit looks sprites up by name, so every alias gets an entry of its own
+        subimages: texture
+            .images
+            .iter()
+            .flat_map(|image| image.names().map(move |name| build_image(image, name)))
+            .collect(),
+    }
+}
+
+fn build_image(image: &Image, name: &str) -> SpriteKitImage {
+    // SpriteKit measures the offset between the centers of the trimmed and
+    // untrimmed images, with y pointing up
+    let trim_x = -image.frame_x;
+    let trim_y = -image.frame_y;
+    let offset_x = (2 * trim_x + image.width - image.frame_width) / 2;
+    let offset_y = (image.frame_height - 2 * trim_y - image.height) / 2;
+
+    SpriteKitImage {
+        name: format!("{}.png", name),
+        is_fully_opaque: false,
+        sprite_offset: format!("{{{},{}}}", offset_x, offset_y),
+        sprite_source_size: format!("{{{},{}}}", image.frame_width, image.frame_height),
+        texture_rect: format!(
+            "{{{{{},{}}},{{{},{}}}}}",
+            image.x, image.y, image.width, image.height
+        ),
+        texture_rotated: image.rotated,
+    }
+}
@@ -0,0 +1,35 @@
+use crate::error::Result;
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, RgbaImage};
+use std::path::Path;
+
+/// Whether `path` has the extension of a GIF
+pub fn is_gif_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("gif"))
+}
+
+/// Decodes every frame of the GIF at `path`, named `name_0`, `name_1`... (or just `name`
+/// if it isn't animated).
+///
+/// Each frame is composited over the previous ones as it would be displayed, so that every
+/// sprite has the full size of the GIF.
+pub fn frames(path: &Path, name: &str) -> Result<Vec<(String, RgbaImage)>> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let frames = GifDecoder::new(file)?.into_frames().collect_frames()?;
+
+    let count = frames.len();
+    Ok(frames
+        .into_iter()
+        .enumerate()
+        .map(|(idx, frame)| {
+            let frame_name = if count == 1 {
+                name.to_owned()
+            } else {
+                format!("{}_{}", name, idx)
+            };
+            (frame_name, frame.into_buffer())
+        })
+        .collect())
+}
@@ -0,0 +1,102 @@
+use crate::error::Result;
+use image::{Rgba32FImage, RgbaImage};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Whether `path` has the extension of a Radiance HDR or OpenEXR image
+pub fn is_hdr_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            ext.eq_ignore_ascii_case("hdr") || ext.eq_ignore_ascii_case("exr")
+        })
+}
+
+/// Decodes the HDR image at `path` into linear colors
+pub fn load(path: &Path) -> Result<Rgba32FImage> {
+    Ok(image::open(path)?.into_rgba32f())
+}
+
+/// How the linear colors of HDR images are brought into the 0 to 1 range before being stored in
+/// 8 bits
+#[derive(Deserialize, Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemap {
+    /// Cuts off anything brighter than 1
+    Clamp,
+    /// `c / (1 + c)`, which keeps some detail in the highlights
+    Reinhard,
+    /// A fit of the ACES filmic curve, with more contrast than Reinhard
+    Aces,
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Tonemap::Clamp
+    }
+}
+
+impl std::str::FromStr for Tonemap {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match &*s.to_ascii_lowercase() {
+            "clamp" => Ok(Tonemap::Clamp),
+            "reinhard" => Ok(Tonemap::Reinhard),
+            "aces" => Ok(Tonemap::Aces),
+            _ => Err(format!("unknown tonemap operator: {}", s)),
+        }
+    }
+}
+
+impl Tonemap {
+    /// Maps a linear color channel to the 0 to 1 range
+    fn map(self, c: f32) -> f32 {
+        let c = c.max(0.0);
+        let mapped = match self {
+            Tonemap::Clamp => c,
+            Tonemap::Reinhard => c / (1.0 + c),
+            Tonemap::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+        };
+        mapped.min(1.0)
+    }
+
+    /// Tonemaps the linear colors of `image` and encodes them as 8-bit sRGB. Alpha is only clamped.
+    pub fn apply(self, image: &Rgba32FImage) -> RgbaImage {
+        let data = image
+            .as_raw()
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let alpha = pixel[3].max(0.0).min(1.0);
+                [
+                    to_srgb(self.map(pixel[0])),
+                    to_srgb(self.map(pixel[1])),
+                    to_srgb(self.map(pixel[2])),
+                    (alpha * 255.0).round() as u8,
+                ]
+            })
+            .collect();
+        RgbaImage::from_raw(image.width(), image.height(), data)
+            .expect("tonemapped buffer has the size of the image")
+    }
+}
+
+/// Encodes a linear value from 0 to 1 with the sRGB transfer function
+fn to_srgb(c: f32) -> u8 {
+    let encoded = if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+/// Decodes an 8-bit sRGB value into a linear value from 0 to 1
+pub fn from_srgb(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -0,0 +1,161 @@
+//! The trimmed bitmaps loaded from every input file, kept in the cache directory so that files
+//! that didn't change don't have to be decoded and trimmed again when the atlas is repacked.
+
+use crate::config::ImageSettings;
+use crate::error::Result;
+use crate::image_wrapper::ImageWrapper;
+use crate::{serial, Loaded, Opt};
+use metrohash::MetroHash;
+use serde::de::{Deserializer, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+/// The folder inside the cache directory that holds the bitmaps
+const FOLDER: &str = "images";
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    sprites: Vec<Sprite>,
+    animations: Vec<serial::Animation>,
+}
+
+/// A trimmed bitmap, before any of the settings from its sidecar file are applied
+#[derive(Serialize, Deserialize)]
+struct Sprite {
+    name: String,
+    width: i32,
+    height: i32,
+    frame_x: i32,
+    frame_y: i32,
+    frame_w: i32,
+    frame_h: i32,
+    data: Bytes,
+    hdr: Option<Vec<f32>>,
+    hash: u64,
+}
+
+/// Pixels stored as a single byte string, rather than as an array of numbers
+struct Bytes(Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> std::result::Result<Bytes, E> {
+        Ok(Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> std::result::Result<Bytes, E> {
+        Ok(Bytes(v))
+    }
+}
+
+/// The key of the bitmaps loaded from a file whose contents hash to `file_hash`, covering
+/// everything that changes how the file is decoded and trimmed
+pub fn key(file_hash: u64, name: &str, settings: &ImageSettings, opt: &Opt) -> u64 {
+    let mut hasher = MetroHash::default();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    file_hash.hash(&mut hasher);
+    name.hash(&mut hasher);
+    settings.premultiply.hash(&mut hasher);
+    settings.trim.hash(&mut hasher);
+    settings.scale.hash(&mut hasher);
+    opt.trim_margin.hash(&mut hasher);
+    opt.aseprite_layers.hash(&mut hasher);
+    opt.aseprite_tags.hash(&mut hasher);
+    opt.gif_animations.hash(&mut hasher);
+    opt.psd_layers.hash(&mut hasher);
+    opt.tonemap.hash(&mut hasher);
+    opt.extension.to_ascii_lowercase().hash(&mut hasher);
+    opt.slice.hash(&mut hasher);
+    opt.strip.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn path(opt: &Opt, key: u64) -> PathBuf {
+    opt.cache_dir
+        .join(FOLDER)
+        .join(format!("{:016x}.cbor", key))
+}
+
+/// The bitmaps cached under `key`, if there are any readable ones
+pub fn load(opt: &Opt, key: u64) -> Option<Loaded> {
+    let path = path(opt, key);
+    let contents = std::fs::read(&path).ok()?;
+    let entry: Entry = match serde_cbor::from_slice(&contents) {
+        Ok(entry) => entry,
+        Err(err) => {
+            log::warn!("ignoring invalid cached image {}: {}", path.display(), err);
+            return None;
+        }
+    };
+
+    let images = entry
+        .sprites
+        .into_iter()
+        .map(|sprite| {
+            let mut image = ImageWrapper::empty(0, 0);
+            image.name = sprite.name;
+            image.width = sprite.width;
+            image.height = sprite.height;
+            image.frame_x = sprite.frame_x;
+            image.frame_y = sprite.frame_y;
+            image.frame_w = sprite.frame_w;
+            image.frame_h = sprite.frame_h;
+            image.data = sprite.data.0;
+            image.hdr = sprite.hdr;
+            image.hash_value = sprite.hash;
+            image
+        })
+        .collect();
+    Some(Loaded {
+        images,
+        animations: entry.animations,
+    })
+}
+
+/// Caches the bitmaps of `loaded` under `key`
+pub fn save(opt: &Opt, key: u64, loaded: &Loaded) -> Result<()> {
+    let entry = Entry {
+        sprites: loaded
+            .images
+            .iter()
+            .map(|image| Sprite {
+                name: image.name.clone(),
+                width: image.width,
+                height: image.height,
+                frame_x: image.frame_x,
+                frame_y: image.frame_y,
+                frame_w: image.frame_w,
+                frame_h: image.frame_h,
+                data: Bytes(image.data.clone()),
+                hdr: image.hdr.clone(),
+                hash: image.hash_value,
+            })
+            .collect(),
+        animations: loaded.animations.clone(),
+    };
+    let path = path(opt, key);
+    std::fs::create_dir_all(path.parent().expect("cache files are in a folder"))?;
+    std::fs::write(&path, serde_cbor::to_vec(&entry)?)?;
+    Ok(())
+}
@@ -1,23 +1,78 @@
 use crate::error::Result;
-use image::RgbaImage;
+use crate::packer::Rotation;
+use crate::serial::Source;
+use image::{Rgba32FImage, RgbaImage};
 use metrohash::MetroHash;
+use std::collections::BTreeMap;
 use std::hash::Hasher;
 
+/// An RGBA bitmap ready to be packed, trimmed of any transparent border
+#[derive(Clone)]
 pub struct ImageWrapper {
+    /// The name written to the atlas metadata
     pub name: String,
+    /// The size of the trimmed bitmap
     pub width: i32,
     pub height: i32,
+    /// The offset of the trimmed bitmap in the original image (zero or negative)
     pub frame_x: i32,
     pub frame_y: i32,
+    /// The size of the original image
     pub frame_w: i32,
     pub frame_h: i32,
+    /// The trimmed pixels, four bytes per pixel
     pub data: Vec<u8>,
+    /// The trimmed linear pixels of HDR images, four floats per pixel, kept for float pages
+    pub hdr: Option<Vec<f32>>,
+    /// A hash of the trimmed pixels, used to find duplicates
     pub hash_value: u64,
+    /// The size of the source file in bytes
     pub original_size: u64,
+    /// The pivot point, in pixels from the top-left of the original image
+    pub pivot_x: f32,
+    pub pivot_y: f32,
+    /// The padding after this image, overriding the page's
+    pub pad: Option<i32>,
+    /// How many times the edge pixels are repeated around the image in the page
+    pub extrude: i32,
+    /// Images with the same group are always packed into the same page
+    pub group: Option<usize>,
+    /// Images with a higher priority are packed first
+    pub priority: i32,
+    /// Custom values passed through to the atlas metadata
+    pub user_data: BTreeMap<String, serde_json::Value>,
+    /// Other names the image is listed under in the atlas metadata
+    pub aliases: Vec<String>,
+    /// Where the image goes in the larger image it was cut out of, if it's a tile
+    pub tile: Option<TilePosition>,
+    /// The file the image was read from, with `--sources`
+    pub source: Option<Source>,
+}
+
+/// The position of a tile in the image it was cut out of
+#[derive(Clone, Debug, PartialEq)]
+pub struct TilePosition {
+    /// The name and untrimmed size of the whole image
+    pub image: String,
+    pub width: i32,
+    pub height: i32,
+    pub x: i32,
+    pub y: i32,
 }
 
 impl ImageWrapper {
-    pub fn new(image: RgbaImage, name: String, premultiply: bool, trim: bool, original_size: u64) -> Self {
+    /// Wraps `image`, optionally premultiplying its alpha and trimming its transparent border.
+    ///
+    /// Trimming keeps up to `trim_margin` transparent pixels around the visible ones, as long as
+    /// the image has them.
+    pub fn new(
+        image: RgbaImage,
+        name: String,
+        premultiply: bool,
+        trim: bool,
+        trim_margin: i32,
+        original_size: u64,
+    ) -> Self {
         let w = image.width() as i32;
         let h = image.height() as i32;
 
@@ -25,34 +80,24 @@ impl ImageWrapper {
 
         // premultiply all pixels by their alpha
         if premultiply {
-            let count = (w as usize) * (h as usize);
-            for i in 0..count {
-                let r = pixels[i * 4 + 0];
-                let g = pixels[i * 4 + 1];
-                let b = pixels[i * 4 + 2];
-                let a = pixels[i * 4 + 3] as f32 / 255f32;
-
-                pixels[i * 4 + 0] = (r as f32 * a) as u8;
-                pixels[i * 4 + 1] = (g as f32 * a) as u8;
-                pixels[i * 4 + 2] = (b as f32 * a) as u8;
-            }
+            premultiply_pixels(&mut pixels);
         }
 
         // get pixel bounds
+        let stride = w as usize * 4;
         let mut min_x = w - 1;
         let mut min_y = h - 1;
         let mut max_x = 0;
         let mut max_y = 0;
-        if trim {
-            for y in 0..h {
-                for x in 0..w {
-                    let a = pixels[(y * w + x) as usize * 4 + 3];
-                    if a > 0 {
-                        min_x = std::cmp::min(x, min_x);
-                        min_y = std::cmp::min(y, min_y);
-                        max_x = std::cmp::max(x, max_x);
-                        max_y = std::cmp::max(y, max_y);
-                    }
+        if trim && w > 0 {
+            for (y, row) in pixels.chunks_exact(stride).enumerate() {
+                let is_opaque = |pixel: &[u8]| pixel[3] > 0;
+                if let Some(first) = row.chunks_exact(4).position(is_opaque) {
+                    let last = row.chunks_exact(4).rposition(is_opaque).unwrap_or(first);
+                    min_x = std::cmp::min(first as i32, min_x);
+                    max_x = std::cmp::max(last as i32, max_x);
+                    min_y = std::cmp::min(y as i32, min_y);
+                    max_y = y as i32;
                 }
             }
             if max_x < min_x || max_y < min_y {
@@ -61,6 +106,11 @@ impl ImageWrapper {
                 max_x = w - 1;
                 max_y = h - 1;
                 log::warn!("image is completely transparent: {}", &name);
+            } else {
+                min_x = std::cmp::max(min_x - trim_margin, 0);
+                min_y = std::cmp::max(min_y - trim_margin, 0);
+                max_x = std::cmp::min(max_x + trim_margin, w - 1);
+                max_y = std::cmp::min(max_y + trim_margin, h - 1);
             }
         } else {
             min_x = 0;
@@ -78,35 +128,26 @@ impl ImageWrapper {
         let (frame_x, frame_y, data) = if width == w && height == h {
             (0, 0, pixels)
         } else {
-            // create the trimmed image data
-            let mut data = vec![0; (width * height) as usize * 4];
-            let frame_x = -min_x;
-            let frame_y = -min_y;
-
-            // copy trimmed pixels over to the trimmed pixel array
-            for y in min_y..max_y + 1 {
-                for x in min_x..max_x + 1 {
-                    data[((y - min_y) * width + (x - min_x)) as usize * 4 + 0] =
-                        pixels[(y * w + x) as usize * 4 + 0];
-                    data[((y - min_y) * width + (x - min_x)) as usize * 4 + 1] =
-                        pixels[(y * w + x) as usize * 4 + 1];
-                    data[((y - min_y) * width + (x - min_x)) as usize * 4 + 2] =
-                        pixels[(y * w + x) as usize * 4 + 2];
-                    data[((y - min_y) * width + (x - min_x)) as usize * 4 + 3] =
-                        pixels[(y * w + x) as usize * 4 + 3];
-                }
+            // copy the trimmed rows over to the trimmed pixel array
+            let row_len = width as usize * 4;
+            let mut data = Vec::with_capacity(row_len * height as usize);
+            for row in pixels
+                .chunks_exact(stride)
+                .skip(min_y as usize)
+                .take(height as usize)
+            {
+                let start = min_x as usize * 4;
+                data.extend_from_slice(&row[start..start + row_len]);
             }
 
-            (frame_x, frame_y, data)
+            (-min_x, -min_y, data)
         };
 
         // generate a hash for the bitmap
         let mut hash = MetroHash::default();
         hash.write_i32(width);
         hash.write_i32(height);
-        for byte in data.iter() {
-            hash.write_u8(byte.clone());
-        }
+        hash.write(&data);
         let hash_value = hash.finish();
 
         Self {
@@ -118,11 +159,23 @@ impl ImageWrapper {
             frame_w,
             frame_h,
             data,
+            hdr: None,
             hash_value,
             original_size,
+            pivot_x: 0.0,
+            pivot_y: 0.0,
+            pad: None,
+            extrude: 0,
+            group: None,
+            priority: 0,
+            user_data: BTreeMap::new(),
+            aliases: vec![],
+            tile: None,
+            source: None,
         }
     }
 
+    /// A fully transparent bitmap
     pub fn empty(width: i32, height: i32) -> Self {
         Self {
             name: String::new(),
@@ -133,9 +186,90 @@ impl ImageWrapper {
             frame_w: width,
             frame_h: width,
             data: vec![0; (width * height) as usize * 4],
+            hdr: None,
             hash_value: 0,
             original_size: 0,
+            pivot_x: 0.0,
+            pivot_y: 0.0,
+            pad: None,
+            extrude: 0,
+            group: None,
+            priority: 0,
+            user_data: BTreeMap::new(),
+            aliases: vec![],
+            tile: None,
+            source: None,
+        }
+    }
+
+    /// Keeps the linear pixels of the HDR image this bitmap was tonemapped from, trimmed the same
+    /// way and premultiplied if the bitmap was
+    pub fn with_hdr(mut self, image: &Rgba32FImage, premultiply: bool) -> Self {
+        let stride = image.width() as usize * 4;
+        let row_len = self.width as usize * 4;
+        let left = (-self.frame_x) as usize * 4;
+        let mut hdr = Vec::with_capacity(row_len * self.height as usize);
+        for row in image
+            .as_raw()
+            .chunks_exact(stride)
+            .skip((-self.frame_y) as usize)
+            .take(self.height as usize)
+        {
+            hdr.extend_from_slice(&row[left..left + row_len]);
+        }
+
+        if premultiply {
+            premultiply_hdr_pixels(&mut hdr);
+        }
+
+        // Sprites that only differ in their highlights aren't duplicates
+        let mut hash = MetroHash::default();
+        hash.write_u64(self.hash_value);
+        for value in &hdr {
+            hash.write_u32(value.to_bits());
+        }
+        self.hash_value = hash.finish();
+        self.hdr = Some(hdr);
+        self
+    }
+
+    /// Makes the trimmed bitmap the whole image, for runtimes that ignore the offset of trimmed
+    /// sprites
+    pub fn crop(&mut self) {
+        self.frame_x = 0;
+        self.frame_y = 0;
+        self.frame_w = self.width;
+        self.frame_h = self.height;
+    }
+
+    /// Whether `other` has the same size and none of its channels differ from this bitmap's by
+    /// more than `max_diff`. HDR bitmaps have to be identical.
+    pub fn is_near(&self, other: &ImageWrapper, max_diff: u8) -> bool {
+        if self.hdr.is_some() || other.hdr.is_some() {
+            return self == other;
         }
+        self.width == other.width
+            && self.height == other.height
+            && self
+                .data
+                .chunks_exact(4)
+                .zip(other.data.chunks_exact(4))
+                .all(|(a, b)| is_near_pixel(a, b, max_diff))
+    }
+
+    /// The fraction of pixels of `other` where none of the channels differ from this bitmap's
+    /// by more than `max_diff`, from 0 to 1. Bitmaps of different sizes have nothing in common.
+    pub fn similarity(&self, other: &ImageWrapper, max_diff: u8) -> f32 {
+        if self.width != other.width || self.height != other.height || self.data.is_empty() {
+            return 0.0;
+        }
+        let near = self
+            .data
+            .chunks_exact(4)
+            .zip(other.data.chunks_exact(4))
+            .filter(|(a, b)| is_near_pixel(a, b, max_diff))
+            .count();
+        near as f32 / (self.data.len() / 4) as f32
     }
 
     pub fn get_image(&self) -> RgbaImage {
@@ -160,42 +294,95 @@ impl ImageWrapper {
         self.data[idx] = value;
     }
 
+    /// Copies all of `src` into this bitmap at (`tx`, `ty`), a row at a time
     pub fn copy_pixels(&mut self, src: &ImageWrapper, tx: i32, ty: i32) {
-        for y in 0..src.height {
-            for x in 0..src.width {
-                for channel in 0..4 {
-                    self.set_pixel(
-                        (tx + x) as usize,
-                        (ty + y) as usize,
-                        channel,
-                        src.get_pixel(x as usize, y as usize, channel),
-                    );
-                }
-            }
+        let stride = self.width as usize * 4;
+        let len = src.width as usize * 4;
+        for (y, row) in src.data.chunks_exact(len).enumerate() {
+            let start = (ty as usize + y) * stride + tx as usize * 4;
+            self.data[start..start + len].copy_from_slice(row);
         }
     }
 
-    pub fn copy_pixels_rot(&mut self, src: &ImageWrapper, tx: i32, ty: i32) {
-        let r = src.height - 1;
+    /// Copies all of `src`, rotated 90 degrees in the direction of `rotation`, into this bitmap
+    /// at (`tx`, `ty`)
+    pub fn copy_pixels_rot(&mut self, src: &ImageWrapper, tx: i32, ty: i32, rotation: Rotation) {
+        let stride = self.width as usize * 4;
+        // The rotated bitmap is as wide as the source is tall
+        let len = src.height as usize * 4;
         for y in 0..src.width {
-            for x in 0..src.height {
-                for channel in 0..4 {
-                    self.set_pixel(
-                        (tx + x) as usize,
-                        (ty + y) as usize,
-                        channel,
-                        src.get_pixel(y as usize, (r - x) as usize, channel),
-                    );
-                }
+            let start = (ty + y) as usize * stride + tx as usize * 4;
+            copy_rotated_row(
+                &mut self.data[start..start + len],
+                &src.data,
+                src.width,
+                y,
+                rotation,
+            );
+        }
+    }
+}
+
+/// Fills `row` with row `y` of a bitmap `width` pixels wide, made of `data`, once rotated 90
+/// degrees in the direction of `rotation`. That row is a column of the bitmap, which is read a
+/// pixel per source row while `row` is written in order.
+pub fn copy_rotated_row<T: Copy>(
+    row: &mut [T],
+    data: &[T],
+    width: i32,
+    y: i32,
+    rotation: Rotation,
+) {
+    let stride = width as usize * 4;
+    let pixels = row.chunks_exact_mut(4);
+    match rotation {
+        // Column `y`, from the bottom up
+        Rotation::Cw => {
+            let column = data[y as usize * 4..].chunks(stride).rev();
+            for (pixel, src) in pixels.zip(column) {
+                pixel.copy_from_slice(&src[..4]);
             }
         }
+        // Column `width - 1 - y`, from the top down
+        Rotation::Ccw => {
+            let column = data[(width - 1 - y) as usize * 4..].chunks(stride);
+            for (pixel, src) in pixels.zip(column) {
+                pixel.copy_from_slice(&src[..4]);
+            }
+        }
+    }
+}
+
+/// Multiplies the color of every RGBA pixel of `pixels` by its alpha
+pub fn premultiply_pixels(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let a = pixel[3] as f32 / 255f32;
+        pixel[0] = (pixel[0] as f32 * a) as u8;
+        pixel[1] = (pixel[1] as f32 * a) as u8;
+        pixel[2] = (pixel[2] as f32 * a) as u8;
     }
 }
 
+/// Multiplies the color of every linear RGBA pixel of `pixels` by its alpha
+pub fn premultiply_hdr_pixels(pixels: &mut [f32]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let a = pixel[3];
+        pixel[0] *= a;
+        pixel[1] *= a;
+        pixel[2] *= a;
+    }
+}
+
+fn is_near_pixel(a: &[u8], b: &[u8], max_diff: u8) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .all(|(a, b)| a.abs_diff(*b) <= max_diff)
+}
+
 impl PartialEq for ImageWrapper {
     fn eq(&self, other: &Self) -> bool {
         if self.width == other.width && self.height == other.height {
-            return self.data == other.data;
+            return self.data == other.data && self.hdr == other.hdr;
         }
         false
     }
@@ -0,0 +1,332 @@
+use crate::error::Result;
+use crate::image_wrapper::ImageWrapper;
+use crate::packer::{Packer, Point, Rotation};
+use crate::serial::{Animation, Atlas};
+use metrohash::MetroHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/// The layout of a previous run, stored next to the `.hash` file so that impact can repack
+/// incrementally when only the contents of some images change.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Layout {
+    /// The hash of the options the atlas was packed with
+    pub options: u64,
+    pub pages: Vec<Page>,
+    /// The animations defined by the input files
+    #[serde(default)]
+    pub animations: Vec<Animation>,
+    /// The index of every sprite, with `--stable-indices`
+    #[serde(default)]
+    pub indices: Indices,
+}
+
+/// The numbers given to sprites with `--stable-indices`, kept from one run to the next
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Indices {
+    pub by_name: BTreeMap<String, u32>,
+    /// The index the next new sprite gets. Indices of removed sprites aren't given out again,
+    /// so that a runtime still using one can't pick up the wrong sprite.
+    pub next: u32,
+}
+
+impl Indices {
+    /// Keeps the indices of the sprites of `atlas`, e.g. one being appended to or merged.
+    /// Sprites whose name or index is already taken are left out, to get a new index.
+    pub fn add_atlas(&mut self, atlas: &Atlas) {
+        let mut taken: HashSet<u32> = self.by_name.values().copied().collect();
+        for image in atlas
+            .textures
+            .iter()
+            .flat_map(|texture| texture.images.iter())
+        {
+            let index = match image.index {
+                Some(index) if !self.by_name.contains_key(&image.name) => index,
+                _ => continue,
+            };
+            if taken.insert(index) {
+                self.by_name.insert(image.name.clone(), index);
+                // Removed sprites aren't in the atlas, so this is as far as it's known to go
+                self.next = std::cmp::max(self.next, index + 1);
+            }
+        }
+    }
+
+    /// Numbers the sprites called `names`, keeping the indices of the ones already numbered
+    /// and giving the new ones the next indices, in order of name
+    pub fn assign<'a>(&self, names: impl Iterator<Item = &'a str>) -> Indices {
+        let mut names: Vec<&str> = names.collect();
+        names.sort_unstable();
+        names.dedup();
+
+        let mut indices = Indices {
+            by_name: BTreeMap::new(),
+            next: self.next,
+        };
+        for name in names {
+            let index = match self.by_name.get(name) {
+                Some(&index) => index,
+                None => {
+                    indices.next += 1;
+                    indices.next - 1
+                }
+            };
+            indices.by_name.insert(name.to_owned(), index);
+        }
+        indices
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Page {
+    pub width: i32,
+    pub height: i32,
+    pub sprites: Vec<Sprite>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Sprite {
+    pub name: String,
+    /// The hash of the sprite's (trimmed) pixels
+    pub hash: u64,
+    pub width: i32,
+    pub height: i32,
+    pub frame_x: i32,
+    pub frame_y: i32,
+    pub frame_w: i32,
+    pub frame_h: i32,
+    pub x: i32,
+    pub y: i32,
+    pub dup_id: i32,
+    pub rot: bool,
+    /// The per-sprite settings from its sidecar file
+    #[serde(default)]
+    pub pivot_x: f32,
+    #[serde(default)]
+    pub pivot_y: f32,
+    #[serde(default)]
+    pub pad: Option<i32>,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub data: BTreeMap<String, serde_json::Value>,
+    /// The other names the sprite is listed under, from `~alias=` suffixes
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// The size of the file the sprite was loaded from, in bytes, for the stats
+    #[serde(default)]
+    pub original_size: u64,
+}
+
+/// Pages rebuilt from a previous layout
+pub struct Reused {
+    pub packers: Vec<Packer>,
+    /// Whether the pixels of each page changed, so that it has to be written again
+    pub dirty: Vec<bool>,
+    /// Whether anything written to the metadata changed
+    pub metadata_changed: bool,
+}
+
+impl Layout {
+    pub fn from_packers(options: u64, packers: &[Packer], animations: Vec<Animation>) -> Self {
+        let pages = packers
+            .iter()
+            .map(|packer| Page {
+                width: packer.width,
+                height: packer.height,
+                sprites: packer
+                    .images
+                    .iter()
+                    .zip(packer.points.iter())
+                    .map(|(image, point)| Sprite {
+                        name: image.name.clone(),
+                        hash: image.hash_value,
+                        width: image.width,
+                        height: image.height,
+                        frame_x: image.frame_x,
+                        frame_y: image.frame_y,
+                        frame_w: image.frame_w,
+                        frame_h: image.frame_h,
+                        x: point.x,
+                        y: point.y,
+                        dup_id: point.dup_id,
+                        rot: point.rot,
+                        pivot_x: image.pivot_x,
+                        pivot_y: image.pivot_y,
+                        pad: image.pad,
+                        priority: image.priority,
+                        data: image.user_data.clone(),
+                        aliases: image.aliases.clone(),
+                        original_size: image.original_size,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            options,
+            pages,
+            animations,
+            indices: Indices::default(),
+        }
+    }
+
+    /// Loads a previous layout, if there is a readable one
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let contents = std::fs::read(path.as_ref()).ok()?;
+        match serde_json::from_slice(&contents) {
+            Ok(layout) => Some(layout),
+            Err(err) => {
+                log::warn!(
+                    "ignoring invalid layout {}: {}",
+                    path.as_ref().display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let res = serde_json::to_vec(self).expect("failed to serialize layout");
+        std::fs::write(path, &res)?;
+        Ok(())
+    }
+
+    /// Places the images at the same positions as last time.
+    ///
+    /// This only succeeds if exactly the same sprites are present, each with the same trimmed
+    /// size, and (with `unique`) the same sprites are duplicates of each other; otherwise the
+    /// images are left untouched and the atlas has to be repacked from scratch.
+    pub fn reuse(
+        &self,
+        images: &mut Vec<ImageWrapper>,
+        unique: bool,
+        pad: i32,
+        rotation: Rotation,
+    ) -> Option<Reused> {
+        let sprite_count: usize = self.pages.iter().map(|page| page.sprites.len()).sum();
+        if sprite_count != images.len() {
+            log::info!("the number of images changed, repacking");
+            return None;
+        }
+
+        let mut by_name = MetroHashMap::default();
+        for (idx, image) in images.iter().enumerate() {
+            if by_name.insert(image.name.as_str(), idx).is_some() {
+                return None;
+            }
+        }
+
+        // Match every sprite to its new image before touching anything
+        let mut placements = vec![];
+        // The packed (non-duplicate) images of every page so far, to catch images that became
+        // duplicates of ones in an earlier page
+        let mut packed: MetroHashMap<u64, Vec<usize>> = MetroHashMap::default();
+        for page in self.pages.iter() {
+            let mut indices: Vec<usize> = vec![];
+            let mut dup_lookup = MetroHashMap::default();
+            for sprite in page.sprites.iter() {
+                let idx = match by_name.get(sprite.name.as_str()) {
+                    Some(idx) => *idx,
+                    None => {
+                        log::info!("image {} was added or removed, repacking", sprite.name);
+                        return None;
+                    }
+                };
+                let image = &images[idx];
+                if image.width != sprite.width || image.height != sprite.height {
+                    log::info!("image {} changed size, repacking", sprite.name);
+                    return None;
+                }
+                if image.pad != sprite.pad {
+                    log::info!("padding of image {} changed, repacking", sprite.name);
+                    return None;
+                }
+                if image.priority != sprite.priority {
+                    log::info!("priority of image {} changed, repacking", sprite.name);
+                    return None;
+                }
+
+                if unique {
+                    let dup_of = dup_lookup
+                        .get(&image.hash_value)
+                        .filter(|dup_id| images[indices[**dup_id]] == *image)
+                        .map(|dup_id| *dup_id as i32)
+                        .unwrap_or(-1);
+                    if dup_of != sprite.dup_id {
+                        log::info!("duplicates of image {} changed, repacking", sprite.name);
+                        return None;
+                    }
+                    if dup_of < 0 {
+                        let earlier = packed.get(&image.hash_value).map_or(false, |others| {
+                            others.iter().any(|other| images[*other] == *image)
+                        });
+                        if earlier {
+                            log::info!("image {} became a duplicate, repacking", sprite.name);
+                            return None;
+                        }
+                        dup_lookup.insert(image.hash_value, indices.len());
+                    }
+                }
+                indices.push(idx);
+            }
+            if unique {
+                for (sprite, idx) in page.sprites.iter().zip(indices.iter()) {
+                    if sprite.dup_id < 0 {
+                        packed
+                            .entry(images[*idx].hash_value)
+                            .or_default()
+                            .push(*idx);
+                    }
+                }
+            }
+            placements.push(indices);
+        }
+
+        let mut slots: Vec<Option<ImageWrapper>> = images.drain(..).map(Some).collect();
+        let mut packers = vec![];
+        let mut dirty = vec![];
+        let mut metadata_changed = false;
+        for (page, indices) in self.pages.iter().zip(placements.into_iter()) {
+            let mut packer = Packer::new(page.width, page.height, pad);
+            packer.rotation = rotation;
+            let mut page_dirty = false;
+            for (sprite, idx) in page.sprites.iter().zip(indices.into_iter()) {
+                let image = slots[idx].take().expect("image placed twice");
+                page_dirty |= image.hash_value != sprite.hash;
+                metadata_changed |= image.frame_x != sprite.frame_x
+                    || image.frame_y != sprite.frame_y
+                    || image.frame_w != sprite.frame_w
+                    || image.frame_h != sprite.frame_h
+                    || image.pivot_x != sprite.pivot_x
+                    || image.pivot_y != sprite.pivot_y
+                    || image.user_data != sprite.data
+                    || image.aliases != sprite.aliases;
+
+                if unique && sprite.dup_id < 0 {
+                    packer
+                        .dup_lookup
+                        .insert(image.hash_value, packer.points.len());
+                }
+                packer.points.push(Point {
+                    x: sprite.x,
+                    y: sprite.y,
+                    dup_id: sprite.dup_id,
+                    rot: sprite.rot,
+                });
+                packer.images.push(image);
+            }
+            metadata_changed |= page_dirty;
+            packers.push(packer);
+            dirty.push(page_dirty);
+        }
+
+        Some(Reused {
+            packers,
+            dirty,
+            metadata_changed,
+        })
+    }
+}
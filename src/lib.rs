@@ -0,0 +1,46 @@
+//! A texture packer.
+//!
+//! impact loads images into [`ImageWrapper`](image_wrapper::ImageWrapper)s, packs them into
+//! pages with [`Packer`](packer::Packer), and describes the result with
+//! [`serial::Atlas`], which can be written out in any of the supported metadata formats.
+//!
+//! ```no_run
+//! use impact::bin_packs::max_rects::FreeRectChoiceHeuristic;
+//! use impact::image_wrapper::ImageWrapper;
+//! use impact::packer::Packer;
+//! use impact::serial::{Atlas, Texture};
+//!
+//! # fn main() -> impact::error::Result<()> {
+//! let image = image::open("sprite.png")?.to_rgba8();
+//! let mut images = vec![ImageWrapper::new(image, "sprite".into(), true, true, 0, 0)];
+//!
+//! let mut atlas = Atlas::default();
+//! while !images.is_empty() {
+//!     let mut packer = Packer::new(1024, 1024, 1);
+//!     let method = FreeRectChoiceHeuristic::RectBestShortSideFit;
+//!     packer.pack(&mut images, true, false, method, &mut || ());
+//!     if packer.images.is_empty() {
+//!         // The next image doesn't fit on an empty page
+//!         break;
+//!     }
+//!
+//!     let name = format!("atlas{}", atlas.textures.len());
+//!     packer.save_png(format!("{}.png", name))?;
+//!     atlas.textures.push(Texture::from_packer(name, &packer));
+//! }
+//! atlas.write_to_xml_file("atlas.xml", false)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod bin_packs;
+pub mod error;
+mod font;
+pub mod formats;
+pub mod hdr;
+pub mod image_wrapper;
+pub mod packer;
+pub mod rect;
+pub mod serial;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,27 +1,63 @@
 use metrohash::MetroHash;
+use rayon::prelude::*;
 use std::fs::metadata;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
-use structopt::clap::arg_enum;
+use std::time::Instant;
+use structopt::clap::{arg_enum, AppSettings};
 use structopt::StructOpt;
 
-mod bin_packs;
-mod error;
-mod image_wrapper;
-mod packer;
+/// Prints a line of a report meant for people, like `println!`, but to stderr while the atlas
+/// data is written to stdout with `--stdout`, so that it can still be piped
+macro_rules! reportln {
+    ($opt:expr, $($arg:tt)*) => {
+        if $opt.stdout.is_some() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod animation;
+mod archive;
+mod aseprite;
+mod blend;
+mod checksum;
+mod commands;
+mod config;
+mod exit;
+mod file_index;
+mod gif;
+mod image_cache;
+mod layout;
+mod naming;
+mod near_duplicates;
+mod options_hash;
+mod page_name;
 mod path_glob;
-mod rect;
-mod serial;
+mod progress;
+mod psd;
+mod reserve;
+mod scale;
+mod sidecar;
+mod slice;
+mod split;
+mod stats;
+mod tile;
+mod timings;
+mod walk;
+mod watch;
+
+use impact::formats::bundle::EntryKind;
+use impact::{bin_packs, error, hdr, image_wrapper, packer, rect, serial};
 
 use error::Result;
 use image_wrapper::ImageWrapper;
 use path_glob::Glob;
 
-// Trait for extending std::path::PathBuf
-use path_slash::PathBufExt;
-
 arg_enum! {
-    #[derive(Debug, Copy, Clone, Hash)]
+    #[derive(Debug, Copy, Clone, Hash, PartialEq)]
     enum FreeRectChoiceHeuristic {
         BestShortSideFit,
         BestLongSideFit,
@@ -31,6 +67,34 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    #[derive(Debug, Copy, Clone, Hash)]
+    enum LogFormat {
+        Text,
+        Json,
+    }
+}
+
+arg_enum! {
+    /// What to try again with when a page overflows, with `--retry-overflow`
+    #[derive(Debug, Copy, Clone, Hash, PartialEq)]
+    enum RetryOverflow {
+        Heuristics,
+        Rotation,
+        All,
+    }
+}
+
+arg_enum! {
+    /// Which pages are shrunk to fit their sprites after packing, with `--shrink`
+    #[derive(Debug, Copy, Clone, Hash, PartialEq)]
+    enum Shrink {
+        None,
+        Last,
+        All,
+    }
+}
+
 impl Into<bin_packs::max_rects::FreeRectChoiceHeuristic> for FreeRectChoiceHeuristic {
     fn into(self) -> bin_packs::max_rects::FreeRectChoiceHeuristic {
         match self {
@@ -53,72 +117,530 @@ impl Into<bin_packs::max_rects::FreeRectChoiceHeuristic> for FreeRectChoiceHeuri
     }
 }
 
+/// The atlas data formats, selected with `--format`
+#[derive(Debug, Copy, Clone, Hash, PartialEq)]
+enum Format {
+    Xml,
+    Json,
+    Binary,
+    Cbor,
+    FlatBuffers,
+    Proto,
+    BevyRon,
+    CreateJs,
+    SpriteKit,
+    Bundle,
+}
+
+impl Format {
+    const ALL: [Format; 10] = [
+        Format::Xml,
+        Format::Json,
+        Format::Binary,
+        Format::Cbor,
+        Format::FlatBuffers,
+        Format::Proto,
+        Format::BevyRon,
+        Format::CreateJs,
+        Format::SpriteKit,
+        Format::Bundle,
+    ];
+
+    fn variants() -> [&'static str; 10] {
+        [
+            "xml",
+            "json",
+            "bin",
+            "cbor",
+            "flatbuffers",
+            "proto",
+            "bevy-ron",
+            "createjs",
+            "spritekit",
+            "bundle",
+        ]
+    }
+
+    /// The name of the format, as given to `--format`
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Xml => "xml",
+            Format::Json => "json",
+            Format::Binary => "bin",
+            Format::Cbor => "cbor",
+            Format::FlatBuffers => "flatbuffers",
+            Format::Proto => "proto",
+            Format::BevyRon => "bevy-ron",
+            Format::CreateJs => "createjs",
+            Format::SpriteKit => "spritekit",
+            Format::Bundle => "bundle",
+        }
+    }
+
+    /// The file extension used when saving atlas data in this format
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Xml => "xml",
+            Format::Json => "json",
+            Format::Binary => "bin",
+            Format::Cbor => "cbor",
+            Format::FlatBuffers => "fb",
+            Format::Proto => "pb",
+            Format::BevyRon => "atlas.ron",
+            Format::CreateJs => "createjs.json",
+            Format::SpriteKit => "atlasc",
+            Format::Bundle => "impact",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match &*s.to_ascii_lowercase() {
+            "xml" => Ok(Format::Xml),
+            "json" => Ok(Format::Json),
+            "bin" => Ok(Format::Binary),
+            "cbor" => Ok(Format::Cbor),
+            "flatbuffers" => Ok(Format::FlatBuffers),
+            "proto" => Ok(Format::Proto),
+            "bevy-ron" => Ok(Format::BevyRon),
+            "createjs" => Ok(Format::CreateJs),
+            "spritekit" => Ok(Format::SpriteKit),
+            "bundle" => Ok(Format::Bundle),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// The image formats atlas pages can be saved in
+#[cfg(not(feature = "avif"))]
+const PAGE_EXTENSIONS: &[&str] = &[
+    "ico", "jpg", "jpeg", "png", "pbm", "pgm", "ppm", "pam", "bmp", "tif", "tiff", "tga", "qoi",
+    "webp", "exr",
+];
+#[cfg(feature = "avif")]
+const PAGE_EXTENSIONS: &[&str] = &[
+    "ico", "jpg", "jpeg", "png", "pbm", "pgm", "ppm", "pam", "bmp", "tif", "tiff", "tga", "qoi",
+    "webp", "exr", "avif",
+];
+
+/// The smallest and largest page sizes that can be given with `--size`
+const MIN_SIZE: u16 = 64;
+const MAX_SIZE: u16 = 16384;
+
 /// A texture packer
-#[derive(StructOpt, Debug, Hash)]
-#[structopt(name = "impact")]
+#[derive(StructOpt, Debug, Clone, Hash)]
+#[structopt(name = "impact", setting = AppSettings::SubcommandsNegateReqs)]
 struct Opt {
+    /// Load options from a config file (options given on the command line take precedence)
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
     /// Use default settings (-x -p -t -u)
     #[structopt(short, long)]
     default: bool,
 
-    /// Saves the atlas data as a .xml file
+    /// Saves the atlas data as a .xml file (the same as `--format xml`)
     #[structopt(short, long)]
     xml: bool,
 
-    /// Saves the atlas data as a .bin file
+    /// Saves the atlas data as a .bin file (the same as `--format bin`)
     #[structopt(short, long)]
     binary: bool,
 
-    /// Saves the atlas data as a .json file
+    /// Saves the atlas data as a .json file (the same as `--format json`)
     #[structopt(short, long)]
     json: bool,
 
+    /// The formats to save the atlas data as, separated by commas (e.g. `--format xml,json`, can
+    /// be repeated)
+    #[structopt(long, require_delimiter = true, possible_values = &Format::variants(), case_insensitive = true)]
+    format: Vec<Format>,
+
+    /// Write the atlas data to stdout in this format, instead of to a file (the pages are still
+    /// saved)
+    #[structopt(long, possible_values = &Format::variants(), case_insensitive = true)]
+    stdout: Option<Format>,
+
+    /// Use unabbreviated keys (`name`, `width`, `frame_x`...) in the .xml and .json files
+    #[structopt(long)]
+    long_keys: bool,
+
+    /// Add a `meta` section to the .json, .cbor and .xml files with the version of impact and
+    /// the settings the atlas was packed with
+    #[structopt(long)]
+    meta: bool,
+
+    /// Record the BLAKE3 hash of every page image in the atlas data, so that runtimes and
+    /// `impact verify` can tell when a page doesn't match
+    #[structopt(long)]
+    checksums: bool,
+
+    /// Record the path, modification time and hash of the file every sprite was read from in a
+    /// `sources` section of the atlas data
+    #[structopt(long)]
+    sources: bool,
+
+    /// Give every sprite an index in the atlas data that stays the same from one run to the
+    /// next, as sprites are added and removed
+    #[structopt(long)]
+    stable_indices: bool,
+
     /// Premultiplies the pixels of the bitmaps by their alpha channel
     #[structopt(short, long)]
     premultiply: bool,
+    /// Saves every page twice from the same layout: with straight alpha, and premultiplied as
+    /// `<page>.pm.<extension>`. Takes over from --premultiply.
+    #[structopt(long)]
+    alpha_variants: bool,
     /// Trims excess transparency off the bitmaps
     #[structopt(short, long)]
     trim: bool,
 
+    /// Trims excess transparency off the bitmaps like --trim, but records the trimmed size as
+    /// their full size, without any offset
+    #[structopt(long)]
+    crop: bool,
+
+    /// Keep up to this many transparent pixels around the visible ones when trimming, for
+    /// shaders that sample slightly outside sprites
+    #[structopt(long, default_value = "0")]
+    trim_margin: u8,
+
     /// Print to the debug console as the packer works
     #[structopt(short, long, parse(from_occurrences))]
     verbose: u8,
 
+    /// Only print errors, and hide progress bars
+    #[structopt(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// The file to write the full log to
+    #[structopt(long, default_value = "impact.log", parse(from_os_str))]
+    log_file: PathBuf,
+
+    /// Don't write a log file
+    #[structopt(long, conflicts_with = "log-file")]
+    no_log_file: bool,
+
+    /// The format of log messages, for both stderr and the log file
+    #[structopt(long, possible_values = &LogFormat::variants(), default_value = "Text", case_insensitive = true)]
+    log_format: LogFormat,
+
     /// Ignore caching, forcing the packer to repack
     #[structopt(short, long)]
     force: bool,
 
+    /// Read and hash every input file to check whether the atlas is up to date, instead of
+    /// trusting the size and modification time of files that were already hashed
+    #[structopt(long)]
+    paranoid: bool,
+
+    /// The directory to keep the cache in, used to skip or speed up repacking
+    #[structopt(long, default_value = ".impact-cache", parse(from_os_str))]
+    cache_dir: PathBuf,
+
+    /// Load and pack the images, reporting the resulting pages without writing any files
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Print the page, position, rotation and trim of every image once packed
+    #[structopt(long)]
+    print_plan: bool,
+
+    /// Also write a map of how every page is used, showing the sprites, their padding and the
+    /// free space left (`<page>.debug.png`)
+    #[structopt(long)]
+    debug_image: bool,
+
+    /// Also write a copy of every page with each sprite outlined and labelled with its name
+    /// (`<page>.outlines.png`)
+    #[structopt(long)]
+    debug_outlines: bool,
+
+    /// Print the occupancy of every page, the space saved by trimming and removing duplicates,
+    /// and the space left unused once packed
+    #[structopt(long)]
+    stats: bool,
+
+    /// Write the packing statistics to this JSON file
+    #[structopt(long, parse(from_os_str))]
+    stats_file: Option<PathBuf>,
+
+    /// Write a JSON report to this file with the packing statistics, and the size of the input
+    /// files and of every page and atlas data file written
+    #[structopt(long, parse(from_os_str))]
+    report: Option<PathBuf>,
+
+    /// Write the pages and atlas data into this zip archive instead of as loose files
+    #[structopt(long, parse(from_os_str))]
+    archive: Option<PathBuf>,
+
+    /// Print every pair of sprites whose pixels are at least this similar, in percent, with
+    /// pixels matching if they're within --unique-fuzzy of each other
+    #[structopt(long)]
+    near_duplicates: Option<u8>,
+
+    /// Print how long each phase of packing took, as text or JSON (they're always logged with
+    /// `-v`)
+    #[structopt(long, possible_values = &LogFormat::variants(), case_insensitive = true)]
+    timings: Option<LogFormat>,
+
+    /// Exit with an error, without writing anything, if the atlas is out of date
+    #[structopt(long, conflicts_with_all = &["force", "dry-run", "watch"])]
+    check: bool,
+
+    /// Keep running, repacking whenever the inputs change
+    #[structopt(short, long)]
+    watch: bool,
+
     /// Remove duplicate bitmaps from the atlas
     #[structopt(short, long)]
     unique: bool,
 
-    /// Enables rotating bitmaps 90 degrees clockwise when packing
+    /// Also treat bitmaps as duplicates when none of their channels differ by more than this,
+    /// e.g. re-exports with a little noise (implies --unique)
+    #[structopt(long)]
+    unique_fuzzy: Option<u8>,
+
+    /// List the names of duplicates removed by --unique as aliases of the sprite they share a
+    /// rectangle with, instead of as sprites of their own
+    #[structopt(long)]
+    aliases: bool,
+
+    /// Enables rotating bitmaps 90 degrees (clockwise, unless changed with --rotation) when
+    /// packing
     #[structopt(short, long)]
     rotate: bool,
 
-    /// Max atlas size
-    #[structopt(short, long, default_value = "4096", possible_values = &["64", "128", "256", "512", "1024", "2048", "4096"])]
+    /// Which way to rotate bitmaps with --rotate, to match what the engine expects
+    #[structopt(long, default_value = "cw", possible_values = &["cw", "ccw"], case_insensitive = true)]
+    rotation: packer::Rotation,
+
+    /// How many pixels better a rotated placement has to fit than an unrotated one to be
+    /// chosen with --rotate, to rotate fewer bitmaps
+    #[structopt(long, default_value = "0")]
+    rotation_penalty: u16,
+
+    /// Max atlas size, from 64 to 16384
+    #[structopt(short, long, default_value = "4096")]
     size: u16,
 
     /// Padding between images (can be from 0 to 16)
     #[structopt(short = "P", long, default_value = "1")]
     pad: u8,
 
+    /// What to fill the padding after every image with: `transparent`, `clamp` (its nearest
+    /// edge pixels) or a color such as `#ff00ff`
+    #[structopt(long, default_value = "transparent")]
+    pad_fill: packer::PadFill,
+
+    /// Repeat the edge pixels of images this many times around them, so that sampling at their
+    /// edges doesn't bleed into their neighbours (can be from 0 to 16)
+    #[structopt(long, default_value = "0")]
+    extrude: u8,
+
+    /// Only place images (along with their extruded edges) at coordinates that are multiples of
+    /// this, for block-compressed textures or mipmapped pages
+    #[structopt(long, default_value = "1")]
+    align: u16,
+
+    /// Leave an area of every page empty, e.g. for the engine to draw into at runtime, given as
+    /// `X,Y,WxH`, or `N:X,Y,WxH` to only leave it on page N (can be repeated)
+    #[structopt(long, number_of_values = 1)]
+    reserve: Vec<reserve::Reserve>,
+
+    /// Resize images by this factor as they're loaded
+    #[structopt(long, default_value = "1")]
+    scale: scale::Scale,
+
+    /// Resize images whose file names end in `@2x` (or `@3x`, `@1.5x`...) down to 1x, removing the
+    /// suffix from their sprite names
+    #[structopt(long)]
+    input_scale_suffix: bool,
+
+    /// Pack every sprite whose name, or one of its folders, matches this pattern into the same
+    /// page (can be repeated)
+    #[structopt(long, number_of_values = 1, parse(try_from_str = glob::Pattern::new))]
+    keep_together: Vec<glob::Pattern>,
+
+    /// Pack sprites whose name, or one of their folders, matches this pattern before any others,
+    /// so that they end up on the first page (can be repeated, earlier patterns go first)
+    #[structopt(long, number_of_values = 1, parse(try_from_str = glob::Pattern::new))]
+    priority: Vec<glob::Pattern>,
+
     /// The image-packing heuristic to use
     #[structopt(short, long, possible_values = &FreeRectChoiceHeuristic::variants(), default_value = "BestShortSideFit", case_insensitive = true)]
     heuristic: FreeRectChoiceHeuristic,
 
+    /// When a page overflows, pack it again with the other heuristics, with rotation (even without
+    /// --rotate), or with both, keeping whichever fits the most sprites
+    #[structopt(long, possible_values = &RetryOverflow::variants(), case_insensitive = true)]
+    retry_overflow: Option<RetryOverflow>,
+
+    /// Which pages are halved in size for as long as their sprites still fit: all of them, only
+    /// the last one (e.g. for texture arrays), or none
+    #[structopt(long, default_value = "All", possible_values = &Shrink::variants(), case_insensitive = true)]
+    shrink: Shrink,
+
+    /// Never shrink pages, so that every one of them is exactly --size (the same as `--shrink
+    /// none`)
+    #[structopt(long)]
+    fixed_size: bool,
+
+    /// Round the width and height of every page up to a power of two, for a --size that isn't
+    /// one
+    #[structopt(long)]
+    pot: bool,
+
+    /// Round the width and height of every page up to a multiple of this
+    #[structopt(long)]
+    multiple_of: Option<u16>,
+
+    /// Split images too large for a page into tiles that fit, instead of failing
+    #[structopt(long)]
+    tile_oversized: bool,
+
+    /// Skip images (with a warning) whose width and height are both smaller than this, such as
+    /// placeholders
+    #[structopt(long)]
+    min_sprite_size: Option<u16>,
+
+    /// Skip images (with a warning) whose width or height is larger than this, such as
+    /// accidentally exported masters
+    #[structopt(long)]
+    max_sprite_size: Option<u16>,
+
+    /// Skip (with a warning) input images that can't be read or decoded, listing them at the
+    /// end, instead of failing
+    #[structopt(long, conflicts_with = "fail-fast")]
+    skip_bad_inputs: bool,
+
+    /// Fail as soon as an input image can't be read or decoded (the default), even if the
+    /// config file asks to skip bad inputs
+    #[structopt(long)]
+    fail_fast: bool,
+
     /// The image format to use when saving atlas images
-    #[structopt(short, long, default_value = "png", possible_values = &["ico", "jpg", "jpeg", "png", "pbm", "pgm", "ppm", "pam", "bmp", "tif", "tiff"], case_insensitive = true)]
+    #[structopt(short, long, default_value = "png", possible_values = PAGE_EXTENSIONS, case_insensitive = true)]
     extension: String,
 
+    /// The quality of WebP and AVIF pages (can be from 0 to 100). WebP pages are lossless without
+    /// it, and AVIF pages near-lossless.
+    #[structopt(long)]
+    quality: Option<u8>,
+
+    /// How the colors of HDR images (`.hdr`, `.exr`) are brought down to 8 bits. With
+    /// `--extension exr`, pages keep their full range instead.
+    #[structopt(long, default_value = "clamp", possible_values = &["clamp", "reinhard", "aces"], case_insensitive = true)]
+    tonemap: hdr::Tonemap,
+
+    /// The naming scheme for atlas pages, using the `{name}` and `{index}` (or `{index:02}`) placeholders
+    #[structopt(long, default_value = "{name}{index}")]
+    page_name: page_name::PageName,
+
+    /// Group numbered sprites (`run_001`, `run_002`...) into animations in the atlas data
+    #[structopt(long)]
+    animations: bool,
+
+    /// The suffix that numbers animation frames; its first capture group is the frame number
+    #[structopt(long, default_value = r"_(\d+)$")]
+    animation_suffix: animation::SuffixPattern,
+
+    /// Make an animation of every leaf input folder, with its images as frames in natural order
+    #[structopt(long)]
+    animations_from_folders: bool,
+
+    /// Pack every visible layer of Aseprite files as its own sprite, instead of flattening them
+    #[structopt(long)]
+    aseprite_layers: bool,
+
+    /// Make an animation of every tag in Aseprite files
+    #[structopt(long)]
+    aseprite_tags: bool,
+
+    /// Make an animation of the frames of every animated GIF
+    #[structopt(long)]
+    gif_animations: bool,
+
+    /// Pack every visible top-level layer (or group) of PSD files as its own sprite, instead of
+    /// the flattened image
+    #[structopt(long)]
+    psd_layers: bool,
+
+    /// Cut an existing spritesheet into a grid of cells of the given size, packed as sprites
+    /// `name_0`, `name_1`... (e.g. `sheet.png:32x32`, can be repeated)
+    #[structopt(long, number_of_values = 1)]
+    slice: Vec<slice::Slice>,
+
+    /// Cut a horizontal strip of animation frames into this many sprites `name_0`, `name_1`...,
+    /// and make an animation of them named after the file (e.g. `walk.png:8`, can be repeated)
+    #[structopt(long, number_of_values = 1)]
+    strip: Vec<slice::Strip>,
+
+    /// Name sprites after their file stem only, without the folders they're in
+    #[structopt(long)]
+    flatten: bool,
+
+    /// Name sprites after their path relative to this folder, however the inputs were given
+    #[structopt(long, parse(from_os_str))]
+    basepath: Option<PathBuf>,
+
+    /// Remove this leading path from sprite names
+    #[structopt(long, parse(from_os_str))]
+    strip_prefix: Option<PathBuf>,
+
+    /// Rename sprites whose name is already taken by another file (`idle-2`, `idle-3`...),
+    /// instead of failing
+    #[structopt(long)]
+    rename_duplicates: bool,
+
+    /// The separator between the folders in sprite names
+    #[structopt(long, default_value = "/")]
+    name_separator: String,
+
+    /// Remove leading numbers used for ordering (`01_`, `2-`...) from every part of sprite names
+    #[structopt(long)]
+    trim_numbers: bool,
+
     /// File to output
-    #[structopt(name = "OUTPUT", parse(from_os_str))]
-    output: PathBuf,
+    #[structopt(name = "OUTPUT", parse(from_os_str), required_unless = "config")]
+    output: Option<PathBuf>,
+
+    /// Only pack files in input folders whose names match one of these patterns (can be repeated)
+    #[structopt(long, number_of_values = 1, parse(try_from_str = glob::Pattern::new))]
+    include: Vec<glob::Pattern>,
+
+    /// Follow symlinks inside input folders (they're skipped otherwise)
+    #[structopt(long)]
+    follow_symlinks: bool,
+
+    /// Pack hidden files and folders inside input folders (dotfiles, `Thumbs.db`, editor
+    /// backups...), which are skipped otherwise
+    #[structopt(long)]
+    hidden: bool,
+
+    /// How many levels of input folders to descend into (1 only packs the files directly inside)
+    #[structopt(long)]
+    max_depth: Option<usize>,
+
+    /// Pack every folder directly inside the input folders into its own atlas, named after the
+    /// folder and saved next to OUTPUT, which only gets the files outside of them
+    #[structopt(long)]
+    split_by_folder: bool,
 
     /// Files or folders to process
     #[structopt(name = "INPUTS", parse(from_os_str))]
     inputs: Vec<PathBuf>,
+
+    /// Per-folder setting overrides, loaded from the config file
+    #[structopt(skip)]
+    folders: Vec<config::FolderOverride>,
+
+    #[structopt(subcommand)]
+    cmd: Option<commands::Command>,
 }
 
 /// Use the available extensions in the `image` crate to determine if a file extension
@@ -139,242 +661,1300 @@ fn is_image_file<P: AsRef<std::path::Path>>(path: P) -> bool {
         "pam" => true,
         "bmp" => true,
         "tif" | "tiff" => true,
-        _ => false,
+        "tga" => true,
+        "qoi" => true,
+        "hdr" | "exr" => true,
+        "gif" => true,
+        _ => aseprite::is_aseprite_file(p) || psd::is_psd_file(p),
     }
 }
 
-fn hash_files(path: &PathBuf, hasher: &mut dyn std::hash::Hasher) -> Result<()> {
-    let dir_iter = std::fs::read_dir(path)?;
-    for dir in dir_iter {
-        let dir = dir?;
-        if dir.metadata()?.is_dir() {
-            hash_files(&dir.path(), hasher)?;
-        } else {
-            hash_file(&dir.path(), hasher)?;
+/// The hashes of the input files already read during a run, so that files shared by several
+/// atlases are only read once, along with the index of the files hashed by earlier runs in every
+/// cache directory
+#[derive(Default)]
+struct FileHashes {
+    hashes: std::collections::HashMap<PathBuf, u64>,
+    indices: std::collections::HashMap<PathBuf, file_index::FileIndex>,
+}
+
+impl FileHashes {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// The hash of the file at `path`, if it was hashed during this run
+    fn get(&self, path: &std::path::Path) -> Option<&u64> {
+        self.hashes.get(path)
+    }
+
+    /// Hashes the contents of the file at `path` and of its sidecar file, unless the index in
+    /// the cache directory shows that neither changed since they were last hashed
+    fn hash(&mut self, path: &std::path::Path, opt: &Opt) -> Result<u64> {
+        if let Some(hash) = self.hashes.get(path) {
+            return Ok(*hash);
         }
+        let index = self
+            .indices
+            .entry(opt.cache_dir.clone())
+            .or_insert_with(|| file_index::FileIndex::load(&opt.cache_dir));
+
+        // The stamps are taken before reading, so that a file changed while it's being read is
+        // hashed again next time
+        let stamps = file_index::Stamps::of(path);
+        let indexed = stamps
+            .filter(|_| !opt.paranoid)
+            .and_then(|stamps| index.lookup(path, stamps));
+        let hash = match indexed {
+            Some(hash) => hash,
+            None => {
+                let mut file_hasher = MetroHash::default();
+                file_hasher.write(&std::fs::read(path)?);
+                if let Some(sidecar) = sidecar::path(path) {
+                    file_hasher.write(&std::fs::read(sidecar)?);
+                }
+                let hash = file_hasher.finish();
+                if let Some(stamps) = stamps {
+                    index.insert(path, stamps, hash);
+                }
+                hash
+            }
+        };
+        self.hashes.insert(path.to_path_buf(), hash);
+        Ok(hash)
     }
-    Ok(())
+
+    /// Saves the index of the cache directory of `opt`, with every file hashed for it so far
+    fn save_index(&self, opt: &Opt) -> Result<()> {
+        match self.indices.get(&opt.cache_dir) {
+            Some(index) => index.save(&opt.cache_dir),
+            None => Ok(()),
+        }
+    }
+}
+
+fn hash_files(
+    path: &PathBuf,
+    hasher: &mut dyn std::hash::Hasher,
+    opt: &Opt,
+    hashes: &mut FileHashes,
+) -> Result<()> {
+    walk::walk_dir(path, opt, &mut |file| {
+        hash_file(path, file, hasher, opt, hashes)
+    })
 }
 
-fn hash_file(path: &PathBuf, hasher: &mut dyn std::hash::Hasher) -> Result<()> {
+/// Hashes the image at `path` along with its path relative to `root`, since renaming a file
+/// changes its sprite name or its flags (such as `~notrim`) without changing its contents
+fn hash_file(
+    root: &std::path::Path,
+    path: &std::path::Path,
+    hasher: &mut dyn std::hash::Hasher,
+    opt: &Opt,
+    hashes: &mut FileHashes,
+) -> Result<()> {
     if is_image_file(path) {
-        let bytes = std::fs::read(path)?;
-        hasher.write(&bytes);
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        hasher.write(relative.to_string_lossy().as_bytes());
+        hasher.write_u8(0);
+        hasher.write_u64(hashes.hash(path, opt)?);
     }
     Ok(())
 }
 
+/// The sprites loaded from a single input file
+#[derive(Default)]
+struct Loaded {
+    images: Vec<ImageWrapper>,
+    /// Animations defined by the file itself, e.g. Aseprite tags
+    animations: Vec<serial::Animation>,
+}
+
+/// Loads the sprites in the file at `path`, reusing the bitmaps cached for it if its hash in
+/// `hashes` hasn't changed
 fn load_image<P: AsRef<std::path::Path>>(
     path: P,
-    images: &mut Vec<ImageWrapper>,
     opt: &Opt,
-) -> Result<()> {
+    hashes: &FileHashes,
+) -> Result<Loaded> {
     if is_image_file(&path) {
-        log::info!("Reading file {}", path.as_ref().to_string_lossy());
+        let flags = naming::flags(path.as_ref());
+        if flags.nopack {
+            log::info!(
+                "File {} is copied as is, skipping...",
+                path.as_ref().to_string_lossy()
+            );
+            return Ok(Loaded::default());
+        }
+        let mut settings = config::ImageSettings::for_path(path.as_ref(), opt)?;
+        if flags.notrim {
+            settings.trim = false;
+        }
         let size = std::fs::metadata(path.as_ref())?.len();
-        let img = image::open(path.as_ref().clone())?.to_rgba8();
-        let mut given_path = path.as_ref().to_path_buf();
-        given_path.pop();
-        given_path.push(path.as_ref().file_stem().unwrap());
-        let img = ImageWrapper::new(
-            img,
-            given_path.to_slash().unwrap().into_owned(),
-            opt.premultiply,
-            opt.trim,
-            size,
-        );
-        images.push(img);
+        let name = naming::sprite_name(path.as_ref(), opt);
+
+        let key = hashes
+            .get(path.as_ref())
+            .map(|hash| image_cache::key(*hash, &name, &settings, opt));
+        let cached = key
+            .filter(|_| !opt.force)
+            .and_then(|key| image_cache::load(opt, key));
+        let mut loaded = match cached {
+            Some(loaded) => {
+                log::info!("Using cached file {}", path.as_ref().to_string_lossy());
+                loaded
+            }
+            None => {
+                log::info!("Reading file {}", path.as_ref().to_string_lossy());
+                let loaded = decode_image(path.as_ref(), name, &settings, opt)?;
+                if let Some(key) = key.filter(|_| !opt.dry_run) {
+                    image_cache::save(opt, key, &loaded)?;
+                }
+                loaded
+            }
+        };
+
+        let sidecar = match sidecar::path(path.as_ref()) {
+            Some(sidecar) => {
+                log::info!("Reading sidecar {}", sidecar.to_string_lossy());
+                Some(sidecar::Sidecar::load(&sidecar)?)
+            }
+            None => None,
+        };
+        for (idx, img) in loaded.images.iter_mut().enumerate() {
+            // The size of the file is only counted once, however many sprites it holds
+            img.original_size = if idx == 0 { size } else { 0 };
+            img.pad = settings.pad;
+            img.extrude = settings.extrude;
+            if let Some(sidecar) = &sidecar {
+                sidecar.apply(img);
+                // Pivots are given in pixels of the image file
+                img.pivot_x *= settings.scale.factor();
+                img.pivot_y *= settings.scale.factor();
+                if opt.crop {
+                    // Keep pointing at the same pixel once the offset is dropped
+                    img.pivot_x += img.frame_x as f32;
+                    img.pivot_y += img.frame_y as f32;
+                }
+            }
+            if opt.crop {
+                img.crop();
+            }
+            img.priority = img.priority.max(pattern_priority(opt, &img.name));
+        }
+        if opt.sources {
+            let source = source(path.as_ref())?;
+            for img in loaded.images.iter_mut() {
+                img.source = Some(serial::Source {
+                    name: img.name.clone(),
+                    ..source.clone()
+                });
+            }
+        }
+        let aliases = naming::alias_names(path.as_ref(), opt);
+        match loaded.images.as_mut_slice() {
+            _ if aliases.is_empty() => {}
+            [image] => image.aliases = aliases,
+            images => log::warn!(
+                "{} holds {} sprites, ignoring its aliases",
+                path.as_ref().to_string_lossy(),
+                images.len()
+            ),
+        }
+        Ok(loaded)
     } else {
         log::info!(
             "File {} is not an image, skipping...",
             path.as_ref().to_string_lossy()
         );
+        Ok(Loaded::default())
     }
-    Ok(())
 }
 
-fn load_images<P: AsRef<std::path::Path>>(
-    path: P,
-    images: &mut Vec<ImageWrapper>,
+/// The path, modification time and hash of the file at `path`, for `--sources`
+fn source(path: &std::path::Path) -> Result<serial::Source> {
+    use path_slash::PathExt;
+    let modified = std::fs::metadata(path)?
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    Ok(serial::Source {
+        name: String::new(),
+        path: path.to_slash_lossy().into_owned(),
+        modified,
+        hash: checksum::of_file(path)?,
+    })
+}
+
+/// Decodes the image file at `path` into trimmed bitmaps, along with the animations it defines
+fn decode_image(
+    path: &std::path::Path,
+    name: String,
+    settings: &config::ImageSettings,
     opt: &Opt,
-) -> Result<()> {
-    let dir_iter = std::fs::read_dir(&path)?;
-    log::info!("Reading directory {}", path.as_ref().to_string_lossy());
-    for dir in dir_iter {
-        let dir = dir?;
-        if dir.metadata()?.is_dir() {
-            load_images(&dir.path(), images, opt)?;
+) -> Result<Loaded> {
+    let mut loaded = Loaded::default();
+    // The linear colors of HDR images, kept for float pages
+    let mut hdr_image = None;
+    let sprites = if aseprite::is_aseprite_file(path) {
+        let document = aseprite::Aseprite::load(path)?;
+        if opt.aseprite_tags {
+            loaded.animations = document.animations(&name, opt.aseprite_layers);
+        }
+        document.sprites(&name, opt.aseprite_layers)
+    } else if gif::is_gif_file(path) {
+        let frames = gif::frames(path, &name)?;
+        if opt.gif_animations && frames.len() > 1 {
+            loaded.animations.push(serial::Animation {
+                name,
+                frames: frames.iter().map(|(name, _)| name.clone()).collect(),
+            });
+        }
+        frames
+    } else if psd::is_psd_file(path) {
+        psd::Psd::load(path)?.sprites(&name, opt.psd_layers)
+    } else if hdr::is_hdr_file(path) {
+        let image = hdr::load(path)?;
+        let tonemapped = opt.tonemap.apply(&image);
+        if opt.extension.eq_ignore_ascii_case("exr") {
+            hdr_image = Some(image);
+        }
+        vec![(name, tonemapped)]
+    } else {
+        vec![(name, image::open(path)?.to_rgba8())]
+    };
+    let sprites = match opt.slice.iter().find(|slice| slice.matches(path)) {
+        Some(slice) => {
+            if hdr_image.take().is_some() {
+                log::warn!("sliced HDR images are only packed tonemapped");
+            }
+            sprites
+                .iter()
+                .flat_map(|(name, image)| slice.apply(image, name))
+                .collect()
+        }
+        None => sprites,
+    };
+    let sprites = match opt.strip.iter().find(|strip| strip.matches(path)) {
+        Some(strip) => {
+            if hdr_image.take().is_some() {
+                log::warn!("HDR strips are only packed tonemapped");
+            }
+            let mut frames = vec![];
+            for (name, image) in &sprites {
+                let cells = strip.slice(image).apply(image, name);
+                loaded.animations.push(serial::Animation {
+                    name: name.clone(),
+                    frames: cells.iter().map(|(name, _)| name.clone()).collect(),
+                });
+                frames.extend(cells);
+            }
+            frames
+        }
+        None => sprites,
+    };
+
+    // Files such as animations can hold many sprites, so trim and hash them in parallel
+    loaded.images = sprites
+        .into_par_iter()
+        .map(|(name, img)| {
+            let img = settings.scale.apply(img);
+            let img = timings::time(timings::Phase::Trimming, || {
+                ImageWrapper::new(
+                    img,
+                    name,
+                    settings.premultiply,
+                    settings.trim,
+                    opt.trim_margin as i32,
+                    0,
+                )
+            });
+            match &hdr_image {
+                Some(hdr_image) => {
+                    let hdr_image = settings.scale.apply(hdr_image.clone());
+                    img.with_hdr(&hdr_image, settings.premultiply)
+                }
+                None => img,
+            }
+        })
+        .collect();
+    Ok(loaded)
+}
+
+/// Lists every file to load from the input files and directories
+fn collect_files(opt: &Opt) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for input in &opt.inputs {
+        let md = metadata(input)?;
+        if md.is_dir() {
+            log::info!("Reading directory {}", input.to_string_lossy());
+            walk::walk_dir(input, opt, &mut |file| {
+                files.push(file.to_path_buf());
+                Ok(())
+            })?;
         } else {
-            load_image(&dir.path(), images, opt)?;
+            files.push(input.clone());
         }
     }
-    Ok(())
+    Ok(files)
 }
 
-fn main() -> Result<()> {
-    let mut opt = Opt::from_args();
+/// Loads the bitmaps from all the input files and directories, along with the animations they
+/// define
+fn load_inputs(
+    opt: &Opt,
+    hashes: &FileHashes,
+) -> Result<(Vec<ImageWrapper>, Vec<serial::Animation>)> {
+    log::info!("loading images...");
+    let files = collect_files(opt)?;
+    let progress = progress::bar(opt, files.len(), "loading");
+    let loaded = files
+        .par_iter()
+        .map(|file| {
+            let loaded = match load_image(file, opt, hashes) {
+                Err(err) if opt.skip_bad_inputs => {
+                    log::warn!("skipping {}: {}", file.display(), err);
+                    exit::skip_input(file, &err);
+                    Ok(Loaded::default())
+                }
+                loaded => loaded,
+            };
+            progress.inc(1);
+            loaded
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut images: Vec<ImageWrapper> = vec![];
+    let mut animations = vec![];
+    // The file every sprite name was first taken by
+    let mut sources: std::collections::HashMap<String, &PathBuf> = Default::default();
+    for (path, mut file) in files.iter().zip(loaded) {
+        for image in file.images.iter_mut() {
+            if let Some(first) = sources.get(&image.name) {
+                if !opt.rename_duplicates {
+                    log::error!(
+                        "sprite {} comes from both {} and {}",
+                        image.name,
+                        first.display(),
+                        path.display()
+                    );
+                    return Err(error::ImpactError::DuplicateName {
+                        name: image.name.clone(),
+                        first: first.display().to_string(),
+                        second: path.display().to_string(),
+                    });
+                }
+                let name = naming::free_name(&image.name, |name| sources.contains_key(name));
+                log::warn!(
+                    "sprite {} from {} is already taken by {}, renaming it {}",
+                    image.name,
+                    path.display(),
+                    first.display(),
+                    name
+                );
+                for frame in file
+                    .animations
+                    .iter_mut()
+                    .flat_map(|animation| animation.frames.iter_mut())
+                    .filter(|frame| **frame == image.name)
+                {
+                    *frame = name.clone();
+                }
+                image.name = name;
+            }
+            sources.insert(image.name.clone(), path);
+            for alias in &image.aliases {
+                if let Some(first) = sources.get(alias) {
+                    log::error!(
+                        "sprite {} comes from both {} and an alias in {}",
+                        alias,
+                        first.display(),
+                        path.display()
+                    );
+                    return Err(error::ImpactError::DuplicateName {
+                        name: alias.clone(),
+                        first: first.display().to_string(),
+                        second: path.display().to_string(),
+                    });
+                }
+                sources.insert(alias.clone(), path);
+            }
+        }
+        images.extend(file.images);
+        animations.extend(file.animations);
+    }
+    progress.finish_and_clear();
+
+    let (images, skipped): (Vec<_>, Vec<_>) = images
+        .into_iter()
+        .partition(|image| within_size_limits(opt, image));
+    if !skipped.is_empty() {
+        let skipped: std::collections::HashSet<_> =
+            skipped.into_iter().map(|image| image.name).collect();
+        for animation in animations.iter_mut() {
+            animation.frames.retain(|frame| !skipped.contains(frame));
+        }
+    }
+    let images = if opt.tile_oversized {
+        tile::split_oversized(opt, images)
+    } else {
+        images
+    };
+    log::info!("loaded {} images.", images.len());
+    Ok((images, animations))
+}
 
-    if opt.default {
-        opt.xml = true;
-        opt.premultiply = true;
-        opt.trim = true;
-        opt.unique = true;
+/// Whether the untrimmed size of `image` is within `--min-sprite-size` and
+/// `--max-sprite-size`, warning if it isn't
+fn within_size_limits(opt: &Opt, image: &ImageWrapper) -> bool {
+    let largest = std::cmp::max(image.frame_w, image.frame_h);
+    if let Some(min) = opt.min_sprite_size.filter(|&min| largest < i32::from(min)) {
+        log::warn!(
+            "skipping {}, {}x{} is smaller than --min-sprite-size {}",
+            image.name,
+            image.frame_w,
+            image.frame_h,
+            min
+        );
+        return false;
+    }
+    if let Some(max) = opt.max_sprite_size.filter(|&max| largest > i32::from(max)) {
+        log::warn!(
+            "skipping {}, {}x{} is larger than --max-sprite-size {}",
+            image.name,
+            image.frame_w,
+            image.frame_h,
+            max
+        );
+        return false;
     }
+    true
+}
+
+fn main() {
+    let (code, failed) = match try_main() {
+        Ok(code) => (code, false),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            (exit::for_error(&err), true)
+        }
+    };
+    exit::print_summary(failed);
+    std::process::exit(code);
+}
+
+/// Runs the command line, returning the exit code of a successful run
+fn try_main() -> Result<i32> {
+    let matches = Opt::clap().get_matches();
+    let (mut opt, atlases) = load_options(&matches)?;
 
     let log_level = match opt.verbose {
+        _ if opt.quiet => log::LevelFilter::Error,
         0 => log::LevelFilter::Warn,
         1 => log::LevelFilter::Info,
         2 => log::LevelFilter::Debug,
         _ => log::LevelFilter::Trace,
     };
 
-    let file_config = fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
-        .chain(fern::log_file("impact.log")?)
-        .level(log::LevelFilter::Trace);
-
     let stderr_config = fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
+        .format(log_formatter(opt.log_format))
         .level(log_level)
-        .chain(std::io::stderr());
+        .chain(std::io::stderr())
+        .chain(fern::Output::call(exit::count));
     
-    fern::Dispatch::new()
-        .chain(file_config)
-        .chain(stderr_config)
-        .apply()?;
+    let mut dispatch = fern::Dispatch::new().chain(stderr_config);
+    if !opt.no_log_file {
+        let file_config = fern::Dispatch::new()
+            .format(log_formatter(opt.log_format))
+            .chain(fern::log_file(&opt.log_file)?)
+            .level(log::LevelFilter::Trace);
+        dispatch = dispatch.chain(file_config);
+    }
+    dispatch.apply()?;
+
+    if let Some(cmd) = opt.cmd.take() {
+        // Serving packs the atlases, so it needs the ones from the config file
+        match cmd {
+            commands::Command::Serve(serve) => serve.run(&opt, atlases)?,
+            cmd => cmd.run(&opt)?,
+        }
+        return Ok(exit::SUCCESS);
+    }
+
+    let atlases = atlases_to_pack(&opt, atlases)?;
+    if opt.watch {
+        let mut paths: Vec<PathBuf> = atlases
+            .iter()
+            .flat_map(|atlas| atlas.inputs.iter().cloned())
+            .collect();
+        paths.extend(opt.config.clone());
+        watch::watch(&paths, &written_dirs(&atlases), || {
+            // The config file might have changed since the last pack
+            let (opt, atlases) = load_options(&matches)?;
+            run_all(&atlases_to_pack(&opt, atlases)?).map(|_| ())
+        })?;
+        return Ok(exit::SUCCESS);
+    }
+    match run_all(&atlases)? {
+        // An up-to-date atlas is what `--check` is looking for
+        exit::Outcome::Unchanged if !opt.check => Ok(exit::UNCHANGED),
+        _ => Ok(exit::SUCCESS),
+    }
+}
+
+/// The options of the command line with the settings of the config file applied, along with
+/// the atlases the config file lists
+fn load_options(matches: &structopt::clap::ArgMatches) -> Result<(Opt, Vec<Opt>)> {
+    let mut opt = Opt::from_clap(matches);
+
+    let mut atlases = vec![];
+    if let Some(config_path) = opt.config.clone() {
+        let mut config = config::Config::load(config_path)?;
+        let atlas_configs = std::mem::take(&mut config.atlases);
+        config.apply(&mut opt, matches)?;
+        atlases = config::atlas_options(atlas_configs, &opt, matches)?;
+    }
+
+    apply_default(&mut opt);
+    for atlas in atlases.iter_mut() {
+        apply_default(atlas);
+    }
+    Ok((opt, atlases))
+}
+
+/// The atlases listed in the config file, or the single atlas of `opt` without any, once
+/// they're all validated
+fn atlases_to_pack(opt: &Opt, mut atlases: Vec<Opt>) -> Result<Vec<Opt>> {
+    if atlases.is_empty() {
+        atlases.push(opt.clone());
+    }
+    for atlas in &atlases {
+        validate(atlas)?;
+    }
+    Ok(atlases)
+}
+
+/// The directories packing `atlases` writes to, whose changes `--watch` ignores
+fn written_dirs(atlases: &[Opt]) -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    for atlas in atlases {
+        if let Some(dir) = atlas.output.as_ref().and_then(|output| output.parent()) {
+            dirs.push(dir.to_owned());
+        }
+        dirs.push(atlas.cache_dir.clone());
+    }
+    dirs
+}
+
+/// Turns on the settings implied by `--default`, and the formats selected with `-x`, `-j` and
+/// `-b`
+fn apply_default(opt: &mut Opt) {
+    if opt.default {
+        opt.xml = true;
+        opt.premultiply = true;
+        opt.trim = true;
+        opt.unique = true;
+    }
+    if opt.unique_fuzzy.is_some() {
+        opt.unique = true;
+    }
 
+    let shorthands = [
+        (opt.xml, Format::Xml),
+        (opt.json, Format::Json),
+        (opt.binary, Format::Binary),
+    ];
+    let selected = shorthands
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, format)| *format);
+    let mut formats: Vec<Format> = vec![];
+    for format in selected.chain(opt.format.drain(..)) {
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    opt.format = formats;
+}
+
+/// Checks the packing options of an atlas that clap can't check on its own
+fn validate(opt: &Opt) -> Result<()> {
     if opt.pad > 16 {
         log::error!("Invalid padding value: {}", opt.pad);
         return Err(error::ImpactError::InvalidPadding { size: opt.pad });
     }
+    if opt.extrude > 16 {
+        log::error!("Invalid extrude value: {}", opt.extrude);
+        return Err(error::ImpactError::InvalidExtrude { size: opt.extrude });
+    }
+    if opt.align == 0 {
+        log::error!("Invalid align value: {}", opt.align);
+        return Err(error::ImpactError::InvalidAlign { align: opt.align });
+    }
+    if let Some(format @ (Format::SpriteKit | Format::Bundle)) = opt.stdout {
+        log::error!("{} atlases can't be written to stdout", format.name());
+        return Err(error::ImpactError::UnsupportedStdout {
+            format: format.name().to_owned(),
+        });
+    }
+    if !(MIN_SIZE..=MAX_SIZE).contains(&opt.size) {
+        log::error!("Size {} is out of range", opt.size);
+        return Err(error::ImpactError::InvalidSize { size: opt.size });
+    }
+    if let Some(multiple) = opt
+        .multiple_of
+        .filter(|&multiple| multiple == 0 || opt.size % multiple != 0)
+    {
+        log::error!("Size {} isn't a multiple of {}", opt.size, multiple);
+        return Err(error::ImpactError::NotMultipleOf {
+            size: opt.size,
+            multiple,
+        });
+    }
+    let size = opt.size as i32;
+    if let Some(area) = opt
+        .reserve
+        .iter()
+        .find(|area| area.x + area.width > size || area.y + area.height > size)
+    {
+        log::error!("Reserved area {} doesn't fit in a page", area);
+        return Err(error::ImpactError::ReserveOutOfBounds {
+            area: area.to_string(),
+            size: opt.size,
+        });
+    }
+    if let Some(quality) = opt.quality.filter(|&quality| quality > 100) {
+        log::error!("Invalid quality value: {}", quality);
+        return Err(error::ImpactError::InvalidQuality { quality });
+    }
+    if let Some(similarity) = opt.near_duplicates.filter(|&similarity| similarity > 100) {
+        log::error!("Invalid similarity value: {}", similarity);
+        return Err(error::ImpactError::InvalidSimilarity { similarity });
+    }
+    if opt.archive.is_some() && opt.split_by_folder {
+        log::error!("--archive can't be used with --split-by-folder");
+        return Err(error::ImpactError::IncompatibleOptions {
+            first: "archive".to_owned(),
+            second: "split-by-folder".to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Builds the formatter used for every log record
+fn log_formatter(
+    format: LogFormat,
+) -> impl Fn(fern::FormatCallback, &std::fmt::Arguments, &log::Record) + Sync + Send + 'static {
+    move |out, message, record| match format {
+        LogFormat::Text => out.finish(format_args!(
+            "{}[{}][{}] {}",
+            chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+            record.target(),
+            record.level(),
+            message
+        )),
+        LogFormat::Json => out.finish(format_args!(
+            "{}",
+            serde_json::json!({
+                "time": chrono::Local::now().to_rfc3339(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": message.to_string(),
+            })
+        )),
+    }
+}
+
+/// The path of a cache file for the atlas called `output_name`
+fn cache_path(opt: &Opt, output_name: &std::ffi::OsStr, extension: &str) -> PathBuf {
+    opt.cache_dir
+        .join(&format!("{}", output_name.to_string_lossy()))
+        .with_extension(extension)
+}
+
+/// Removes the files written by a previous run, so that stale pages and formats don't linger
+fn remove_old_files(
+    opt: &Opt,
+    output_dir: &std::path::Path,
+    output_name: &std::ffi::OsStr,
+) -> Result<()> {
+    let hash_path = cache_path(opt, output_name, "hash");
+    if hash_path.exists() {
+        std::fs::remove_file(&hash_path)?;
+    }
+
+    // The layout holds the stable indices, which mustn't be lost if packing fails. It's only
+    // reused if it still matches the images anyway.
+    let layout_path = cache_path(opt, output_name, "layout");
+    if layout_path.exists() && !opt.stable_indices {
+        std::fs::remove_file(&layout_path)?;
+    }
+
+    for format in Format::ALL.iter() {
+        let format_path = output_dir
+            .join(&format!("{}", output_name.to_string_lossy()))
+            .with_extension(format.extension());
+        if format_path.is_dir() {
+            std::fs::remove_dir_all(&format_path)?;
+        } else if format_path.exists() {
+            std::fs::remove_file(&format_path)?;
+        }
+    }
+
+    for extension in &[opt.extension.as_str(), "debug.png", "outlines.png"] {
+        for atlas in output_dir
+            .glob(&format!(
+                "{}.{}",
+                opt.page_name.glob(&output_name.to_string_lossy()),
+                extension
+            ))
+            .expect("failed to read glob pattern")
+        {
+            match atlas {
+                Ok(path) => std::fs::remove_file(&path)?,
+                Err(_) => (),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The atlas data and page files of an atlas that are on disk, for `impact serve`. With
+/// `--split-by-folder`, this includes the files of the atlas of every folder.
+fn existing_output_files(opt: &Opt) -> Result<Vec<PathBuf>> {
+    let atlases = if opt.split_by_folder {
+        split::split_by_folder(opt)?
+    } else {
+        vec![opt.clone()]
+    };
+
+    let mut files = vec![];
+    for atlas in &atlases {
+        let output = atlas
+            .output
+            .clone()
+            .ok_or(error::ImpactError::MissingOutput)?;
+        let output_dir = output
+            .parent()
+            .expect("could not retrieve output directory");
+        let output_name = output
+            .file_name()
+            .expect("could not retrieve output filename")
+            .to_string_lossy();
+
+        for format in Format::ALL.iter() {
+            let path = output.with_extension(format.extension());
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+        for extension in &[atlas.extension.as_str(), "debug.png", "outlines.png"] {
+            let pattern = format!("{}.{}", atlas.page_name.glob(&output_name), extension);
+            files.extend(
+                output_dir
+                    .glob(&pattern)
+                    .expect("failed to read glob pattern")
+                    .filter_map(|page| page.ok()),
+            );
+        }
+    }
+    Ok(files)
+}
+
+/// Prints a table describing where every image was placed
+fn print_plan(opt: &Opt, packers: &[packer::Packer]) {
+    // The options don't matter, only the positions of the sprites
+    print_layout_plan(opt, &layout::Layout::from_packers(0, packers, vec![]).pages);
+}
+
+/// Prints a table describing where every sprite of a layout was placed
+fn print_layout_plan(opt: &Opt, pages: &[layout::Page]) {
+    reportln!(
+        opt,
+        "{:>4} {:>5} {:>5} {:>5} {:>5} {:>3} {:>15}  {}",
+        "page",
+        "x",
+        "y",
+        "w",
+        "h",
+        "rot",
+        "trim (l,t,r,b)",
+        "name"
+    );
+    for (idx, page) in pages.iter().enumerate() {
+        for sprite in page.sprites.iter() {
+            let left = -sprite.frame_x;
+            let top = -sprite.frame_y;
+            let right = sprite.frame_w - sprite.width - left;
+            let bottom = sprite.frame_h - sprite.height - top;
+            let name = if sprite.dup_id < 0 {
+                sprite.name.clone()
+            } else {
+                format!(
+                    "{} (duplicate of {})",
+                    sprite.name, page.sprites[sprite.dup_id as usize].name
+                )
+            };
+            reportln!(
+                opt,
+                "{:>4} {:>5} {:>5} {:>5} {:>5} {:>3} {:>15}  {}",
+                idx,
+                sprite.x,
+                sprite.y,
+                sprite.width,
+                sprite.height,
+                if sprite.rot { "yes" } else { "no" },
+                format!("{},{},{},{}", left, top, right, bottom),
+                name
+            );
+        }
+    }
+}
+
+/// Packs every atlas in turn, splitting them with `--split-by-folder`, reporting them as
+/// unchanged only if they all are
+fn run_all(atlases: &[Opt]) -> Result<exit::Outcome> {
+    let mut outcome = exit::Outcome::Unchanged;
+    let mut hashes = FileHashes::new();
+    for opt in atlases {
+        let split = if opt.split_by_folder {
+            split::split_by_folder(opt)?
+        } else {
+            vec![opt.clone()]
+        };
+        for atlas in &split {
+            if let Some(output) = &atlas.output {
+                log::info!("packing atlas {}", output.display());
+            }
+            if run(atlas, &mut hashes)? == exit::Outcome::Packed {
+                outcome = exit::Outcome::Packed;
+            }
+        }
+    }
+    Ok(outcome)
+}
 
-    let output_dir = opt
+/// Packs the inputs and writes the atlas, unless the cached hash shows it's unchanged. `hashes`
+/// holds the input files hashed by the atlases packed before this one.
+fn run(opt: &Opt, hashes: &mut FileHashes) -> Result<exit::Outcome> {
+    let output = opt
         .output
+        .clone()
+        .ok_or(error::ImpactError::MissingOutput)?;
+    let output_dir = output
         .parent()
         .expect("could not retrieve output directory");
-    let output_name = opt
-        .output
+    let output_name = output
         .file_name()
         .expect("could not retrieve output filename");
 
+    // Folder settings files are part of the options, so that changing them repacks
+    let opt = &Opt {
+        folders: config::load_folder_overrides(opt)?,
+        ..opt.clone()
+    };
+
     // Hash the arguments and input directories
-    let mut hasher = MetroHash::default();
-    opt.hash(&mut hasher);
-    for input in &opt.inputs {
-        let md = metadata(input)?;
-        if md.is_dir() {
-            hash_files(input, &mut hasher)?;
-        } else {
-            hash_file(input, &mut hasher)?;
+    let options_hash = options_hash::options_hash(opt);
+    timings::reset();
+    let hash = timings::time(timings::Phase::Hashing, || -> Result<u64> {
+        let mut hasher = MetroHash::default();
+        hasher.write_u64(options_hash);
+        for input in &opt.inputs {
+            let md = metadata(input)?;
+            if md.is_dir() {
+                hash_files(input, &mut hasher, opt, hashes)?;
+            } else {
+                let root = input.parent().unwrap_or_else(|| std::path::Path::new(""));
+                hash_file(root, input, &mut hasher, opt, hashes)?;
+            }
         }
-    }
-    let hash = hasher.finish();
+        if !opt.dry_run && !opt.check {
+            hashes.save_index(opt)?;
+        }
+        Ok(hasher.finish())
+    })?;
     let hash_str = format!("{}", hash);
 
     // Load the old hash
-    let hash_path = output_dir
-        .join(&format!("{}", output_name.to_string_lossy()))
-        .with_extension("hash");
-    if hash_path.exists() {
+    let hash_path = cache_path(opt, output_name, "hash");
+    if !opt.dry_run && hash_path.exists() {
         let contents = std::fs::read_to_string(&hash_path)?;
-        if !opt.force && contents == hash_str {
+        // The atlas data still has to be written to stdout when it's up to date
+        if !opt.force && opt.stdout.is_none() && contents == hash_str {
             log::info!("Atlas is unchanged: {}", output_name.to_string_lossy());
-            return Ok(());
+            report_unchanged(opt, output_dir, output_name, hashes)?;
+            timings::report(opt, &output_name.to_string_lossy());
+            return Ok(exit::Outcome::Unchanged);
         }
     }
+    if opt.check {
+        log::error!("Atlas is out of date: {}", output_name.to_string_lossy());
+        return Err(error::ImpactError::OutOfDate {
+            name: output_name.to_string_lossy().into_owned(),
+        });
+    }
 
     log::trace!("Options:\n{:?}", opt);
 
-    // Remove old files
-    if hash_path.exists() {
-        std::fs::remove_file(&hash_path)?;
+    let (mut images, animations) =
+        timings::time(timings::Phase::Loading, || load_inputs(opt, hashes))?;
+    
+    {
+        use humansize::{format_size, DECIMAL};
+        let size = images.iter().fold(0, |sum, img| sum + img.original_size);
+        log::info!("size of all images: {}", format_size(size, DECIMAL));
     }
-    let bin_path = output_dir
-        .join(&format!("{}", output_name.to_string_lossy()))
-        .with_extension("bin");
-    if bin_path.exists() {
-        std::fs::remove_file(&bin_path)?;
+    if let Some(percent) = opt.near_duplicates {
+        near_duplicates::print(opt, &images, percent);
     }
 
-    let xml_path = output_dir
-        .join(&format!("{}", output_name.to_string_lossy()))
-        .with_extension("xml");
-    if xml_path.exists() {
-        std::fs::remove_file(&xml_path)?;
+    // Reuse the previous layout if only the contents of some images changed
+    let packing = Instant::now();
+    let layout_path = cache_path(opt, output_name, "layout");
+    // Near duplicates can only be found by comparing every image, so they're always repacked,
+    // and archived pages no longer exist as files to keep when they haven't changed
+    let stored = layout::Layout::load(&layout_path);
+    // Sprites keep their indices whatever the options, even when the atlas is repacked
+    let stored_indices = stored
+        .as_ref()
+        .map(|layout| layout.indices.clone())
+        .unwrap_or_default();
+    let previous = if opt.force || opt.unique_fuzzy.is_some() || opt.archive.is_some() {
+        None
+    } else {
+        stored.filter(|layout| layout.options == options_hash)
+    };
+    let reused = previous
+        .as_ref()
+        .and_then(|layout| layout.reuse(&mut images, opt.unique, opt.pad as i32, opt.rotation));
+
+    let (mut packers, dirty, metadata_changed) = match reused {
+        Some(reused) => {
+            log::info!(
+                "reusing previous layout, {} of {} page(s) changed",
+                reused.dirty.iter().filter(|dirty| **dirty).count(),
+                reused.packers.len()
+            );
+            let animations_changed =
+                previous.map_or(true, |layout| layout.animations != animations);
+            (
+                reused.packers,
+                reused.dirty,
+                reused.metadata_changed || animations_changed,
+            )
+        }
+        None => {
+            // Remove old files
+            if !opt.dry_run {
+                remove_old_files(opt, output_dir, output_name)?;
+            }
+
+            let packers = pack_images(opt, images)?;
+            let dirty = vec![true; packers.len()];
+            (packers, dirty, true)
+        }
+    };
+    timings::add(timings::Phase::Packing, packing.elapsed());
+    for (index, packer) in packers.iter_mut().enumerate() {
+        packer.pad_fill = opt.pad_fill;
+        packer.reserved = reserve::rects(&opt.reserve, index);
     }
 
-    let json_path = output_dir
-        .join(&format!("{}", output_name.to_string_lossy()))
-        .with_extension("json");
-    if json_path.exists() {
-        std::fs::remove_file(&json_path)?;
+    if opt.print_plan {
+        print_plan(opt, &packers);
     }
 
-    for atlas in output_dir
-        .glob(&format!(
-            "{}*.{}",
-            output_name.to_string_lossy(),
-            &opt.extension
-        ))
-        .expect("failed to read glob pattern")
-    {
-        match atlas {
-            Ok(path) => std::fs::remove_file(&path)?,
-            Err(_) => (),
+    report_stats(opt, &packers)?;
+    let indices = if opt.stable_indices {
+        Some(assign_indices(&stored_indices, &packers))
+    } else {
+        None
+    };
+    if opt.dry_run {
+        timings::report(opt, &output_name.to_string_lossy());
+        return Ok(exit::Outcome::Packed);
+    }
+
+    timings::time(timings::Phase::Saving, || {
+        save_pages(opt, &packers, &dirty, output_dir, output_name)
+    })?;
+    copy_unpacked(opt, output_dir)?;
+    // Bundles hold the pages too, and checksums depend on them, so they're written again
+    // whenever a page is. Sources change with any input.
+    let pages_changed =
+        (opt.checksums || opt.format.contains(&Format::Bundle)) && dirty.iter().any(|dirty| *dirty);
+    if metadata_changed || pages_changed || opt.sources || opt.stdout.is_some() {
+        timings::time(timings::Phase::Metadata, || {
+            write_metadata(
+                opt,
+                &packers,
+                &animations,
+                indices.as_ref(),
+                output_dir,
+                output_name,
+            )
+        })?;
+    } else {
+        log::info!("metadata is unchanged");
+    }
+    let mut outputs = output_files(opt, packers.len(), output_dir, output_name);
+    if let Some(path) = &opt.archive {
+        archive::write(path, output_dir, &outputs)?;
+        outputs = vec![path.clone()];
+    }
+    if let Some(path) = &opt.report {
+        log::info!("writing report {}", path.display());
+        stats::Report::new(stats::Stats::new(&packers), outputs)?.save(path)?;
+    }
+
+    // Save the new layout and hash
+    std::fs::create_dir_all(&opt.cache_dir)?;
+    let mut layout = layout::Layout::from_packers(options_hash, &packers, animations);
+    layout.indices = indices.unwrap_or(stored_indices);
+    layout.save(&layout_path)?;
+    std::fs::write(&hash_path, hash_str)?;
+    timings::report(opt, &output_name.to_string_lossy());
+    Ok(exit::Outcome::Packed)
+}
+
+/// Prints the reports asked for about an atlas that's up to date, which isn't packed again
+fn report_unchanged(
+    opt: &Opt,
+    output_dir: &std::path::Path,
+    output_name: &std::ffi::OsStr,
+    hashes: &FileHashes,
+) -> Result<()> {
+    if opt.print_plan || opt.stats || opt.stats_file.is_some() || opt.report.is_some() {
+        // The layout is saved along with the hash, so it's the one of the atlas on disk
+        match layout::Layout::load(cache_path(opt, output_name, "layout")) {
+            Some(layout) => {
+                if opt.print_plan {
+                    print_layout_plan(opt, &layout.pages);
+                }
+                let stats = stats::Stats::from_pages(&layout.pages);
+                write_stats(opt, &stats)?;
+                if let Some(path) = &opt.report {
+                    let outputs = match &opt.archive {
+                        Some(archive) => vec![archive.clone()],
+                        None => output_files(opt, layout.pages.len(), output_dir, output_name),
+                    };
+                    log::info!("writing report {}", path.display());
+                    stats::Report::new(stats, outputs)?.save(path)?;
+                }
+            }
+            None => log::info!("no layout is stored, so the reports were skipped"),
         }
     }
+    if let Some(percent) = opt.near_duplicates {
+        // The sprites have to be loaded to be compared, but aren't packed again
+        let (images, _) = timings::time(timings::Phase::Loading, || load_inputs(opt, hashes))?;
+        near_duplicates::print(opt, &images, percent);
+    }
+    Ok(())
+}
 
-    // Load the bitmaps from all the input files and directories
-    log::info!("loading images...");
-    let mut images = vec![];
-    for input in &opt.inputs {
-        let md = metadata(input)?;
-        if md.is_dir() {
-            load_images(input, &mut images, &opt)?;
-        } else {
-            load_image(input, &mut images, &opt)?;
+/// Numbers every sprite of `packers` and their aliases for `--stable-indices`, keeping the
+/// indices they already have in `previous`
+fn assign_indices(previous: &layout::Indices, packers: &[packer::Packer]) -> layout::Indices {
+    let names = packers
+        .iter()
+        .flat_map(|packer| packer.images.iter())
+        .flat_map(|image| std::iter::once(&image.name).chain(image.aliases.iter()))
+        .map(String::as_str);
+    previous.assign(names)
+}
+
+/// Prints the packing statistics for `--dry-run` and `--stats`, and writes them for
+/// `--stats-file`
+fn report_stats(opt: &Opt, packers: &[packer::Packer]) -> Result<()> {
+    if !opt.dry_run && !opt.stats && opt.stats_file.is_none() {
+        return Ok(());
+    }
+    write_stats(opt, &stats::Stats::new(packers))
+}
+
+fn write_stats(opt: &Opt, stats: &stats::Stats) -> Result<()> {
+    if opt.dry_run || opt.stats {
+        stats.print(opt);
+    }
+    if let Some(path) = &opt.stats_file {
+        log::info!("writing stats {}", path.display());
+        stats.save(path)?;
+    }
+    Ok(())
+}
+
+/// Saves the atlas images, compositing and encoding the pages in parallel. Pages that aren't
+/// `dirty` are only written if they're missing.
+fn save_pages(
+    opt: &Opt,
+    packers: &[packer::Packer],
+    dirty: &[bool],
+    output_dir: &std::path::Path,
+    output_name: &std::ffi::OsStr,
+) -> Result<()> {
+    let progress = progress::bar(opt, packers.len(), "saving");
+    packers
+        .par_iter()
+        .enumerate()
+        .try_for_each(|(idx, packer)| -> Result<()> {
+            let out_path = output_dir.join(&format!(
+                "{}.{}",
+                opt.page_name.format(&output_name.to_string_lossy(), idx),
+                &opt.extension
+            ));
+            if !dirty[idx] && out_path.exists() {
+                log::info!("page {} is unchanged", idx);
+            } else {
+                log::info!("writing image {}", out_path.display());
+                let saved = packer.save_image(&out_path, opt.quality)?;
+                timings::add(timings::Phase::Compositing, saved.compositing);
+                timings::add(timings::Phase::Encoding, saved.encoding);
+            }
+            if opt.alpha_variants {
+                let pm_path = premultiplied_path(opt, &out_path);
+                if dirty[idx] || !pm_path.exists() {
+                    log::info!("writing premultiplied image {}", pm_path.display());
+                    let saved = packer.save_premultiplied_image(&pm_path, opt.quality)?;
+                    timings::add(timings::Phase::Compositing, saved.compositing);
+                    timings::add(timings::Phase::Encoding, saved.encoding);
+                }
+            }
+            if opt.debug_image {
+                let debug_path = out_path.with_extension("debug.png");
+                if dirty[idx] || !debug_path.exists() {
+                    log::info!("writing debug image {}", debug_path.display());
+                    packer.debug_image().save(&debug_path)?;
+                }
+            }
+            if opt.debug_outlines {
+                let outlines_path = out_path.with_extension("outlines.png");
+                if dirty[idx] || !outlines_path.exists() {
+                    log::info!("writing outlines {}", outlines_path.display());
+                    packer.outline_image().save(&outlines_path)?;
+                }
+            }
+            progress.inc(1);
+            Ok(())
+        })?;
+    progress.finish_and_clear();
+
+    Ok(())
+}
+
+/// The pages and atlas data files written for an atlas of `pages` pages
+fn output_files(
+    opt: &Opt,
+    pages: usize,
+    output_dir: &std::path::Path,
+    output_name: &std::ffi::OsStr,
+) -> Vec<PathBuf> {
+    let pages = (0..pages).flat_map(|idx| {
+        let page = output_dir.join(&format!(
+            "{}.{}",
+            opt.page_name.format(&output_name.to_string_lossy(), idx),
+            &opt.extension
+        ));
+        let premultiplied = Some(premultiplied_path(opt, &page)).filter(|_| opt.alpha_variants);
+        std::iter::once(page).chain(premultiplied)
+    });
+    let metadata = opt
+        .format
+        .iter()
+        .filter(|format| Some(**format) != opt.stdout)
+        .map(|format| {
+            output_dir
+                .join(&format!("{}", output_name.to_string_lossy()))
+                .with_extension(format.extension())
+        });
+    pages.chain(metadata).collect()
+}
+
+/// The premultiplied copy of the page at `page`, with `--alpha-variants`
+fn premultiplied_path(opt: &Opt, page: &std::path::Path) -> PathBuf {
+    page.with_extension(format!("pm.{}", opt.extension))
+}
+
+/// Copies the input images marked `~nopack` next to the atlas as they are, without the flag
+fn copy_unpacked(opt: &Opt, output_dir: &std::path::Path) -> Result<()> {
+    for file in collect_files(opt)? {
+        if !is_image_file(&file) || !naming::flags(&file).nopack {
+            continue;
+        }
+        if let Some(name) = naming::file_name_without_flags(&file) {
+            let out_path = output_dir.join(name);
+            log::info!("copying {} to {}", file.display(), out_path.display());
+            std::fs::copy(&file, &out_path)?;
         }
     }
-    log::info!("loaded {} images.", images.len());
-    
-    {
-        use humansize::{format_size, DECIMAL};
-        let size = images.iter().fold(0, |sum, img| sum + img.original_size);
-        log::info!("size of all images: {}", format_size(size, DECIMAL));
+    Ok(())
+}
+
+/// Sorts the bitmaps by area and packs them into as many pages as needed
+fn pack_images(opt: &Opt, mut images: Vec<ImageWrapper>) -> Result<Vec<packer::Packer>> {
+    let mut groups: Vec<String> = vec![];
+    for image in images.iter_mut() {
+        if let Some(group) = keep_together_group(opt, &image.name) {
+            let id = match groups.iter().position(|other| other == group) {
+                Some(id) => id,
+                None => {
+                    groups.push(group.to_owned());
+                    groups.len() - 1
+                }
+            };
+            image.group = Some(id);
+        }
     }
 
-    // Sort the bitmaps by area
+    check_sizes(opt, &images)?;
+
+    // Sort the bitmaps by priority, then area, since they're packed from the end
     images.sort_unstable_by(|a: &ImageWrapper, b: &ImageWrapper| {
-        (a.width * a.height).cmp(&(b.width * b.height))
+        a.priority
+            .cmp(&b.priority)
+            .then((a.width * a.height).cmp(&(b.width * b.height)))
     });
 
     // Pack the bitmaps
     let mut packers = vec![];
+    let progress = progress::bar(opt, images.len(), "packing");
     while !images.is_empty() {
         log::info!("packing {} images...", images.len());
-        let mut packer = packer::Packer::new(opt.size as i32, opt.size as i32, opt.pad as i32);
+        let before = opt.retry_overflow.map(|_| images.clone());
+        let mut packer = new_page(opt, packers.len());
         packer.pack(
             &mut images,
             opt.unique,
             opt.rotate,
             opt.heuristic.into(),
+            &mut || progress.inc(1),
         );
+        if let Some(before) = before.filter(|_| !images.is_empty()) {
+            for (heuristic, rotate) in retry_attempts(opt) {
+                let mut attempt_images = before.clone();
+                let mut attempt = new_page(opt, packers.len());
+                attempt.pack(
+                    &mut attempt_images,
+                    opt.unique,
+                    rotate,
+                    heuristic.into(),
+                    &mut || (),
+                );
+                if attempt.images.len() > packer.images.len() {
+                    log::info!(
+                        "{} with rotation {} fits {} more image(s) in page {}",
+                        heuristic,
+                        if rotate { "on" } else { "off" },
+                        attempt.images.len() - packer.images.len(),
+                        packers.len()
+                    );
+                    progress.inc((attempt.images.len() - packer.images.len()) as u64);
+                    packer = attempt;
+                    images = attempt_images;
+                }
+            }
+        }
         log::info!(
                 "finished packing {} - ({}x{})",
                 packers.len(),
@@ -382,6 +1962,11 @@ fn main() -> Result<()> {
                 packer.height
             );
         if packer.images.is_empty() {
+            if let Some(id) = images.last().and_then(|image| image.group) {
+                let group = groups[id].clone();
+                log::error!("packing failed, sprites in {} don't fit in one page", group);
+                return Err(error::ImpactError::CantKeepTogether { group });
+            }
             log::error!(
                 "packing failed, could not fit image {}",
                 images.first().unwrap().name
@@ -390,74 +1975,363 @@ fn main() -> Result<()> {
         }
         packers.push(packer);
     }
+    progress.finish_and_clear();
 
-    // Save the atlas image
-    for (idx, packer) in packers.iter().enumerate() {
-        let out_path = output_dir
-            .join(&format!("{}{}", output_name.to_string_lossy(), idx))
-            .with_extension(&opt.extension);
-        log::info!("writing image {}", out_path.display());
-        packer.save_png(out_path)?;
+    resize_pages(opt, &mut packers);
+    Ok(packers)
+}
+
+/// Grows the pages that shouldn't be shrunk with `--shrink` back to the full size, and rounds
+/// the size of every page up for `--pot` and `--multiple-of`, which leaves every sprite where
+/// it is
+fn resize_pages(opt: &Opt, packers: &mut [packer::Packer]) {
+    let full_size = match opt.shrink {
+        _ if opt.fixed_size => packers.len(),
+        Shrink::All => 0,
+        Shrink::Last => packers.len().saturating_sub(1),
+        Shrink::None => packers.len(),
+    };
+    for packer in packers.iter_mut().take(full_size) {
+        packer.width = std::cmp::max(packer.width, opt.size as i32);
+        packer.height = std::cmp::max(packer.height, opt.size as i32);
     }
 
-    // Create info
-    let mut atlas = serial::Atlas { textures: vec![] };
+    let round = |size: i32| {
+        let mut size = size as u32;
+        if opt.pot {
+            size = size.next_power_of_two();
+        }
+        if let Some(multiple) = opt.multiple_of.map(u32::from) {
+            size = (size + multiple - 1) / multiple * multiple;
+        }
+        size as i32
+    };
+    for packer in packers.iter_mut() {
+        packer.width = round(packer.width);
+        packer.height = round(packer.height);
+    }
+}
 
-    for (idx, packer) in packers.iter().enumerate() {
-        let name = output_name.to_string_lossy();
-        let mut texture = serial::Texture {
-            name: format!("{}{}", name, idx),
-            images: vec![],
+/// An empty page to pack images into, the `index`th of the atlas
+fn new_page(opt: &Opt, index: usize) -> packer::Packer {
+    let mut packer = packer::Packer::new(opt.size as i32, opt.size as i32, opt.pad as i32);
+    packer.rotation = opt.rotation;
+    packer.align = opt.align as i32;
+    packer.rotation_penalty = opt.rotation_penalty as i32;
+    packer.max_diff = opt.unique_fuzzy.unwrap_or(0);
+    packer.reserved = reserve::rects(&opt.reserve, index);
+    packer
+}
+
+/// The heuristics and rotation settings to pack an overflowing page again with, for
+/// `--retry-overflow`
+fn retry_attempts(opt: &Opt) -> Vec<(FreeRectChoiceHeuristic, bool)> {
+    let all_heuristics = || {
+        FreeRectChoiceHeuristic::variants()
+            .iter()
+            .map(|name| name.parse().expect("heuristic names parse"))
+            .collect()
+    };
+    let (heuristics, rotations): (Vec<FreeRectChoiceHeuristic>, &[bool]) =
+        match opt.retry_overflow {
+            Some(RetryOverflow::Heuristics) => (all_heuristics(), &[opt.rotate]),
+            Some(RetryOverflow::Rotation) => (vec![opt.heuristic], &[true]),
+            Some(RetryOverflow::All) => (all_heuristics(), &[opt.rotate, true]),
+            None => return vec![],
         };
-        for (img_idx, img) in packer.images.iter().enumerate() {
-            let p = &packer.points[img_idx];
-            let s_img = serial::Image {
-                name: String::from(&img.name),
-                x: p.x,
-                y: p.y,
-                width: img.width,
-                height: img.height,
-                frame_x: img.frame_x,
-                frame_y: img.frame_y,
-                frame_width: img.frame_w,
-                frame_height: img.frame_h,
-                rotated: p.rot,
-            };
-            texture.images.push(s_img);
+    let mut attempts = vec![];
+    for &rotate in rotations {
+        for &heuristic in &heuristics {
+            let attempt = (heuristic, rotate);
+            if attempt != (opt.heuristic, opt.rotate) && !attempts.contains(&attempt) {
+                attempts.push(attempt);
+            }
         }
-        atlas.textures.push(texture);
     }
+    attempts
+}
 
-    // Save the atlas binary
-    if opt.binary {
-        let out_path = output_dir
-            .join(&format!("{}", output_name.to_string_lossy()))
-            .with_extension("bin");
-        log::info!("writing binary {}", out_path.display());
-        let res = bincode::serialize(&atlas).expect("failed to serialize into binary data");
-        std::fs::write(out_path, &res)?;
+/// Fails if any of the images are too large for an empty page, reporting all of them at once
+fn check_sizes(opt: &Opt, images: &[ImageWrapper]) -> Result<()> {
+    let size = opt.size as i32;
+    let mut count = 0;
+    let mut needed = 0;
+    for image in images {
+        let border = 2 * image.extrude + image.pad.unwrap_or(opt.pad as i32);
+        let (width, height) = (image.width + border, image.height + border);
+        if width > size || height > size {
+            log::error!(
+                "{} is {}x{} ({}x{} with its border and padding), too large for a {}x{} page",
+                image.name,
+                image.width,
+                image.height,
+                width,
+                height,
+                size,
+                size
+            );
+            count += 1;
+            needed = std::cmp::max(needed, std::cmp::max(width, height));
+        }
+    }
+    if count > 0 {
+        return Err(error::ImpactError::TooLarge {
+            count,
+            min_size: (needed as u32).next_power_of_two(),
+        });
     }
+    Ok(())
+}
 
-    // Save the atlas xml
-    if opt.xml {
-        let out_path = output_dir
-            .join(&format!("{}", output_name.to_string_lossy()))
-            .with_extension("xml");
-        log::info!("writing xml {}", out_path.display());
-        atlas.write_to_xml_file(out_path)?;
+/// The folders of the sprite called `name`, outermost first, followed by the name itself
+fn folders_and_name<'a>(opt: &Opt, name: &'a str) -> Vec<&'a str> {
+    let mut paths = vec![name];
+    if !opt.name_separator.is_empty() {
+        let mut rest = name;
+        while let Some(idx) = rest.rfind(opt.name_separator.as_str()) {
+            rest = &rest[..idx];
+            paths.push(rest);
+        }
+    }
+    paths.reverse();
+    paths
+}
+
+/// The outermost folder of the sprite called `name` (or the sprite itself) that matches a
+/// `--keep-together` pattern
+fn keep_together_group<'a>(opt: &Opt, name: &'a str) -> Option<&'a str> {
+    folders_and_name(opt, name).into_iter().find(|path| {
+        opt.keep_together
+            .iter()
+            .any(|pattern| pattern.matches(path))
+    })
+}
+
+/// The priority given to the sprite called `name` by the `--priority` patterns: the earlier
+/// the pattern it matches, the higher, or 0 if it matches none
+fn pattern_priority(opt: &Opt, name: &str) -> i32 {
+    let paths = folders_and_name(opt, name);
+    opt.priority
+        .iter()
+        .position(|pattern| paths.iter().any(|path| pattern.matches(path)))
+        .map_or(0, |idx| (opt.priority.len() - idx) as i32)
+}
+
+/// Writes the atlas metadata in every requested format
+fn write_metadata(
+    opt: &Opt,
+    packers: &[packer::Packer],
+    animations: &[serial::Animation],
+    indices: Option<&layout::Indices>,
+    output_dir: &std::path::Path,
+    output_name: &std::ffi::OsStr,
+) -> Result<()> {
+    // Create info
+    let mut atlas = serial::Atlas::default();
+
+    for (idx, packer) in packers.iter().enumerate() {
+        let name = opt.page_name.format(&output_name.to_string_lossy(), idx);
+        atlas
+            .textures
+            .push(serial::Texture::from_packer(name, packer));
+    }
+    if opt.checksums {
+        for texture in atlas.textures.iter_mut() {
+            let page = output_dir.join(format!("{}.{}", texture.name, opt.extension));
+            texture.checksum = Some(checksum::of_file(&page)?);
+        }
+    }
+    atlas.animations = animations.to_vec();
+    atlas.rotation = opt.rotation;
+    atlas.tiled = tiled_images(packers);
+    if opt.sources {
+        atlas.sources = sources(packers);
+    }
+    // Sprites named with `~alias=` are listed as sprites of their own unless aliases are kept
+    if !opt.aliases {
+        atlas.expand_aliases();
+    }
+    if let Some(indices) = indices {
+        for image in atlas
+            .textures
+            .iter_mut()
+            .flat_map(|texture| texture.images.iter_mut())
+        {
+            image.index = indices.by_name.get(&image.name).copied();
+        }
+    }
+    if opt.meta {
+        atlas.meta = Some(meta(opt));
+    }
+    // Animations that were already defined (e.g. by an atlas being appended to) win over
+    // generated ones with the same name
+    let defined: std::collections::HashSet<String> = atlas
+        .animations
+        .iter()
+        .map(|animation| animation.name.clone())
+        .collect();
+    let is_new = |animation: &serial::Animation| !defined.contains(&animation.name);
+    if opt.animations {
+        let animations = animation::from_suffixes(&atlas, &opt.animation_suffix);
+        atlas
+            .animations
+            .extend(animations.into_iter().filter(is_new));
+    }
+    if opt.animations_from_folders {
+        let animations = animation::from_folders(&atlas, &opt.name_separator);
+        atlas
+            .animations
+            .extend(animations.into_iter().filter(is_new));
+    }
+    // Fold duplicates last so that they can still be animation frames
+    if opt.aliases {
+        for texture in atlas.textures.iter_mut() {
+            texture.fold_aliases();
+        }
     }
 
-    // Save the atlas json
-    if opt.json {
+    // Save the atlas in every format, except the one written to stdout instead
+    let files = opt
+        .format
+        .iter()
+        .filter(|format| Some(**format) != opt.stdout);
+    for format in files {
         let out_path = output_dir
             .join(&format!("{}", output_name.to_string_lossy()))
-            .with_extension("json");
-        log::info!("writing json {}", out_path.display());
-        let res = serde_json::to_vec_pretty(&atlas).expect("failed to serialize into json");
-        std::fs::write(out_path, &res)?;
+            .with_extension(format.extension());
+        log::info!("writing {} {}", format.name(), out_path.display());
+        match format {
+            Format::SpriteKit => {
+                atlas.write_to_spritekit_bundle(out_path, output_dir, &opt.extension)?
+            }
+            Format::Bundle => std::fs::write(out_path, bundle(opt, &atlas, output_dir)?)?,
+            _ => std::fs::write(out_path, encode(&atlas, *format, opt)?)?,
+        }
+    }
+
+    if let Some(format) = opt.stdout {
+        use std::io::Write;
+        log::info!("writing {} to stdout", format.name());
+        let mut stdout = std::io::stdout();
+        stdout.write_all(&encode(&atlas, format, opt)?)?;
+        stdout.flush()?;
     }
 
-    // Save the new hash
-    std::fs::write(&hash_path, hash_str)?;
     Ok(())
 }
+
+/// Bundles the atlas data with the pages saved in `output_dir`
+fn bundle(opt: &Opt, atlas: &serial::Atlas, output_dir: &std::path::Path) -> Result<Vec<u8>> {
+    let mut pages = vec![];
+    for texture in &atlas.textures {
+        let page = output_dir.join(format!("{}.{}", texture.name, opt.extension));
+        let mut files = vec![(page.clone(), EntryKind::Page)];
+        if opt.alpha_variants {
+            files.push((premultiplied_path(opt, &page), EntryKind::PremultipliedPage));
+        }
+        for (file, kind) in files {
+            let name = file
+                .file_name()
+                .expect("page has a file name")
+                .to_string_lossy()
+                .into_owned();
+            pages.push((name, kind, std::fs::read(&file)?));
+        }
+    }
+    Ok(atlas.to_bundle(&pages))
+}
+
+/// The images split into tiles by `--tile-oversized`, along with where each tile goes
+fn tiled_images(packers: &[packer::Packer]) -> Vec<serial::TiledImage> {
+    let mut tiled: Vec<serial::TiledImage> = vec![];
+    for image in packers.iter().flat_map(|packer| packer.images.iter()) {
+        let position = match &image.tile {
+            Some(position) => position,
+            None => continue,
+        };
+        let tile = serial::Tile {
+            name: image.name.clone(),
+            x: position.x,
+            y: position.y,
+        };
+        match tiled.iter_mut().find(|tiled| tiled.name == position.image) {
+            Some(tiled) => tiled.tiles.push(tile),
+            None => tiled.push(serial::TiledImage {
+                name: position.image.clone(),
+                width: position.width,
+                height: position.height,
+                tiles: vec![tile],
+            }),
+        }
+    }
+    for image in tiled.iter_mut() {
+        image.tiles.sort_by_key(|tile| (tile.y, tile.x));
+    }
+    tiled
+}
+
+/// The file every sprite was read from, by sprite name, for `--sources`
+fn sources(packers: &[packer::Packer]) -> Vec<serial::Source> {
+    let mut sources: Vec<serial::Source> = packers
+        .iter()
+        .flat_map(|packer| packer.images.iter())
+        .filter_map(|image| image.source.clone())
+        .collect();
+    sources.sort_by(|a, b| a.name.cmp(&b.name));
+    sources
+}
+
+/// The version of impact and the options that change how the atlas is packed, for `--meta`
+fn meta(opt: &Opt) -> serial::Meta {
+    use serde_json::json;
+    let settings = vec![
+        ("size", json!(opt.size)),
+        ("pad", json!(opt.pad)),
+        ("pad-fill", json!(opt.pad_fill)),
+        ("extrude", json!(opt.extrude)),
+        ("align", json!(opt.align)),
+        ("premultiply", json!(opt.premultiply)),
+        ("alpha-variants", json!(opt.alpha_variants)),
+        ("trim", json!(opt.trim)),
+        ("trim-margin", json!(opt.trim_margin)),
+        ("crop", json!(opt.crop)),
+        ("scale", json!(opt.scale.factor())),
+        ("unique", json!(opt.unique)),
+        ("unique-fuzzy", json!(opt.unique_fuzzy)),
+        ("rotate", json!(opt.rotate)),
+        ("rotation", json!(opt.rotation)),
+        ("rotation-penalty", json!(opt.rotation_penalty)),
+        ("heuristic", json!(opt.heuristic.to_string())),
+        ("shrink", json!(opt.shrink.to_string().to_lowercase())),
+        ("extension", json!(opt.extension)),
+    ];
+    serial::Meta {
+        app: "impact".to_owned(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        settings: settings
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect(),
+    }
+}
+
+/// Serializes the atlas data in `format`, which must be a single file
+fn encode(atlas: &serial::Atlas, format: Format, opt: &Opt) -> Result<Vec<u8>> {
+    Ok(match format {
+        Format::Xml => atlas.to_xml(opt.long_keys)?,
+        Format::Json => if opt.long_keys {
+            serde_json::to_vec_pretty(&serial::LongKeys(atlas))
+        } else {
+            serde_json::to_vec_pretty(atlas)
+        }
+        .expect("failed to serialize into json"),
+        Format::Binary => atlas.to_binary(),
+        Format::Cbor => serde_cbor::to_vec(atlas).expect("failed to serialize into cbor"),
+        Format::FlatBuffers => atlas.to_flatbuffers(),
+        Format::Proto => atlas.to_proto(),
+        Format::BevyRon => atlas.to_bevy_ron(&opt.extension)?.into_bytes(),
+        Format::CreateJs => atlas.to_createjs(&opt.extension),
+        Format::SpriteKit => unreachable!("spritekit atlases are folders"),
+        Format::Bundle => unreachable!("bundles are only written to files"),
+    })
+}
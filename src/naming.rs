@@ -0,0 +1,174 @@
+use crate::Opt;
+use path_slash::PathExt;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Processing flags given by `~flag` suffixes at the end of the file stem of an input, such as
+/// `sword~notrim.png`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Flags {
+    /// `~notrim`: the image isn't trimmed, even with `--trim`
+    pub notrim: bool,
+    /// `~nopack`: the file is copied next to the atlas as it is, instead of being packed
+    pub nopack: bool,
+    /// `~alias=name`: other names the sprite is listed under, in the same folder
+    pub aliases: Vec<String>,
+}
+
+/// The flags on the file name of `path`
+pub fn flags(path: &Path) -> Flags {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(|stem| split_flags(stem).1)
+        .unwrap_or_default()
+}
+
+/// The file name of `path` without its flags, e.g. `logo.png` for `logo~nopack.png`
+pub fn file_name_without_flags(path: &Path) -> Option<OsString> {
+    let stem = path.file_stem()?;
+    let mut name = match stem.to_str() {
+        Some(stem) => OsString::from(split_flags(stem).0),
+        None => stem.to_owned(),
+    };
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    Some(name)
+}
+
+/// Splits the known flags off the end of `stem`, returning the rest of it and the flags. Any
+/// other `~` is part of the name.
+fn split_flags(stem: &str) -> (&str, Flags) {
+    let mut flags = Flags::default();
+    let mut rest = stem;
+    while let Some(tilde) = rest.rfind('~') {
+        match &rest[tilde + 1..] {
+            "notrim" => flags.notrim = true,
+            "nopack" => flags.nopack = true,
+            flag => match flag.strip_prefix("alias=") {
+                Some(alias) if !alias.is_empty() => flags.aliases.insert(0, alias.to_owned()),
+                _ => break,
+            },
+        }
+        rest = &rest[..tilde];
+    }
+    (rest, flags)
+}
+
+/// The name of the sprite loaded from the image at `path`, following the naming options:
+/// the path relative to `--basepath`, without its extension, flags, `--input-scale-suffix` and
+/// `--strip-prefix`, with its folders joined by `--name-separator`, or just the file stem with
+/// `--flatten`.
+pub fn sprite_name(path: &Path, opt: &Opt) -> String {
+    name_with_stem(path, None, opt)
+}
+
+/// The names given to the sprite loaded from the image at `path` by its `~alias=` flags,
+/// following the same naming options as `sprite_name`
+pub fn alias_names(path: &Path, opt: &Opt) -> Vec<String> {
+    flags(path)
+        .aliases
+        .iter()
+        .map(|alias| name_with_stem(path, Some(alias), opt))
+        .collect()
+}
+
+/// The name of the sprite loaded from `path`, with its file stem replaced by `stem` if given
+fn name_with_stem(path: &Path, stem: Option<&str>, opt: &Opt) -> String {
+    let path = match &opt.basepath {
+        Some(base) => relative_to(path, base),
+        None => path.to_path_buf(),
+    };
+    let mut path = path.with_extension("");
+    let file_stem = path.file_name().and_then(|name| name.to_str()).map(|name| {
+        let name = stem.unwrap_or_else(|| split_flags(name).0);
+        match scale_suffix(name) {
+            Some((name, _)) if opt.input_scale_suffix => name.to_owned(),
+            _ => name.to_owned(),
+        }
+    });
+    if let Some(file_stem) = file_stem {
+        path.set_file_name(file_stem);
+    }
+    let path = match &opt.strip_prefix {
+        Some(prefix) => path.strip_prefix(prefix).unwrap_or(&path),
+        None => &path,
+    };
+
+    let name = if opt.flatten {
+        path.file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    } else {
+        path.to_slash_lossy().into_owned()
+    };
+
+    name.split('/')
+        .map(|part| {
+            if opt.trim_numbers {
+                trim_number(part)
+            } else {
+                part
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(&opt.name_separator)
+}
+
+/// The pixel density given by an `@2x`-style suffix on the file name of `path`, if it has one
+pub fn density(path: &Path) -> Option<f32> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| scale_suffix(split_flags(stem).0))
+        .map(|(_, density)| density)
+}
+
+/// Splits an `@2x`-style suffix (`@1.5x`, `@3x`...) off `stem`, returning the rest of it and
+/// the density
+fn scale_suffix(stem: &str) -> Option<(&str, f32)> {
+    let at = stem.rfind('@')?;
+    let density = stem[at + 1..].strip_suffix('x')?.parse::<f32>().ok()?;
+    if at == 0 || !density.is_finite() || density <= 0.0 {
+        return None;
+    }
+    Some((&stem[..at], density))
+}
+
+/// The first of `name-2`, `name-3`... that isn't `taken`
+pub fn free_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    (2..)
+        .map(|n| format!("{}-{}", name, n))
+        .find(|name| !taken(name))
+        .expect("ran out of names")
+}
+
+/// `path` relative to the folder `base`, however either of them was written, or `path` itself
+/// if it isn't inside `base`
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    if let (Ok(absolute), Ok(base)) = (path.canonicalize(), base.canonicalize()) {
+        if let Ok(relative) = absolute.strip_prefix(&base) {
+            return relative.to_path_buf();
+        }
+    }
+    log::warn!(
+        "{} is outside of the base path {}",
+        path.display(),
+        base.display()
+    );
+    path.to_path_buf()
+}
+
+/// Removes a leading number used for ordering, e.g. `01_` in `01_idle`, unless that would
+/// leave nothing
+fn trim_number(part: &str) -> &str {
+    let rest = part.trim_start_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == part.len() {
+        return part;
+    }
+    let rest = rest.trim_start_matches(|c| c == '_' || c == '-' || c == '.' || c == ' ');
+    if rest.is_empty() {
+        part
+    } else {
+        rest
+    }
+}
@@ -0,0 +1,82 @@
+//! Finds pairs of sprites that look almost the same, for `--near-duplicates`, so that the
+//! source images can be cleaned up even when they aren't merged.
+
+use crate::image_wrapper::ImageWrapper;
+use crate::Opt;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+/// Two sprites and the fraction of their pixels that match
+pub struct Pair<'a> {
+    pub first: &'a str,
+    pub second: &'a str,
+    pub similarity: f32,
+}
+
+/// Every pair of `images` where at least `min_similarity` (from 0 to 1) of the pixels match,
+/// most similar first. Pixels match if none of their channels differ by more than `max_diff`.
+///
+/// Sprites are compared after trimming, so only sprites of the same trimmed size can match.
+pub fn find(images: &[ImageWrapper], max_diff: u8, min_similarity: f32) -> Vec<Pair> {
+    let mut by_size: BTreeMap<(i32, i32), Vec<&ImageWrapper>> = BTreeMap::new();
+    for image in images {
+        by_size
+            .entry((image.width, image.height))
+            .or_default()
+            .push(image);
+    }
+
+    let mut pairs: Vec<Pair> = by_size
+        .par_iter()
+        .flat_map_iter(|(_, images)| {
+            images
+                .iter()
+                .copied()
+                .enumerate()
+                .flat_map(move |(idx, first)| {
+                    images[idx + 1..].iter().copied().filter_map(move |second| {
+                        let similarity = first.similarity(second, max_diff);
+                        if similarity >= min_similarity {
+                            Some(Pair {
+                                first: &first.name,
+                                second: &second.name,
+                                similarity,
+                            })
+                        } else {
+                            None
+                        }
+                    })
+                })
+        })
+        .collect();
+    pairs.sort_by(|a, b| {
+        b.similarity
+            .partial_cmp(&a.similarity)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.first.cmp(b.first))
+            .then_with(|| a.second.cmp(b.second))
+    });
+    pairs
+}
+
+/// Prints the pairs of `images` that are at least `percent` similar, with pixels matching if
+/// they're within `--unique-fuzzy` of each other
+pub fn print(opt: &Opt, images: &[ImageWrapper], percent: u8) {
+    let max_diff = opt.unique_fuzzy.unwrap_or(0);
+    let pairs = find(images, max_diff, f32::from(percent) / 100.0);
+    reportln!(
+        opt,
+        "{} pair(s) of sprites are at least {}% similar",
+        pairs.len(),
+        percent
+    );
+    for pair in pairs {
+        reportln!(
+            opt,
+            "{} ~ {}: {:.2}%",
+            pair.first,
+            pair.second,
+            pair.similarity * 100.0
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! The hash of the options an atlas is packed with, stored along with the atlas to tell
+//! whether it's up to date and whether its layout can be reused.
+
+use crate::{animation, config, hdr, packer, page_name, reserve, scale, slice};
+use crate::{Format, FreeRectChoiceHeuristic, Opt, RetryOverflow, Shrink};
+use metrohash::MetroHash;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+/// The options that change how an atlas is packed, or which files are written and what's in
+/// them. Options that only change what's reported or logged are left out, so that passing one
+/// of them doesn't repack the atlas.
+#[derive(Hash)]
+struct PackingOptions<'a> {
+    default: bool,
+    xml: bool,
+    binary: bool,
+    json: bool,
+    format: &'a [Format],
+    stdout: Option<Format>,
+    long_keys: bool,
+    meta: bool,
+    checksums: bool,
+    sources: bool,
+    stable_indices: bool,
+    premultiply: bool,
+    alpha_variants: bool,
+    trim: bool,
+    crop: bool,
+    trim_margin: u8,
+    debug_image: bool,
+    debug_outlines: bool,
+    archive: &'a Option<PathBuf>,
+    unique: bool,
+    unique_fuzzy: Option<u8>,
+    aliases: bool,
+    rotate: bool,
+    rotation: packer::Rotation,
+    rotation_penalty: u16,
+    size: u16,
+    pad: u8,
+    pad_fill: packer::PadFill,
+    extrude: u8,
+    align: u16,
+    reserve: &'a [reserve::Reserve],
+    scale: &'a scale::Scale,
+    input_scale_suffix: bool,
+    keep_together: &'a [glob::Pattern],
+    priority: &'a [glob::Pattern],
+    heuristic: FreeRectChoiceHeuristic,
+    retry_overflow: Option<RetryOverflow>,
+    shrink: Shrink,
+    fixed_size: bool,
+    pot: bool,
+    multiple_of: Option<u16>,
+    tile_oversized: bool,
+    min_sprite_size: Option<u16>,
+    max_sprite_size: Option<u16>,
+    skip_bad_inputs: bool,
+    fail_fast: bool,
+    extension: &'a str,
+    quality: Option<u8>,
+    tonemap: hdr::Tonemap,
+    page_name: &'a page_name::PageName,
+    animations: bool,
+    animation_suffix: &'a animation::SuffixPattern,
+    animations_from_folders: bool,
+    aseprite_layers: bool,
+    aseprite_tags: bool,
+    gif_animations: bool,
+    psd_layers: bool,
+    slice: &'a [slice::Slice],
+    strip: &'a [slice::Strip],
+    flatten: bool,
+    basepath: &'a Option<PathBuf>,
+    strip_prefix: &'a Option<PathBuf>,
+    rename_duplicates: bool,
+    name_separator: &'a str,
+    trim_numbers: bool,
+    output: &'a Option<PathBuf>,
+    include: &'a [glob::Pattern],
+    follow_symlinks: bool,
+    hidden: bool,
+    max_depth: Option<usize>,
+    split_by_folder: bool,
+    inputs: &'a [PathBuf],
+    folders: &'a [config::FolderOverride],
+}
+
+/// Hashes the options of `opt` that change what's packed and written, along with the version
+/// of impact, since upgrading it can change that too
+pub fn options_hash(opt: &Opt) -> u64 {
+    // Every option is named, so that a new one can't be added without deciding whether it
+    // belongs in the hash
+    let Opt {
+        // The config file's settings are applied to the other options
+        config: _,
+        // Only change what's logged or reported, or whether to pack at all. `--check` must
+        // also produce the same hash as the run that wrote the atlas.
+        verbose: _,
+        quiet: _,
+        log_file: _,
+        no_log_file: _,
+        log_format: _,
+        force: _,
+        paranoid: _,
+        cache_dir: _,
+        dry_run: _,
+        print_plan: _,
+        stats: _,
+        stats_file: _,
+        report: _,
+        near_duplicates: _,
+        timings: _,
+        check: _,
+        watch: _,
+        cmd: _,
+        default,
+        xml,
+        binary,
+        json,
+        format,
+        stdout,
+        long_keys,
+        meta,
+        checksums,
+        sources,
+        stable_indices,
+        premultiply,
+        alpha_variants,
+        trim,
+        crop,
+        trim_margin,
+        debug_image,
+        debug_outlines,
+        archive,
+        unique,
+        unique_fuzzy,
+        aliases,
+        rotate,
+        rotation,
+        rotation_penalty,
+        size,
+        pad,
+        pad_fill,
+        extrude,
+        align,
+        reserve,
+        scale,
+        input_scale_suffix,
+        keep_together,
+        priority,
+        heuristic,
+        retry_overflow,
+        shrink,
+        fixed_size,
+        pot,
+        multiple_of,
+        tile_oversized,
+        min_sprite_size,
+        max_sprite_size,
+        skip_bad_inputs,
+        fail_fast,
+        extension,
+        quality,
+        tonemap,
+        page_name,
+        animations,
+        animation_suffix,
+        animations_from_folders,
+        aseprite_layers,
+        aseprite_tags,
+        gif_animations,
+        psd_layers,
+        slice,
+        strip,
+        flatten,
+        basepath,
+        strip_prefix,
+        rename_duplicates,
+        name_separator,
+        trim_numbers,
+        output,
+        include,
+        follow_symlinks,
+        hidden,
+        max_depth,
+        split_by_folder,
+        inputs,
+        folders,
+    } = opt;
+
+    let options = PackingOptions {
+        default: *default,
+        xml: *xml,
+        binary: *binary,
+        json: *json,
+        format,
+        stdout: *stdout,
+        long_keys: *long_keys,
+        meta: *meta,
+        checksums: *checksums,
+        sources: *sources,
+        stable_indices: *stable_indices,
+        premultiply: *premultiply,
+        alpha_variants: *alpha_variants,
+        trim: *trim,
+        crop: *crop,
+        trim_margin: *trim_margin,
+        debug_image: *debug_image,
+        debug_outlines: *debug_outlines,
+        archive,
+        unique: *unique,
+        unique_fuzzy: *unique_fuzzy,
+        aliases: *aliases,
+        rotate: *rotate,
+        rotation: *rotation,
+        rotation_penalty: *rotation_penalty,
+        size: *size,
+        pad: *pad,
+        pad_fill: *pad_fill,
+        extrude: *extrude,
+        align: *align,
+        reserve,
+        scale,
+        input_scale_suffix: *input_scale_suffix,
+        keep_together,
+        priority,
+        heuristic: *heuristic,
+        retry_overflow: *retry_overflow,
+        shrink: *shrink,
+        fixed_size: *fixed_size,
+        pot: *pot,
+        multiple_of: *multiple_of,
+        tile_oversized: *tile_oversized,
+        min_sprite_size: *min_sprite_size,
+        max_sprite_size: *max_sprite_size,
+        skip_bad_inputs: *skip_bad_inputs,
+        fail_fast: *fail_fast,
+        extension,
+        quality: *quality,
+        tonemap: *tonemap,
+        page_name,
+        animations: *animations,
+        animation_suffix,
+        animations_from_folders: *animations_from_folders,
+        aseprite_layers: *aseprite_layers,
+        aseprite_tags: *aseprite_tags,
+        gif_animations: *gif_animations,
+        psd_layers: *psd_layers,
+        slice,
+        strip,
+        flatten: *flatten,
+        basepath,
+        strip_prefix,
+        rename_duplicates: *rename_duplicates,
+        name_separator,
+        trim_numbers: *trim_numbers,
+        output,
+        include,
+        follow_symlinks: *follow_symlinks,
+        hidden: *hidden,
+        max_depth: *max_depth,
+        split_by_folder: *split_by_folder,
+        inputs,
+        folders,
+    };
+
+    let mut hasher = MetroHash::default();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    options.hash(&mut hasher);
+    hasher.finish()
+}
@@ -1,24 +1,186 @@
 use crate::bin_packs::max_rects::{FreeRectChoiceHeuristic, MaxRectsBinPack};
 use crate::error::Result;
-use crate::image_wrapper::ImageWrapper;
+use crate::font;
+use crate::hdr;
+use crate::image_wrapper::{
+    copy_rotated_row, premultiply_hdr_pixels, premultiply_pixels, ImageWrapper,
+};
+use crate::rect::Rect;
+use image::{Rgba, Rgba32FImage, RgbaImage};
 use metrohash::MetroHashMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::{Duration, Instant};
 
+/// The number of rows composited at a time when streaming a page to a PNG
+const BAND_HEIGHT: i32 = 64;
+
+/// The speed of the AVIF encoder, from 0 (slowest, smallest files) to 10
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 4;
+
+/// The colors of the page maps drawn by `Packer::debug_image`
+const DEBUG_FREE: Rgba<u8> = Rgba([32, 32, 48, 255]);
+const DEBUG_FREE_OUTLINE: Rgba<u8> = Rgba([220, 40, 40, 255]);
+const DEBUG_USED: Rgba<u8> = Rgba([40, 160, 60, 255]);
+const DEBUG_PADDING: Rgba<u8> = Rgba([230, 200, 40, 255]);
+const DEBUG_RESERVED: Rgba<u8> = Rgba([110, 110, 120, 255]);
+
+/// The colors the sprites are outlined in by `Packer::outline_image`, in turn
+const OUTLINE_COLORS: [Rgba<u8>; 6] = [
+    Rgba([255, 64, 64, 255]),
+    Rgba([64, 220, 64, 255]),
+    Rgba([64, 128, 255, 255]),
+    Rgba([255, 220, 32, 255]),
+    Rgba([255, 64, 255, 255]),
+    Rgba([32, 230, 230, 255]),
+];
+const LABEL_BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// How long saving a page took, split between drawing its sprites and encoding the image
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SaveTimings {
+    pub compositing: Duration,
+    /// Includes writing the file, which PNG pages are streamed into as they're encoded
+    pub encoding: Duration,
+}
+
+/// Which way images are turned by 90 degrees when they're rotated to fit, since engines assume
+/// different conventions
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    /// Clockwise
+    Cw,
+    /// Counter-clockwise
+    Ccw,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation::Cw
+    }
+}
+
+impl std::str::FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match &*s.to_ascii_lowercase() {
+            "cw" => Ok(Rotation::Cw),
+            "ccw" => Ok(Rotation::Ccw),
+            _ => Err(format!("unknown rotation: {}", s)),
+        }
+    }
+}
+
+impl Rotation {
+    pub fn is_cw(&self) -> bool {
+        *self == Rotation::Cw
+    }
+}
+
+/// What the padding after every image is filled with
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum PadFill {
+    /// Transparent black
+    Transparent,
+    /// The nearest edge pixels of the image, as if it was extruded into its padding
+    Clamp,
+    /// A solid color, e.g. to make the padding visible
+    Color([u8; 4]),
+}
+
+impl Default for PadFill {
+    fn default() -> Self {
+        PadFill::Transparent
+    }
+}
+
+impl std::str::FromStr for PadFill {
+    type Err = String;
+
+    /// Parses `transparent`, `clamp`, or a color given as `#rrggbb` or `#rrggbbaa`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match &*s.to_ascii_lowercase() {
+            "transparent" => Ok(PadFill::Transparent),
+            "clamp" => Ok(PadFill::Clamp),
+            color => {
+                let hex = color.trim_start_matches('#');
+                let channel = |idx: usize| {
+                    hex.get(idx * 2..idx * 2 + 2)
+                        .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+                };
+                match (hex.len(), channel(0), channel(1), channel(2)) {
+                    (6, Some(r), Some(g), Some(b)) => Ok(PadFill::Color([r, g, b, 255])),
+                    (8, Some(r), Some(g), Some(b)) => match channel(3) {
+                        Some(a) => Ok(PadFill::Color([r, g, b, a])),
+                        None => Err(format!("unknown padding fill: {}", s)),
+                    },
+                    _ => Err(format!("unknown padding fill: {}", s)),
+                }
+            }
+        }
+    }
+}
+
+impl std::convert::TryFrom<String> for PadFill {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PadFill> for String {
+    fn from(fill: PadFill) -> String {
+        match fill {
+            PadFill::Transparent => "transparent".to_owned(),
+            PadFill::Clamp => "clamp".to_owned(),
+            PadFill::Color([r, g, b, a]) => format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a),
+        }
+    }
+}
+
+/// Where an image was placed in a page
 #[derive(Debug, Clone)]
 pub struct Point {
     pub x: i32,
     pub y: i32,
+    /// The index of the image this one is a duplicate of, or -1
     pub dup_id: i32,
+    /// Whether the image was rotated 90 degrees, in the direction of the page's `rotation`
     pub rot: bool,
 }
 
+/// A single page of the atlas
 pub struct Packer {
+    /// The size of the page, halved for as long as every image still fits once packed
     pub width: i32,
     pub height: i32,
+    /// The space left to the right of and below every image, unless the image has its own
     pub pad: i32,
+    /// Which way rotated images are turned
+    pub rotation: Rotation,
+    /// What the padding after every image is filled with
+    pub pad_fill: PadFill,
+    /// Images are only placed at multiples of this, with their extruded edges before it
+    pub align: i32,
+    /// How many pixels worse a rotated placement has to fit than an unrotated one
+    pub rotation_penalty: i32,
+    /// Areas of the page that are left empty
+    pub reserved: Vec<Rect>,
 
+    /// The images packed into the page, in the same order as their `points`
     pub images: Vec<ImageWrapper>,
     pub points: Vec<Point>,
+    /// Maps image hashes to the index of the first image with that hash
     pub dup_lookup: MetroHashMap<u64, usize>,
+    /// Images are also duplicates of a packed image if none of their channels differ from it by
+    /// more than this
+    pub max_diff: u8,
 }
 
 impl Packer {
@@ -27,24 +189,44 @@ impl Packer {
             width,
             height,
             pad,
+            rotation: Rotation::default(),
+            pad_fill: PadFill::default(),
+            align: 1,
+            rotation_penalty: 0,
+            reserved: vec![],
 
             images: vec![],
             points: vec![],
             dup_lookup: MetroHashMap::default(),
+            max_diff: 0,
         }
     }
 
+    /// Packs as many of `images` as fit into the page, taking them from the end of the vector.
+    ///
+    /// Any images that don't fit are left in `images`, ready to be packed into another page.
+    /// Images in the same `group` are all packed into the page, or none of them are.
+    /// `on_image` is called after each image is placed.
     pub fn pack(
         &mut self,
         images: &mut Vec<ImageWrapper>,
         unique: bool,
         rotate: bool,
         method: FreeRectChoiceHeuristic,
+        on_image: &mut dyn FnMut(),
     ) {
-        let mut packer = MaxRectsBinPack::new(self.width, self.height);
+        let mut packer = MaxRectsBinPack::new(self.width, self.height)
+            .with_align(self.align)
+            .with_rotation_penalty(self.rotation_penalty);
 
+        // The page never shrinks past its reserved areas
         let mut ww = 0;
         let mut hh = 0;
+        for rect in &self.reserved {
+            packer.place_rect(rect);
+            ww = std::cmp::max(rect.x + rect.width, ww);
+            hh = std::cmp::max(rect.y + rect.height, hh);
+        }
 
         log::info!("packing begin...");
 
@@ -53,85 +235,619 @@ impl Packer {
 
             log::info!("{}: {}", images.len(), image.name);
 
-            if unique {
-                if self.dup_lookup.contains_key(&image.hash_value) {
-                    let idx = self.dup_lookup[&image.hash_value];
-                    if image == self.images[idx] {
-                        let mut p = self.points[idx].clone();
-                        p.dup_id = idx as i32;
-                        self.points.push(p);
-                        self.images.push(image);
-
-                        log::info!("duplicate found");
+            // Take the rest of the image's group along with it, largest first
+            let mut group = vec![];
+            if let Some(id) = image.group {
+                let (members, rest): (Vec<_>, Vec<_>) =
+                    images.drain(..).partition(|other| other.group == Some(id));
+                *images = rest;
+                group.extend(members.into_iter().rev());
+            }
+            group.insert(0, image);
 
-                        continue;
+            let len = self.images.len();
+            let mut extent = (ww, hh);
+            let mut group = group.into_iter();
+            let mut failed = None;
+            for image in &mut group {
+                match self.place(&mut packer, image, unique, rotate, method) {
+                    Ok(Some(rect)) => {
+                        extent.0 = std::cmp::max(rect.x + rect.width, extent.0);
+                        extent.1 = std::cmp::max(rect.y + rect.height, extent.1);
+                    }
+                    Ok(None) => {}
+                    Err(image) => {
+                        failed = Some(image);
+                        break;
                     }
                 }
             }
 
-            // If it's not a duplicate, pack it into the atlas
-            {
-                let rect = packer.insert(
-                    image.width + self.pad,
-                    image.height + self.pad,
-                    rotate,
-                    method,
-                );
-
-                if rect.width == 0 || rect.height == 0 {
-                    images.push(image);
-                    break;
-                }
-
-                if unique {
-                    self.dup_lookup.insert(image.hash_value, self.points.len());
-                }
-
-                // Check if we rotated it
-                let p = Point {
-                    x: rect.x,
-                    y: rect.y,
-                    dup_id: -1,
-                    rot: rotate && image.width != (rect.width - self.pad),
-                };
+            if let Some(image) = failed {
+                // Put the whole group back for the next page, undoing any of it placed here.
+                // Nothing else is packed into this page, so its free space can stay as is.
+                self.dup_lookup.retain(|_, idx| *idx < len);
+                self.points.truncate(len);
+                let mut rest: Vec<_> = self.images.drain(len..).collect();
+                rest.push(image);
+                rest.extend(group);
+                rest.sort_by_key(|image| image.width * image.height);
+                images.append(&mut rest);
+                break;
+            }
 
-                self.points.push(p);
-                self.images.push(image);
+            for _ in len..self.images.len() {
+                on_image();
+            }
+            ww = extent.0;
+            hh = extent.1;
+        }
 
-                ww = std::cmp::max(rect.x + rect.width, ww);
-                hh = std::cmp::max(rect.y + rect.height, hh);
+        // Duplicates take no space, so any that are left over join the images they duplicate
+        // rather than being packed again into another page. Images are only matched on their
+        // trimmed pixels, so each duplicate keeps its own frame.
+        if unique && !images.is_empty() {
+            let (duplicates, rest): (Vec<_>, Vec<_>) = images
+                .drain(..)
+                .partition(|image| image.group.is_none() && self.find_duplicate(image).is_some());
+            *images = rest;
+            for image in duplicates {
+                let idx = self.find_duplicate(&image).unwrap();
+                self.push_duplicate(image, idx);
+                on_image();
             }
         }
 
         log::info!("packing complete. resizing...");
 
-        while self.width / 2 >= ww {
+        // An empty page (when the first image doesn't fit) would otherwise shrink forever
+        while self.width > 1 && self.width / 2 >= ww {
             self.width /= 2;
         }
-        while self.height / 2 >= hh {
+        while self.height > 1 && self.height / 2 >= hh {
             self.height /= 2;
         }
     }
 
-    pub fn save_png<P: AsRef<std::path::Path>>(&self, file: P) -> Result<()> {
-        let mut img = ImageWrapper::empty(self.width, self.height);
-        for i in 0..self.images.len() {
-            if self.points[i].dup_id < 0 {
-                if self.points[i].rot {
-                    img.copy_pixels_rot(&self.images[i], self.points[i].x, self.points[i].y);
+    /// Places a single image in the page, returning the area it takes up (including its
+    /// padding), nothing if it's a duplicate, or the image itself if it doesn't fit
+    fn place(
+        &mut self,
+        packer: &mut MaxRectsBinPack,
+        image: ImageWrapper,
+        unique: bool,
+        rotate: bool,
+        method: FreeRectChoiceHeuristic,
+    ) -> std::result::Result<Option<Rect>, ImageWrapper> {
+        if unique {
+            if let Some(idx) = self.find_duplicate(&image) {
+                self.push_duplicate(image, idx);
+                return Ok(None);
+            }
+        }
+
+        // The extruded border goes on every side, with the padding after it
+        let pad = image.pad.unwrap_or(self.pad);
+        let border = 2 * image.extrude + pad;
+        // The image itself is aligned, rather than the extruded border before it
+        packer.set_align_offset(image.extrude);
+        let rect = packer.insert(image.width + border, image.height + border, rotate, method);
+
+        if rect.width == 0 || rect.height == 0 {
+            return Err(image);
+        }
+
+        if unique {
+            self.dup_lookup.insert(image.hash_value, self.points.len());
+        }
+
+        // Check if we rotated it
+        let p = Point {
+            x: rect.x + image.extrude,
+            y: rect.y + image.extrude,
+            dup_id: -1,
+            rot: rotate && image.width != (rect.width - border),
+        };
+
+        self.points.push(p);
+        self.images.push(image);
+        Ok(Some(rect))
+    }
+
+    /// The index of the packed image with the same trimmed pixels as `image` (or nearly the
+    /// same, with `max_diff`), if any
+    fn find_duplicate(&self, image: &ImageWrapper) -> Option<usize> {
+        let exact = self
+            .dup_lookup
+            .get(&image.hash_value)
+            .cloned()
+            .filter(|idx| {
+                *image == self.images[*idx] && image.extrude == self.images[*idx].extrude
+            });
+        if exact.is_some() || self.max_diff == 0 {
+            return exact;
+        }
+        // Nearly identical images don't share a hash, so they're compared with every image
+        self.images
+            .iter()
+            .zip(self.points.iter())
+            .position(|(other, p)| {
+                p.dup_id < 0
+                    && image.extrude == other.extrude
+                    && image.is_near(other, self.max_diff)
+            })
+    }
+
+    /// Places `image` at the same position as the packed image at `idx`
+    fn push_duplicate(&mut self, image: ImageWrapper, idx: usize) {
+        let mut p = self.points[idx].clone();
+        p.dup_id = idx as i32;
+        self.points.push(p);
+        self.images.push(image);
+
+        log::info!("duplicate found");
+    }
+
+    /// The fraction of the page covered by packed images
+    pub fn occupancy(&self) -> f32 {
+        let used_area: i32 = self
+            .images
+            .iter()
+            .zip(self.points.iter())
+            .filter(|(_, p)| p.dup_id < 0)
+            .map(|(image, _)| image.width * image.height)
+            .sum();
+        (used_area as f32) / ((self.width * self.height) as f32)
+    }
+
+    /// Draws a map of how the page is used: sprites in green, their extruded border and padding
+    /// in yellow, reserved areas in grey, and the free space in dark blue, with the outline of
+    /// every free rectangle the packer would have left in red
+    pub fn debug_image(&self) -> RgbaImage {
+        let mut img = RgbaImage::from_pixel(self.width as u32, self.height as u32, DEBUG_FREE);
+        let page = Rect {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        };
+        let mut packer = MaxRectsBinPack::new(self.width, self.height);
+        for rect in &self.reserved {
+            fill_rect(&mut img, &clip(rect, &page), DEBUG_RESERVED);
+            packer.place_rect(rect);
+        }
+        for (image, p) in self.images.iter().zip(self.points.iter()) {
+            if p.dup_id >= 0 {
+                continue;
+            }
+            let (w, h) = if p.rot {
+                (image.height, image.width)
+            } else {
+                (image.width, image.height)
+            };
+            let e = image.extrude;
+            let pad = image.pad.unwrap_or(self.pad);
+            let outer = Rect {
+                x: p.x - e,
+                y: p.y - e,
+                width: w + 2 * e + pad,
+                height: h + 2 * e + pad,
+            };
+            let outer = clip(&outer, &page);
+            fill_rect(&mut img, &outer, DEBUG_PADDING);
+            let inner = Rect {
+                x: p.x,
+                y: p.y,
+                width: w,
+                height: h,
+            };
+            fill_rect(&mut img, &inner, DEBUG_USED);
+            packer.place_rect(&outer);
+        }
+        for rect in packer.free_rectangles() {
+            outline_rect(&mut img, rect, DEBUG_FREE_OUTLINE);
+        }
+        img
+    }
+
+    /// Composites the page with a border around every sprite, just outside its rectangle, and
+    /// its name in its top-left corner
+    pub fn outline_image(&self) -> RgbaImage {
+        let mut img = self.composite_image(false);
+        let page = Rect {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        };
+        let sprites = self
+            .images
+            .iter()
+            .zip(self.points.iter())
+            .filter(|(_, p)| p.dup_id < 0);
+        for (idx, (image, p)) in sprites.enumerate() {
+            let color = OUTLINE_COLORS[idx % OUTLINE_COLORS.len()];
+            let (w, h) = if p.rot {
+                (image.height, image.width)
+            } else {
+                (image.width, image.height)
+            };
+            let border = Rect {
+                x: p.x - 1,
+                y: p.y - 1,
+                width: w + 2,
+                height: h + 2,
+            };
+            outline_rect(&mut img, &clip(&border, &page), color);
+
+            let sprite = Rect {
+                x: p.x,
+                y: p.y,
+                width: w,
+                height: h,
+            };
+            let label = Rect {
+                x: p.x,
+                y: p.y,
+                width: image.name.chars().count() as i32 * font::ADVANCE + 1,
+                height: font::GLYPH_HEIGHT + 2,
+            };
+            fill_rect(&mut img, &clip(&label, &sprite), LABEL_BACKGROUND);
+            font::draw_text(&mut img, p.x + 1, p.y + 1, &image.name, color, &sprite);
+        }
+        img
+    }
+
+    /// Composites the page and encodes it as a PNG in memory
+    pub fn encode_png(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.write_png(&mut bytes, None, false)?;
+        Ok(bytes)
+    }
+
+    /// Composites the page a band of rows at a time, streaming each band straight into the
+    /// PNG encoder so that the whole page never has to be held in memory. The time spent
+    /// compositing is added to `compositing`, if given. With `premultiply`, the pixels are
+    /// premultiplied by their alpha.
+    fn write_png<W: Write>(
+        &self,
+        out: W,
+        mut compositing: Option<&mut Duration>,
+        premultiply: bool,
+    ) -> Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        let mut stream = writer.stream_writer()?;
+
+        let stride = self.width as usize * 4;
+        let pixels = self.pixels();
+        let mut band = vec![0; stride * BAND_HEIGHT as usize];
+        let mut top = 0;
+        while top < self.height {
+            let bottom = std::cmp::min(top + BAND_HEIGHT, self.height);
+            let band = &mut band[..stride * (bottom - top) as usize];
+            let start = compositing.as_ref().map(|_| Instant::now());
+            band.fill(0);
+            self.composite(top, bottom, band, &pixels, self.pad_color());
+            if premultiply {
+                premultiply_pixels(band);
+            }
+            if let (Some(compositing), Some(start)) = (compositing.as_mut(), start) {
+                **compositing += start.elapsed();
+            }
+            stream.write_all(band)?;
+            top = bottom;
+        }
+        stream.finish()?;
+
+        Ok(())
+    }
+
+    /// The 8-bit pixels of every packed image, in the same order as `images`
+    fn pixels(&self) -> Vec<&[u8]> {
+        self.images.iter().map(|image| &image.data[..]).collect()
+    }
+
+    /// The color the padding is filled with, if it's a solid one
+    fn pad_color(&self) -> Option<[u8; 4]> {
+        match self.pad_fill {
+            PadFill::Color(color) => Some(color),
+            _ => None,
+        }
+    }
+
+    /// Draws the rows from `top` to `bottom` of the page into `band`, which must be zeroed,
+    /// taking the pixels of every image from `pixels`. The padding is filled with `pad_color`,
+    /// if given.
+    fn composite<T: Copy>(
+        &self,
+        top: i32,
+        bottom: i32,
+        band: &mut [T],
+        pixels: &[&[T]],
+        pad_color: Option<[T; 4]>,
+    ) {
+        let stride = self.width as usize * 4;
+        for ((image, p), data) in self.images.iter().zip(self.points.iter()).zip(pixels) {
+            if p.dup_id >= 0 {
+                continue;
+            }
+            let (w, h) = if p.rot {
+                (image.height, image.width)
+            } else {
+                (image.width, image.height)
+            };
+            let e = image.extrude;
+            let pad = image.pad.unwrap_or(self.pad);
+
+            if let Some(color) = pad_color {
+                // The padding is the strip to the right of the extruded image, and the one below
+                let right = p.x + w + e;
+                let below = p.y + h + e;
+                for y in std::cmp::max(p.y - e, top)..std::cmp::min(below + pad, bottom) {
+                    let from = if y < below { right } else { p.x - e };
+                    for x in from..right + pad {
+                        let start = (y - top) as usize * stride + x as usize * 4;
+                        band[start..start + 4].copy_from_slice(&color);
+                    }
+                }
+            }
+
+            // Clamping extrudes the image into its padding too
+            let after = match self.pad_fill {
+                PadFill::Clamp => e + pad,
+                _ => e,
+            };
+            for y in std::cmp::max(p.y - e, top)..std::cmp::min(p.y + h + after, bottom) {
+                let start = (y - top) as usize * stride + p.x as usize * 4;
+                let row = &mut band[start..start + w as usize * 4];
+                // Rows of the extruded border repeat the nearest edge row
+                let sy = (y - p.y).max(0).min(h - 1);
+                if p.rot {
+                    copy_rotated_row(row, data, image.width, sy, self.rotation);
                 } else {
-                    img.copy_pixels(&self.images[i], self.points[i].x, self.points[i].y);
+                    let src = (sy * image.width) as usize * 4;
+                    row.copy_from_slice(&data[src..src + w as usize * 4]);
+                }
+
+                let left = start;
+                let right = start + (w - 1) as usize * 4;
+                for i in 1..=e as usize {
+                    band.copy_within(left..left + 4, left - i * 4);
+                }
+                for i in 1..=after as usize {
+                    band.copy_within(right..right + 4, right + i * 4);
                 }
             }
         }
-        img.save_as(file.as_ref())?;
+    }
+
+    /// Composites the whole page at once, premultiplying its pixels by their alpha if
+    /// `premultiply`
+    fn composite_image(&self, premultiply: bool) -> RgbaImage {
+        let mut data = vec![0; self.width as usize * self.height as usize * 4];
+        self.composite(0, self.height, &mut data, &self.pixels(), self.pad_color());
+        if premultiply {
+            premultiply_pixels(&mut data);
+        }
+        RgbaImage::from_raw(self.width as u32, self.height as u32, data)
+            .expect("page buffer has the size of the page")
+    }
+
+    /// Composites the whole page at once in linear colors, keeping the full range of HDR images
+    /// and decoding the sRGB colors of the others, premultiplying them by their alpha if
+    /// `premultiply`
+    fn composite_hdr_image(&self, premultiply: bool) -> Rgba32FImage {
+        let pixels: Vec<Cow<[f32]>> = self
+            .images
+            .iter()
+            .zip(self.points.iter())
+            .map(|(image, p)| match &image.hdr {
+                Some(hdr) => Cow::Borrowed(&hdr[..]),
+                // Duplicates aren't drawn
+                None if p.dup_id >= 0 => Cow::Borrowed(&[][..]),
+                None => Cow::Owned(
+                    image
+                        .data
+                        .chunks_exact(4)
+                        .flat_map(|pixel| {
+                            [
+                                hdr::from_srgb(pixel[0]),
+                                hdr::from_srgb(pixel[1]),
+                                hdr::from_srgb(pixel[2]),
+                                pixel[3] as f32 / 255.0,
+                            ]
+                        })
+                        .collect(),
+                ),
+            })
+            .collect();
+        let pixels: Vec<&[f32]> = pixels.iter().map(|pixels| &pixels[..]).collect();
+
+        let pad_color = self.pad_color().map(|[r, g, b, a]| {
+            [
+                hdr::from_srgb(r),
+                hdr::from_srgb(g),
+                hdr::from_srgb(b),
+                a as f32 / 255.0,
+            ]
+        });
+        let mut data = vec![0.0; self.width as usize * self.height as usize * 4];
+        self.composite(0, self.height, &mut data, &pixels, pad_color);
+        if premultiply {
+            premultiply_hdr_pixels(&mut data);
+        }
+        Rgba32FImage::from_raw(self.width as u32, self.height as u32, data)
+            .expect("page buffer has the size of the page")
+    }
+
+    /// Composites the page and saves it, in the image format given by the file extension
+    pub fn save_png<P: AsRef<std::path::Path>>(&self, file: P) -> Result<()> {
+        self.save_image(file, None)?;
+        Ok(())
+    }
+
+    /// Composites the page and saves it, in the image format given by the file extension. WebP
+    /// pages are lossy with the given `quality` (from 0 to 100), or lossless without one. AVIF
+    /// pages (with the `avif` feature) default to the highest quality, which is near-lossless.
+    /// EXR pages are saved in linear floats, keeping the full range of HDR images.
+    ///
+    /// Returns how long the page took to composite and to encode.
+    pub fn save_image<P: AsRef<std::path::Path>>(
+        &self,
+        file: P,
+        quality: Option<u8>,
+    ) -> Result<SaveTimings> {
+        self.save(file.as_ref(), quality, false)
+    }
+
+    /// Composites the page like `save_image`, but with its pixels premultiplied by their alpha,
+    /// for a premultiplied copy of a page whose images were loaded with straight alpha
+    pub fn save_premultiplied_image<P: AsRef<std::path::Path>>(
+        &self,
+        file: P,
+        quality: Option<u8>,
+    ) -> Result<SaveTimings> {
+        self.save(file.as_ref(), quality, true)
+    }
+
+    fn save(
+        &self,
+        file: &std::path::Path,
+        quality: Option<u8>,
+        premultiply: bool,
+    ) -> Result<SaveTimings> {
+        let start = Instant::now();
+        let mut timings = SaveTimings::default();
+        let extension = file
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => {
+                let out = std::io::BufWriter::new(std::fs::File::create(file)?);
+                self.write_png(out, Some(&mut timings.compositing), premultiply)?;
+            }
+            Some("webp") => {
+                use image::codecs::webp::{WebPEncoder, WebPQuality};
+                let quality = quality.map_or_else(WebPQuality::lossless, WebPQuality::lossy);
+                let out = std::io::BufWriter::new(std::fs::File::create(file)?);
+                let img = self.composite_image(premultiply);
+                timings.compositing = start.elapsed();
+                WebPEncoder::new_with_quality(out, quality).encode(
+                    &img,
+                    img.width(),
+                    img.height(),
+                    image::ColorType::Rgba8,
+                )?;
+            }
+            #[cfg(feature = "avif")]
+            Some("avif") => {
+                use image::codecs::avif::{AvifEncoder, ColorSpace};
+                use image::ImageEncoder;
+                let out = std::io::BufWriter::new(std::fs::File::create(file)?);
+                let img = self.composite_image(premultiply);
+                timings.compositing = start.elapsed();
+                AvifEncoder::new_with_speed_quality(out, AVIF_SPEED, quality.unwrap_or(100))
+                    .with_colorspace(ColorSpace::Srgb)
+                    .write_image(&img, img.width(), img.height(), image::ColorType::Rgba8)?;
+            }
+            Some("exr") => {
+                let img = self.composite_hdr_image(premultiply);
+                timings.compositing = start.elapsed();
+                img.save(file)?;
+            }
+            _ => {
+                let img = self.composite_image(premultiply);
+                timings.compositing = start.elapsed();
+                img.save(file)?;
+            }
+        }
+        timings.encoding = start.elapsed().saturating_sub(timings.compositing);
 
         {
             use humansize::{format_size, DECIMAL};
-            let size = std::fs::metadata(file.as_ref())?.len();
+            let size = std::fs::metadata(file)?.len();
             log::info!("saving atlas. image size: {}", format_size(size, DECIMAL));
         }
 
-        Ok(())
+        Ok(timings)
+    }
+}
+
+/// The part of `rect` inside `bounds`
+fn clip(rect: &Rect, bounds: &Rect) -> Rect {
+    let x = std::cmp::max(rect.x, bounds.x);
+    let y = std::cmp::max(rect.y, bounds.y);
+    let right = std::cmp::min(rect.x + rect.width, bounds.x + bounds.width);
+    let bottom = std::cmp::min(rect.y + rect.height, bounds.y + bounds.height);
+    Rect {
+        x,
+        y,
+        width: std::cmp::max(right - x, 0),
+        height: std::cmp::max(bottom - y, 0),
+    }
+}
+
+fn fill_rect(img: &mut RgbaImage, rect: &Rect, color: Rgba<u8>) {
+    for y in rect.y..rect.y + rect.height {
+        for x in rect.x..rect.x + rect.width {
+            img.put_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+fn outline_rect(img: &mut RgbaImage, rect: &Rect, color: Rgba<u8>) {
+    if rect.width == 0 || rect.height == 0 {
+        return;
+    }
+    let right = rect.x + rect.width - 1;
+    let bottom = rect.y + rect.height - 1;
+    for x in rect.x..=right {
+        img.put_pixel(x as u32, rect.y as u32, color);
+        img.put_pixel(x as u32, bottom as u32, color);
+    }
+    for y in rect.y..=bottom {
+        img.put_pixel(rect.x as u32, y as u32, color);
+        img.put_pixel(right as u32, y as u32, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_applies_to_the_image_rather_than_its_extruded_border() {
+        let mut images: Vec<_> = (0..6)
+            .map(|i| {
+                let mut image = ImageWrapper::new(
+                    RgbaImage::new(5 + i, 3 + i),
+                    format!("image{}", i),
+                    false,
+                    false,
+                    0,
+                    0,
+                );
+                image.extrude = 1;
+                image
+            })
+            .collect();
+        let mut packer = Packer::new(64, 64, 1);
+        packer.align = 4;
+        packer.pack(
+            &mut images,
+            false,
+            false,
+            FreeRectChoiceHeuristic::RectBestShortSideFit,
+            &mut || (),
+        );
+
+        assert!(images.is_empty());
+        assert_eq!(packer.points.len(), 6);
+        for point in &packer.points {
+            assert_eq!((point.x % 4, point.y % 4), (0, 0));
+        }
     }
 }
@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+/// A template for the names of atlas pages, e.g. `{name}_{index:02}`.
+///
+/// `{name}` is replaced by the output name and `{index}` by the page number, which
+/// can be zero-padded to a given width with `{index:0N}`.
+#[derive(Debug, Clone, Hash)]
+pub struct PageName {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, Hash)]
+enum Part {
+    Literal(String),
+    Name,
+    Index { width: usize },
+}
+
+impl PageName {
+    pub fn format(&self, name: &str, index: usize) -> String {
+        let mut res = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(s) => res.push_str(s),
+                Part::Name => res.push_str(name),
+                Part::Index { width } => res.push_str(&format!("{:0width$}", index, width = width)),
+            }
+        }
+        res
+    }
+
+    /// A glob pattern matching the names of every page for the given output name
+    pub fn glob(&self, name: &str) -> String {
+        let mut res = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(s) => res.push_str(&glob::Pattern::escape(s)),
+                Part::Name => res.push_str(&glob::Pattern::escape(name)),
+                Part::Index { .. } => res.push('*'),
+            }
+        }
+        res
+    }
+}
+
+impl FromStr for PageName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut has_index = false;
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in page name: {}", s))?
+                + start;
+
+            let placeholder = &rest[start + 1..end];
+            let part = match placeholder {
+                "name" => Part::Name,
+                "index" => Part::Index { width: 0 },
+                _ if placeholder.starts_with("index:") => {
+                    let width = placeholder["index:".len()..]
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid index width in page name: {}", s))?;
+                    Part::Index { width }
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown placeholder in page name: {{{}}}",
+                        placeholder
+                    ))
+                }
+            };
+            if let Part::Index { .. } = part {
+                has_index = true;
+            }
+            parts.push(part);
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+
+        // Without the index, every page would be written to the same file
+        if !has_index {
+            return Err(format!("page name must contain {{index}}: {}", s));
+        }
+
+        Ok(Self { parts })
+    }
+}
@@ -0,0 +1,22 @@
+use crate::Opt;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+
+/// Creates a progress bar for a phase of the packing process.
+///
+/// The start of each phase is logged under the `impact::phase` target. The bar is hidden
+/// with `--quiet`, when log messages are being printed (`-v`), or when stderr isn't a terminal.
+pub fn bar(opt: &Opt, len: usize, message: &'static str) -> ProgressBar {
+    log::info!(target: "impact::phase", "{} ({} items)", message, len);
+
+    if opt.quiet || opt.verbose > 0 || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let style = ProgressStyle::with_template("{msg:>12} [{bar:40}] {pos}/{len} ({elapsed})")
+        .expect("invalid progress bar template")
+        .progress_chars("=> ");
+    let bar = ProgressBar::new(len as u64).with_style(style);
+    bar.set_message(message);
+    bar
+}
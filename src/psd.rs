@@ -0,0 +1,487 @@
+use crate::blend;
+use crate::error::{ImpactError, Result};
+use image::RgbaImage;
+use std::io::Read;
+use std::path::Path;
+
+const SIGNATURE: &[u8; 4] = b"8BPS";
+
+const MODE_GRAYSCALE: u16 = 1;
+const MODE_RGB: u16 = 3;
+
+/// Set in the flags of layers hidden in Photoshop
+const LAYER_HIDDEN: u8 = 2;
+
+/// The kinds of `lsct` section dividers, which mark the layer groups
+const SECTION_OPEN_FOLDER: u32 = 1;
+const SECTION_CLOSED_FOLDER: u32 = 2;
+const SECTION_BOUNDING: u32 = 3;
+
+/// Whether `path` has the extension of a Photoshop document
+pub fn is_psd_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("psd"))
+}
+
+/// A Photoshop document, as documented in
+/// <https://www.adobe.com/devnet-apps/photoshop/fileformatashtml/>.
+///
+/// Only 8-bit RGB and grayscale documents are supported. Layer masks, adjustment layers
+/// and blend modes other than normal are ignored.
+pub struct Psd {
+    width: u32,
+    height: u32,
+    /// The composited image saved along with the layers
+    merged: RgbaImage,
+    /// The top-level layers and groups, from bottom to top
+    layers: Vec<TopLevel>,
+}
+
+/// A layer at the root of the document, or a group along with every layer inside it
+struct TopLevel {
+    name: String,
+    visible: bool,
+    opacity: u8,
+    /// The layers to draw, from bottom to top
+    layers: Vec<Layer>,
+}
+
+struct Layer {
+    visible: bool,
+    opacity: u8,
+    x: i32,
+    y: i32,
+    image: RgbaImage,
+}
+
+/// A layer record, before it's sorted into the hierarchy
+struct Record {
+    name: String,
+    visible: bool,
+    opacity: u8,
+    section: u32,
+    layer: Layer,
+}
+
+impl Psd {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::parse(&bytes).map_err(|reason| ImpactError::InvalidPsd {
+            path: path.display().to_string(),
+            reason,
+        })
+    }
+
+    fn parse(bytes: &[u8]) -> std::result::Result<Self, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        // Header
+        if reader.take(4)? != SIGNATURE {
+            return Err("not a photoshop file".to_owned());
+        }
+        if reader.u16()? != 1 {
+            return Err("large documents (.psb) aren't supported".to_owned());
+        }
+        reader.skip(6)?;
+        let channels = reader.u16()? as usize;
+        let height = reader.u32()?;
+        let width = reader.u32()?;
+        let depth = reader.u16()?;
+        let mode = reader.u16()?;
+        if depth != 8 {
+            return Err(format!("unsupported bit depth {}", depth));
+        }
+        if mode != MODE_RGB && mode != MODE_GRAYSCALE {
+            return Err(format!("unsupported color mode {}", mode));
+        }
+
+        // Color mode data and image resources
+        let len = reader.u32()? as usize;
+        reader.skip(len)?;
+        let len = reader.u32()? as usize;
+        reader.skip(len)?;
+
+        // Layer and mask information
+        let len = reader.u32()? as usize;
+        let mut section = reader.sub(len)?;
+        let (records, merged_alpha) = if len > 0 {
+            read_layers(&mut section, mode)?
+        } else {
+            (vec![], false)
+        };
+
+        // Image data
+        let compression = reader.u16()?;
+        let merged_channels = if mode == MODE_RGB { 3 } else { 1 };
+        let has_alpha = channels > merged_channels || merged_alpha;
+        let planes = read_planes(
+            &mut reader,
+            compression,
+            channels,
+            width as usize,
+            height as usize,
+        )?;
+        let merged = to_rgba(width, height, mode, |channel| match channel {
+            -1 if has_alpha => planes.get(merged_channels).map(Vec::as_slice),
+            -1 => None,
+            channel => planes.get(channel as usize).map(Vec::as_slice),
+        });
+
+        Ok(Self {
+            width,
+            height,
+            merged,
+            layers: group(records),
+        })
+    }
+
+    /// Every sprite of the document, named after `name`.
+    ///
+    /// The document is flattened into a single sprite, unless `split_layers` is set, in which
+    /// case every visible top-level layer or group is its own sprite (`name/layer`).
+    pub fn sprites(&self, name: &str, split_layers: bool) -> Vec<(String, RgbaImage)> {
+        if !split_layers {
+            return vec![(name.to_owned(), self.merged.clone())];
+        }
+
+        self.layers
+            .iter()
+            .filter(|top_level| top_level.visible)
+            .map(|top_level| {
+                let mut image = RgbaImage::new(self.width, self.height);
+                let group_opacity = top_level.opacity as f32 / 255.0;
+                for layer in top_level.layers.iter().filter(|layer| layer.visible) {
+                    let opacity = layer.opacity as f32 / 255.0 * group_opacity;
+                    blend::over(&mut image, &layer.image, layer.x, layer.y, opacity);
+                }
+                (format!("{}/{}", name, top_level.name), image)
+            })
+            .collect()
+    }
+}
+
+/// Reads the layer records and their pixels, and whether the first alpha channel of the
+/// merged image holds its transparency
+fn read_layers(reader: &mut Reader, mode: u16) -> std::result::Result<(Vec<Record>, bool), String> {
+    let len = reader.u32()? as usize;
+    let mut reader = reader.sub(len)?;
+    if len == 0 {
+        return Ok((vec![], false));
+    }
+
+    let count = reader.u16()? as i16;
+    let merged_alpha = count < 0;
+
+    // The records come first, then the pixels of every layer in the same order
+    let mut headers = vec![];
+    for _ in 0..count.abs() {
+        let top = reader.u32()? as i32;
+        let left = reader.u32()? as i32;
+        let bottom = reader.u32()? as i32;
+        let right = reader.u32()? as i32;
+        let mut channels = vec![];
+        for _ in 0..reader.u16()? {
+            let id = reader.u16()? as i16;
+            let len = reader.u32()? as usize;
+            channels.push((id, len));
+        }
+        if reader.take(4)? != b"8BIM" {
+            return Err("invalid layer record".to_owned());
+        }
+        reader.skip(4)?;
+        let opacity = reader.u8()?;
+        reader.skip(1)?;
+        let flags = reader.u8()?;
+        reader.skip(1)?;
+
+        let extra_len = reader.u32()? as usize;
+        let mut extra = reader.sub(extra_len)?;
+        let len = extra.u32()? as usize;
+        extra.skip(len)?;
+        let len = extra.u32()? as usize;
+        extra.skip(len)?;
+        let name_len = extra.u8()? as usize;
+        let mut name = String::from_utf8_lossy(extra.take(name_len)?).into_owned();
+        // The name is padded to a multiple of 4 bytes, including its length
+        extra.skip((4 - (name_len + 1) % 4) % 4)?;
+
+        let mut section = 0;
+        while extra.pos + 12 <= extra.bytes.len() {
+            extra.skip(4)?;
+            let key = extra.take(4)?;
+            let len = extra.u32()? as usize;
+            let mut data = extra.sub(len)?;
+            match key {
+                b"luni" => {
+                    let chars = data.u32()? as usize;
+                    let units: Vec<u16> = (0..chars)
+                        .map(|_| data.u16())
+                        .collect::<std::result::Result<_, _>>()?;
+                    name = String::from_utf16_lossy(&units);
+                }
+                b"lsct" | b"lsdk" => section = data.u32()?,
+                _ => {}
+            }
+            // Blocks are padded to an even length
+            extra.skip(len % 2).ok();
+        }
+
+        headers.push((
+            Record {
+                name,
+                visible: flags & LAYER_HIDDEN == 0,
+                opacity,
+                section,
+                layer: Layer {
+                    visible: flags & LAYER_HIDDEN == 0,
+                    opacity,
+                    x: left,
+                    y: top,
+                    image: RgbaImage::new(0, 0),
+                },
+            },
+            (
+                (right - left).max(0) as usize,
+                (bottom - top).max(0) as usize,
+            ),
+            channels,
+        ));
+    }
+
+    let mut records = vec![];
+    for (mut record, (width, height), channels) in headers {
+        let mut planes = vec![];
+        for (id, len) in channels {
+            let mut data = reader.sub(len)?;
+            if len < 2 || !(-1..=2).contains(&id) || width == 0 || height == 0 {
+                continue;
+            }
+            let compression = data.u16()?;
+            let plane = read_planes(&mut data, compression, 1, width, height)?;
+            planes.push((id, plane.into_iter().next().unwrap_or_default()));
+        }
+
+        record.layer.image = to_rgba(width as u32, height as u32, mode, |channel| {
+            planes
+                .iter()
+                .find(|(id, _)| *id == channel)
+                .map(|(_, plane)| plane.as_slice())
+        });
+        records.push(record);
+    }
+
+    Ok((records, merged_alpha))
+}
+
+/// Sorts the layer records, listed from bottom to top, into the top-level layers and groups
+fn group(records: Vec<Record>) -> Vec<TopLevel> {
+    let mut res = vec![];
+    // The layers inside each of the groups being read, innermost last
+    let mut groups: Vec<Vec<Layer>> = vec![];
+
+    for record in records {
+        match record.section {
+            // Marks the bottom of a group
+            SECTION_BOUNDING => groups.push(vec![]),
+            // The group itself, above its layers
+            SECTION_OPEN_FOLDER | SECTION_CLOSED_FOLDER => {
+                let mut layers = groups.pop().unwrap_or_default();
+                match groups.last_mut() {
+                    Some(parent) => {
+                        // Nested groups are flattened into their parent
+                        for layer in layers.iter_mut() {
+                            layer.visible &= record.visible;
+                            layer.opacity =
+                                (layer.opacity as u32 * record.opacity as u32 / 255) as u8;
+                        }
+                        parent.extend(layers);
+                    }
+                    None => res.push(TopLevel {
+                        name: record.name,
+                        visible: record.visible,
+                        opacity: record.opacity,
+                        layers,
+                    }),
+                }
+            }
+            _ => match groups.last_mut() {
+                Some(parent) => parent.push(record.layer),
+                None => res.push(TopLevel {
+                    name: record.name,
+                    visible: record.visible,
+                    opacity: record.opacity,
+                    layers: vec![Layer {
+                        visible: true,
+                        opacity: 255,
+                        ..record.layer
+                    }],
+                }),
+            },
+        }
+    }
+
+    res
+}
+
+/// Reads `channels` planes of `width` by `height` bytes
+fn read_planes(
+    reader: &mut Reader,
+    compression: u16,
+    channels: usize,
+    width: usize,
+    height: usize,
+) -> std::result::Result<Vec<Vec<u8>>, String> {
+    let size = width * height;
+    match compression {
+        // Raw
+        0 => (0..channels)
+            .map(|_| reader.take(size).map(<[u8]>::to_vec))
+            .collect(),
+        // PackBits, with the byte counts of every row of every channel first
+        1 => {
+            let counts: Vec<usize> = (0..channels * height)
+                .map(|_| reader.u16().map(|count| count as usize))
+                .collect::<std::result::Result<_, _>>()?;
+            let mut planes = vec![];
+            for rows in counts.chunks(height.max(1)) {
+                let mut plane = Vec::with_capacity(size);
+                for count in rows.iter() {
+                    let row = unpack_bits(reader.take(*count)?, width);
+                    plane.extend_from_slice(&row);
+                }
+                planes.push(plane);
+            }
+            Ok(planes)
+        }
+        // Zip, without and with prediction
+        2 | 3 => {
+            let mut data = vec![];
+            flate2::read::ZlibDecoder::new(&reader.bytes[reader.pos..])
+                .read_to_end(&mut data)
+                .map_err(|err| format!("invalid zip data: {}", err))?;
+            reader.pos = reader.bytes.len();
+            if data.len() < size * channels {
+                return Err("image data is too short".to_owned());
+            }
+            if compression == 3 {
+                for row in data.chunks_mut(width.max(1)) {
+                    for x in 1..row.len() {
+                        row[x] = row[x].wrapping_add(row[x - 1]);
+                    }
+                }
+            }
+            Ok(data
+                .chunks(size.max(1))
+                .take(channels)
+                .map(<[u8]>::to_vec)
+                .collect())
+        }
+        _ => Err(format!("unsupported compression {}", compression)),
+    }
+}
+
+/// Decodes a row compressed with PackBits, padding or cutting it to `width` bytes
+fn unpack_bits(mut data: &[u8], width: usize) -> Vec<u8> {
+    let mut res = Vec::with_capacity(width);
+    while let Some((&header, rest)) = data.split_first() {
+        let header = header as i8;
+        if header >= 0 {
+            let len = (header as usize + 1).min(rest.len());
+            res.extend_from_slice(&rest[..len]);
+            data = &rest[len..];
+        } else if header != -128 {
+            if let Some(&value) = rest.first() {
+                res.extend(std::iter::repeat(value).take((1 - header as isize) as usize));
+            }
+            data = rest.get(1..).unwrap_or_default();
+        } else {
+            data = rest;
+        }
+    }
+    res.resize(width, 0);
+    res
+}
+
+/// Interleaves the planes of an image, looked up by their channel id (-1 being alpha)
+fn to_rgba<'a>(
+    width: u32,
+    height: u32,
+    mode: u16,
+    plane: impl Fn(i16) -> Option<&'a [u8]>,
+) -> RgbaImage {
+    let size = (width * height) as usize;
+    let channels: Vec<i16> = if mode == MODE_RGB {
+        vec![0, 1, 2]
+    } else {
+        vec![0, 0, 0]
+    };
+
+    let mut res = vec![0; size * 4];
+    for (idx, channel) in channels.into_iter().enumerate() {
+        if let Some(plane) = plane(channel) {
+            for (pixel, value) in res.chunks_mut(4).zip(plane.iter()) {
+                pixel[idx] = *value;
+            }
+        }
+    }
+    match plane(-1) {
+        Some(alpha) => {
+            for (pixel, value) in res.chunks_mut(4).zip(alpha.iter()) {
+                pixel[3] = *value;
+            }
+        }
+        None => {
+            for pixel in res.chunks_mut(4) {
+                pixel[3] = 255;
+            }
+        }
+    }
+
+    RgbaImage::from_vec(width, height, res).expect("layer size mismatch")
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], String> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err("unexpected end of file".to_owned());
+        }
+        let res = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    /// A reader over the next `len` bytes
+    fn sub(&mut self, len: usize) -> std::result::Result<Reader<'a>, String> {
+        Ok(Reader {
+            bytes: self.take(len)?,
+            pos: 0,
+        })
+    }
+
+    fn skip(&mut self, len: usize) -> std::result::Result<(), String> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> std::result::Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, String> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.take(2)?);
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(buf))
+    }
+}
@@ -0,0 +1,81 @@
+use crate::rect::Rect;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// An area of the pages that is left empty, given as `X,Y,WxH` for every page or `N:X,Y,WxH`
+/// for page `N` only
+#[derive(Deserialize, Debug, Clone, Hash, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Reserve {
+    pub page: Option<usize>,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl FromStr for Reserve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid reserved area {}, expected [PAGE:]X,Y,WxH", s);
+        let (page, area) = match s.split_once(':') {
+            Some((page, area)) => (Some(page.parse().map_err(|_| invalid())?), area),
+            None => (None, s),
+        };
+        let mut parts = area.splitn(3, ',');
+        let x = parts.next().and_then(|x| x.parse().ok());
+        let y = parts.next().and_then(|y| y.parse().ok());
+        let size = parts.next().and_then(|size| {
+            let (width, height) = size.split_once('x')?;
+            Some((width.parse().ok()?, height.parse().ok()?))
+        });
+        match (x, y, size) {
+            (Some(x), Some(y), Some((width, height)))
+                if x >= 0 && y >= 0 && width > 0 && height > 0 =>
+            {
+                Ok(Self {
+                    page,
+                    x,
+                    y,
+                    width,
+                    height,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Reserve {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Reserve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(page) = self.page {
+            write!(f, "{}:", page)?;
+        }
+        write!(f, "{},{},{}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// The areas of page `page` that are left empty
+pub fn rects(reserve: &[Reserve], page: usize) -> Vec<Rect> {
+    reserve
+        .iter()
+        .filter(|area| area.page.map_or(true, |only| only == page))
+        .map(|area| Rect {
+            x: area.x,
+            y: area.y,
+            width: area.width,
+            height: area.height,
+        })
+        .collect()
+}
@@ -0,0 +1,74 @@
+use image::imageops::FilterType;
+use image::{ImageBuffer, Pixel};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// A factor to resize images by as they're loaded, e.g. `0.5` to halve their size
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(try_from = "f32")]
+pub struct Scale(f32);
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+impl TryFrom<f32> for Scale {
+    type Error = String;
+
+    fn try_from(value: f32) -> Result<Self, Self::Error> {
+        if value.is_finite() && value > 0.0 {
+            Ok(Self(value))
+        } else {
+            Err(format!("invalid scale: {}", value))
+        }
+    }
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s
+            .parse::<f32>()
+            .map_err(|err| format!("invalid scale {}: {}", s, err))?;
+        Self::try_from(value)
+    }
+}
+
+impl Hash for Scale {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl Scale {
+    pub fn factor(self) -> f32 {
+        self.0
+    }
+
+    /// The scale for an image drawn at `density` times the size of the others, e.g. 2 for `@2x`
+    pub fn for_density(self, density: f32) -> Self {
+        Self(self.0 / density)
+    }
+
+    /// Resizes `image` by the scale, keeping it at least a pixel wide and tall
+    pub fn apply<P>(
+        self,
+        image: ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>>
+    where
+        P: Pixel + 'static,
+        P::Subpixel: 'static,
+    {
+        if self.0 == 1.0 {
+            return image;
+        }
+        let width = ((image.width() as f32 * self.0).round() as u32).max(1);
+        let height = ((image.height() as f32 * self.0).round() as u32).max(1);
+        image::imageops::resize(&image, width, height, FilterType::Lanczos3)
+    }
+}
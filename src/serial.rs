@@ -1,79 +1,840 @@
-use crate::error::Result;
-use serde::{Deserialize, Serialize};
+use crate::error::{ImpactError, Result};
+use crate::packer::{Packer, Rotation};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The metadata describing every page of a packed atlas
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Atlas {
-    #[serde(rename = "t")]
+    #[serde(rename = "t", alias = "textures")]
     pub textures: Vec<Texture>,
+    #[serde(
+        rename = "a",
+        alias = "animations",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub animations: Vec<Animation>,
+    /// Which way the rotated sprites were turned
+    #[serde(
+        rename = "rot",
+        alias = "rotation",
+        default,
+        skip_serializing_if = "Rotation::is_cw"
+    )]
+    pub rotation: Rotation,
+    /// Which version of impact packed the atlas, and with which settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+    /// The images too large for a page that were split into tiles
+    #[serde(
+        rename = "ti",
+        alias = "tiled",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tiled: Vec<TiledImage>,
+    /// The file every sprite was read from, with `--sources`
+    #[serde(
+        rename = "src",
+        alias = "sources",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub sources: Vec<Source>,
+}
+
+/// The tool and settings an atlas was packed with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Meta {
+    pub app: String,
+    pub version: String,
+    /// The options that change how the atlas is packed, by their command line name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub settings: BTreeMap<String, serde_json::Value>,
 }
 
+/// A named sequence of sprites
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Animation {
+    #[serde(rename = "n", alias = "name")]
+    pub name: String,
+    /// The names of the sprites, in order
+    #[serde(rename = "f", alias = "frames")]
+    pub frames: Vec<String>,
+}
+
+/// An image that was too large for a page, split into tiles packed as sprites of their own
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TiledImage {
+    #[serde(rename = "n", alias = "name")]
+    pub name: String,
+    /// The size of the whole image
+    #[serde(rename = "w", alias = "width")]
+    pub width: i32,
+    #[serde(rename = "h", alias = "height")]
+    pub height: i32,
+    #[serde(rename = "t", alias = "tiles")]
+    pub tiles: Vec<Tile>,
+}
+
+/// The file a sprite was read from, to trace it back when something looks wrong
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Source {
+    /// The name of the sprite
+    #[serde(rename = "n", alias = "name")]
+    pub name: String,
+    /// The path of the file, as found from the inputs
+    #[serde(rename = "p", alias = "path")]
+    pub path: String,
+    /// When the file was last modified, in seconds since the Unix epoch
+    #[serde(rename = "m", alias = "modified")]
+    pub modified: u64,
+    /// The BLAKE3 hash of the file, in hex
+    #[serde(rename = "h", alias = "hash")]
+    pub hash: String,
+}
+
+/// A tile of a `TiledImage`, by the name of its sprite
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tile {
+    #[serde(rename = "n", alias = "name")]
+    pub name: String,
+    /// The position of the tile in the whole image
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A single page of the atlas
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Texture {
-    #[serde(rename = "n")]
+    #[serde(rename = "n", alias = "name")]
     pub name: String,
-    #[serde(rename = "imgs")]
+    #[serde(rename = "w", alias = "width")]
+    pub width: i32,
+    #[serde(rename = "h", alias = "height")]
+    pub height: i32,
+    #[serde(rename = "imgs", alias = "images")]
     pub images: Vec<Image>,
+    /// The BLAKE3 hash of the page image file, in hex, with `--checksums`
+    #[serde(
+        rename = "sum",
+        alias = "checksum",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub checksum: Option<String>,
 }
 
+/// A sprite packed into a page
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Image {
-    #[serde(rename = "n")]
+    #[serde(rename = "n", alias = "name")]
     pub name: String,
+    /// The position of the sprite in the page
     pub x: i32,
     pub y: i32,
-    #[serde(rename = "w")]
+    /// The size of the (trimmed) sprite, before rotation
+    #[serde(rename = "w", alias = "width")]
     pub width: i32,
-    #[serde(rename = "h")]
+    #[serde(rename = "h", alias = "height")]
     pub height: i32,
 
-    #[serde(rename = "fx")]
+    /// The offset and size of the original, untrimmed image
+    #[serde(rename = "fx", alias = "frame_x")]
     pub frame_x: i32,
-    #[serde(rename = "fy")]
+    #[serde(rename = "fy", alias = "frame_y")]
     pub frame_y: i32,
-    #[serde(rename = "fw")]
+    #[serde(rename = "fw", alias = "frame_width")]
     pub frame_width: i32,
-    #[serde(rename = "fh")]
+    #[serde(rename = "fh", alias = "frame_height")]
     pub frame_height: i32,
 
-    #[serde(rename = "r")]
+    /// Whether the sprite was rotated 90 degrees, in the direction of the atlas's `rotation`
+    #[serde(rename = "r", alias = "rotated")]
     pub rotated: bool,
+
+    /// The pivot point, in pixels from the top-left of the original, untrimmed image
+    #[serde(
+        rename = "px",
+        alias = "pivot_x",
+        default,
+        skip_serializing_if = "is_zero"
+    )]
+    pub pivot_x: f32,
+    #[serde(
+        rename = "py",
+        alias = "pivot_y",
+        default,
+        skip_serializing_if = "is_zero"
+    )]
+    pub pivot_y: f32,
+
+    /// Custom values from the sprite's sidecar file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub data: BTreeMap<String, serde_json::Value>,
+
+    /// The other names of the sprite: those of identical sprites that share its rectangle, and
+    /// those given by `~alias=` flags on its file name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+
+    /// A number that stays the same for the sprite from one run to the next, with
+    /// `--stable-indices`
+    #[serde(
+        rename = "i",
+        alias = "index",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub index: Option<u32>,
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+impl Texture {
+    /// Describes the images placed by `packer`, as a page called `name`
+    pub fn from_packer(name: String, packer: &Packer) -> Self {
+        let images = packer
+            .images
+            .iter()
+            .zip(packer.points.iter())
+            .map(|(img, p)| Image {
+                name: img.name.clone(),
+                x: p.x,
+                y: p.y,
+                width: img.width,
+                height: img.height,
+                frame_x: img.frame_x,
+                frame_y: img.frame_y,
+                frame_width: img.frame_w,
+                frame_height: img.frame_h,
+                rotated: p.rot,
+                pivot_x: img.pivot_x,
+                pivot_y: img.pivot_y,
+                data: img.user_data.clone(),
+                aliases: img.aliases.clone(),
+                index: None,
+            })
+            .collect();
+
+        Self {
+            name,
+            width: packer.width,
+            height: packer.height,
+            images,
+            checksum: None,
+        }
+    }
+
+    /// Folds every sprite that was deduplicated into the same rectangle as an earlier one
+    /// into the aliases of that earlier sprite.
+    ///
+    /// Sprites only count as identical if their frame, pivot and custom data match too, so
+    /// that no information is lost.
+    pub fn fold_aliases(&mut self) {
+        let mut images: Vec<Image> = Vec::with_capacity(self.images.len());
+        let mut by_position: HashMap<(i32, i32), usize> = HashMap::new();
+        for image in self.images.drain(..) {
+            match by_position.get(&(image.x, image.y)) {
+                Some(&idx) if images[idx].is_alias_of(&image) => {
+                    images[idx].aliases.push(image.name);
+                    images[idx].aliases.extend(image.aliases);
+                }
+                _ => {
+                    by_position.insert((image.x, image.y), images.len());
+                    images.push(image);
+                }
+            }
+        }
+        self.images = images;
+    }
+
+    /// Lists every alias as a sprite of its own again, right after the sprite it aliases
+    pub fn expand_aliases(&mut self) {
+        let mut images = Vec::with_capacity(self.images.len());
+        for mut image in self.images.drain(..) {
+            let aliases = std::mem::take(&mut image.aliases);
+            let copies: Vec<_> = aliases
+                .into_iter()
+                .map(|name| Image {
+                    name,
+                    data: image.data.clone(),
+                    aliases: vec![],
+                    // Aliases are numbered on their own
+                    index: None,
+                    ..image
+                })
+                .collect();
+            images.push(image);
+            images.extend(copies);
+        }
+        self.images = images;
+    }
+}
+
+impl Image {
+    /// Whether `other` describes the same pixels of the page, in the same way
+    fn is_alias_of(&self, other: &Image) -> bool {
+        self.x == other.x
+            && self.y == other.y
+            && self.width == other.width
+            && self.height == other.height
+            && self.frame_x == other.frame_x
+            && self.frame_y == other.frame_y
+            && self.frame_width == other.frame_width
+            && self.frame_height == other.frame_height
+            && self.rotated == other.rotated
+            && self.pivot_x == other.pivot_x
+            && self.pivot_y == other.pivot_y
+            && self.data == other.data
+    }
+
+    /// The name of the sprite followed by those of its aliases
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str()).chain(self.aliases.iter().map(String::as_str))
+    }
 }
 
 impl Atlas {
-    pub fn write_to_xml_file<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
-        let mut file = std::fs::File::create(path)?;
+    /// Lists the aliases of every texture as sprites of their own
+    pub fn expand_aliases(&mut self) {
+        for texture in self.textures.iter_mut() {
+            texture.expand_aliases();
+        }
+    }
+}
+
+/// Serializes the wrapped atlas data using unabbreviated keys (`name`, `width`, `frame_x`...)
+pub struct LongKeys<'a, T>(pub &'a T);
+
+impl Serialize for LongKeys<'_, Atlas> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let atlas = self.0;
+        let textures: Vec<_> = atlas.textures.iter().map(LongKeys).collect();
+        let animations: Vec<_> = atlas.animations.iter().map(LongKeys).collect();
+        let tiled: Vec<_> = atlas.tiled.iter().map(LongKeys).collect();
+        let sources: Vec<_> = atlas.sources.iter().map(LongKeys).collect();
+
+        let mut s = serializer.serialize_struct("Atlas", 6)?;
+        s.serialize_field("textures", &textures)?;
+        if animations.is_empty() {
+            s.skip_field("animations")?;
+        } else {
+            s.serialize_field("animations", &animations)?;
+        }
+        if atlas.rotation.is_cw() {
+            s.skip_field("rotation")?;
+        } else {
+            s.serialize_field("rotation", &atlas.rotation)?;
+        }
+        match &atlas.meta {
+            Some(meta) => s.serialize_field("meta", meta)?,
+            None => s.skip_field("meta")?,
+        }
+        if tiled.is_empty() {
+            s.skip_field("tiled")?;
+        } else {
+            s.serialize_field("tiled", &tiled)?;
+        }
+        if sources.is_empty() {
+            s.skip_field("sources")?;
+        } else {
+            s.serialize_field("sources", &sources)?;
+        }
+        s.end()
+    }
+}
+
+impl Serialize for LongKeys<'_, TiledImage> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let image = self.0;
+        let tiles: Vec<_> = image.tiles.iter().map(LongKeys).collect();
+
+        let mut s = serializer.serialize_struct("TiledImage", 4)?;
+        s.serialize_field("name", &image.name)?;
+        s.serialize_field("width", &image.width)?;
+        s.serialize_field("height", &image.height)?;
+        s.serialize_field("tiles", &tiles)?;
+        s.end()
+    }
+}
+
+impl Serialize for LongKeys<'_, Source> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let source = self.0;
+
+        let mut s = serializer.serialize_struct("Source", 4)?;
+        s.serialize_field("name", &source.name)?;
+        s.serialize_field("path", &source.path)?;
+        s.serialize_field("modified", &source.modified)?;
+        s.serialize_field("hash", &source.hash)?;
+        s.end()
+    }
+}
+
+impl Serialize for LongKeys<'_, Tile> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let tile = self.0;
+
+        let mut s = serializer.serialize_struct("Tile", 3)?;
+        s.serialize_field("name", &tile.name)?;
+        s.serialize_field("x", &tile.x)?;
+        s.serialize_field("y", &tile.y)?;
+        s.end()
+    }
+}
+
+impl Serialize for LongKeys<'_, Animation> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let animation = self.0;
+
+        let mut s = serializer.serialize_struct("Animation", 2)?;
+        s.serialize_field("name", &animation.name)?;
+        s.serialize_field("frames", &animation.frames)?;
+        s.end()
+    }
+}
+
+impl Serialize for LongKeys<'_, Texture> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let texture = self.0;
+        let images: Vec<_> = texture.images.iter().map(LongKeys).collect();
+
+        let mut s = serializer.serialize_struct("Texture", 5)?;
+        s.serialize_field("name", &texture.name)?;
+        s.serialize_field("width", &texture.width)?;
+        s.serialize_field("height", &texture.height)?;
+        s.serialize_field("images", &images)?;
+        if let Some(checksum) = &texture.checksum {
+            s.serialize_field("checksum", checksum)?;
+        }
+        s.end()
+    }
+}
+
+impl Serialize for LongKeys<'_, Image> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let image = self.0;
+
+        let mut s = serializer.serialize_struct("Image", 15)?;
+        s.serialize_field("name", &image.name)?;
+        s.serialize_field("x", &image.x)?;
+        s.serialize_field("y", &image.y)?;
+        s.serialize_field("width", &image.width)?;
+        s.serialize_field("height", &image.height)?;
+        s.serialize_field("frame_x", &image.frame_x)?;
+        s.serialize_field("frame_y", &image.frame_y)?;
+        s.serialize_field("frame_width", &image.frame_width)?;
+        s.serialize_field("frame_height", &image.frame_height)?;
+        s.serialize_field("rotated", &image.rotated)?;
+        if is_zero(&image.pivot_x) {
+            s.skip_field("pivot_x")?;
+        } else {
+            s.serialize_field("pivot_x", &image.pivot_x)?;
+        }
+        if is_zero(&image.pivot_y) {
+            s.skip_field("pivot_y")?;
+        } else {
+            s.serialize_field("pivot_y", &image.pivot_y)?;
+        }
+        if image.data.is_empty() {
+            s.skip_field("data")?;
+        } else {
+            s.serialize_field("data", &image.data)?;
+        }
+        if image.aliases.is_empty() {
+            s.skip_field("aliases")?;
+        } else {
+            s.serialize_field("aliases", &image.aliases)?;
+        }
+        match image.index {
+            Some(index) => s.serialize_field("index", &index)?,
+            None => s.skip_field("index")?,
+        }
+        s.end()
+    }
+}
+
+/// Picks the abbreviated or unabbreviated form of a key
+fn key(short: &'static str, long: &'static str, long_keys: bool) -> &'static str {
+    if long_keys {
+        long
+    } else {
+        short
+    }
+}
+
+impl Atlas {
+    pub fn write_to_xml_file<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        long_keys: bool,
+    ) -> Result<()> {
+        std::fs::write(path, self.to_xml(long_keys)?)?;
+        Ok(())
+    }
+
+    pub fn to_xml(&self, long_keys: bool) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
 
         let mut writer = xml::writer::EmitterConfig::new()
             .perform_indent(true)
-            .create_writer(&mut file);
-        writer.write(xml::writer::XmlEvent::start_element("Atlas"))?;
+            .create_writer(&mut bytes);
+        let mut root = xml::writer::XmlEvent::start_element("Atlas");
+        if !self.rotation.is_cw() {
+            root = root.attr(key("rot", "rotation", long_keys), "ccw");
+        }
+        writer.write(root)?;
+
+        if let Some(meta) = &self.meta {
+            writer.write(
+                xml::writer::XmlEvent::start_element("Meta")
+                    .attr("app", &meta.app)
+                    .attr("version", &meta.version),
+            )?;
+            for (name, value) in meta.settings.iter() {
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                writer.write(
+                    xml::writer::XmlEvent::start_element("Setting")
+                        .attr(key("k", "key", long_keys), name)
+                        .attr(key("v", "value", long_keys), &value),
+                )?;
+                writer.write(xml::writer::XmlEvent::end_element())?;
+            }
+            writer.write(xml::writer::XmlEvent::end_element())?;
+        }
 
         for texture in self.textures.iter() {
-            writer
-                .write(xml::writer::XmlEvent::start_element("Texture").attr("n", &texture.name))?;
+            let width = format!("{}", texture.width);
+            let height = format!("{}", texture.height);
+            let mut element = xml::writer::XmlEvent::start_element("Texture")
+                .attr(key("n", "name", long_keys), &texture.name)
+                .attr(key("w", "width", long_keys), &width)
+                .attr(key("h", "height", long_keys), &height);
+            if let Some(checksum) = &texture.checksum {
+                element = element.attr(key("sum", "checksum", long_keys), checksum);
+            }
+            writer.write(element)?;
 
             for image in texture.images.iter() {
+                let mut attributes = vec![
+                    (key("n", "name", long_keys), image.name.clone()),
+                    ("x", format!("{}", image.x)),
+                    ("y", format!("{}", image.y)),
+                    (key("w", "width", long_keys), format!("{}", image.width)),
+                    (key("h", "height", long_keys), format!("{}", image.height)),
+                    (
+                        key("fx", "frame_x", long_keys),
+                        format!("{}", image.frame_x),
+                    ),
+                    (
+                        key("fy", "frame_y", long_keys),
+                        format!("{}", image.frame_y),
+                    ),
+                    (
+                        key("fw", "frame_width", long_keys),
+                        format!("{}", image.frame_width),
+                    ),
+                    (
+                        key("fh", "frame_height", long_keys),
+                        format!("{}", image.frame_height),
+                    ),
+                    (
+                        key("r", "rotated", long_keys),
+                        if image.rotated { "1" } else { "0" }.to_owned(),
+                    ),
+                ];
+                if image.pivot_x != 0.0 || image.pivot_y != 0.0 {
+                    attributes.push((
+                        key("px", "pivot_x", long_keys),
+                        format!("{}", image.pivot_x),
+                    ));
+                    attributes.push((
+                        key("py", "pivot_y", long_keys),
+                        format!("{}", image.pivot_y),
+                    ));
+                }
+                if let Some(index) = image.index {
+                    attributes.push((key("i", "index", long_keys), format!("{}", index)));
+                }
+
+                let mut element = xml::writer::XmlEvent::start_element("Image");
+                for (name, value) in attributes.iter() {
+                    element = element.attr(*name, value);
+                }
+                writer.write(element)?;
+                for (name, value) in image.data.iter() {
+                    let value = match value {
+                        serde_json::Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    };
+                    writer.write(
+                        xml::writer::XmlEvent::start_element("Data")
+                            .attr(key("k", "key", long_keys), name)
+                            .attr(key("v", "value", long_keys), &value),
+                    )?;
+                    writer.write(xml::writer::XmlEvent::end_element())?;
+                }
+                for alias in image.aliases.iter() {
+                    writer.write(
+                        xml::writer::XmlEvent::start_element("Alias")
+                            .attr(key("n", "name", long_keys), alias),
+                    )?;
+                    writer.write(xml::writer::XmlEvent::end_element())?;
+                }
+                writer.write(xml::writer::XmlEvent::end_element())?;
+            }
+
+            writer.write(xml::writer::XmlEvent::end_element())?;
+        }
+
+        for animation in self.animations.iter() {
+            writer.write(
+                xml::writer::XmlEvent::start_element("Animation")
+                    .attr(key("n", "name", long_keys), &animation.name),
+            )?;
+            for frame in animation.frames.iter() {
+                writer.write(
+                    xml::writer::XmlEvent::start_element("Frame")
+                        .attr(key("n", "name", long_keys), frame),
+                )?;
+                writer.write(xml::writer::XmlEvent::end_element())?;
+            }
+            writer.write(xml::writer::XmlEvent::end_element())?;
+        }
+
+        for image in self.tiled.iter() {
+            writer.write(
+                xml::writer::XmlEvent::start_element("Tiled")
+                    .attr(key("n", "name", long_keys), &image.name)
+                    .attr(key("w", "width", long_keys), &format!("{}", image.width))
+                    .attr(key("h", "height", long_keys), &format!("{}", image.height)),
+            )?;
+            for tile in image.tiles.iter() {
                 writer.write(
-                    xml::writer::XmlEvent::start_element("Image")
-                        .attr("n", &image.name)
-                        .attr("x", &format!("{}", image.x))
-                        .attr("y", &format!("{}", image.y))
-                        .attr("w", &format!("{}", image.width))
-                        .attr("h", &format!("{}", image.height))
-                        .attr("fx", &format!("{}", image.frame_x))
-                        .attr("fy", &format!("{}", image.frame_y))
-                        .attr("fw", &format!("{}", image.frame_width))
-                        .attr("fh", &format!("{}", image.frame_height))
-                        .attr("r", if image.rotated { "1" } else { "0" }),
+                    xml::writer::XmlEvent::start_element("Tile")
+                        .attr(key("n", "name", long_keys), &tile.name)
+                        .attr("x", &format!("{}", tile.x))
+                        .attr("y", &format!("{}", tile.y)),
                 )?;
                 writer.write(xml::writer::XmlEvent::end_element())?;
             }
+            writer.write(xml::writer::XmlEvent::end_element())?;
+        }
 
+        for source in self.sources.iter() {
+            writer.write(
+                xml::writer::XmlEvent::start_element("Source")
+                    .attr(key("n", "name", long_keys), &source.name)
+                    .attr(key("p", "path", long_keys), &source.path)
+                    .attr(
+                        key("m", "modified", long_keys),
+                        &format!("{}", source.modified),
+                    )
+                    .attr(key("h", "hash", long_keys), &source.hash),
+            )?;
             writer.write(xml::writer::XmlEvent::end_element())?;
         }
 
         writer.write(xml::writer::XmlEvent::end_element())?;
 
-        Ok(())
+        Ok(bytes)
+    }
+}
+
+impl Atlas {
+    /// Reads an atlas written by `write_to_xml_file`, with either short or long keys
+    pub fn from_xml<R: std::io::Read>(reader: R) -> Result<Atlas> {
+        use xml::reader::XmlEvent;
+
+        let mut atlas = Atlas::default();
+        for event in xml::reader::EventReader::new(reader) {
+            if let XmlEvent::StartElement {
+                name, attributes, ..
+            } = event?
+            {
+                let attr = |short: &str, long: &str| {
+                    attributes
+                        .iter()
+                        .find(|attr| attr.name.local_name == short || attr.name.local_name == long)
+                        .map(|attr| attr.value.as_str())
+                        .ok_or_else(|| ImpactError::InvalidAtlas {
+                            reason: format!("<{}> is missing the {} attribute", name, long),
+                        })
+                };
+                let int = |short: &str, long: &str| {
+                    attr(short, long)?
+                        .parse::<i32>()
+                        .map_err(|err| ImpactError::InvalidAtlas {
+                            reason: format!("invalid {} attribute: {}", long, err),
+                        })
+                };
+
+                let float = |short: &str, long: &str| match attr(short, long) {
+                    Ok(value) => value
+                        .parse::<f32>()
+                        .map_err(|err| ImpactError::InvalidAtlas {
+                            reason: format!("invalid {} attribute: {}", long, err),
+                        }),
+                    Err(_) => Ok(0.0),
+                };
+
+                match name.local_name.as_str() {
+                    "Atlas" => {
+                        if let Ok(rotation) = attr("rot", "rotation") {
+                            atlas.rotation = rotation
+                                .parse()
+                                .map_err(|reason| ImpactError::InvalidAtlas { reason })?;
+                        }
+                    }
+                    "Meta" => {
+                        atlas.meta = Some(Meta {
+                            app: attr("app", "app")?.to_owned(),
+                            version: attr("version", "version")?.to_owned(),
+                            settings: BTreeMap::new(),
+                        })
+                    }
+                    "Setting" => {
+                        let key = attr("k", "key")?.to_owned();
+                        let value = attr("v", "value")?;
+                        let value = serde_json::from_str(value)
+                            .unwrap_or_else(|_| serde_json::Value::String(value.to_owned()));
+                        atlas
+                            .meta
+                            .as_mut()
+                            .ok_or_else(|| ImpactError::InvalidAtlas {
+                                reason: "<Setting> outside of <Meta>".to_owned(),
+                            })?
+                            .settings
+                            .insert(key, value);
+                    }
+                    "Texture" => atlas.textures.push(Texture {
+                        name: attr("n", "name")?.to_owned(),
+                        width: int("w", "width")?,
+                        height: int("h", "height")?,
+                        images: vec![],
+                        checksum: attr("sum", "checksum").ok().map(str::to_owned),
+                    }),
+                    "Image" => {
+                        let image = Image {
+                            name: attr("n", "name")?.to_owned(),
+                            x: int("x", "x")?,
+                            y: int("y", "y")?,
+                            width: int("w", "width")?,
+                            height: int("h", "height")?,
+                            frame_x: int("fx", "frame_x")?,
+                            frame_y: int("fy", "frame_y")?,
+                            frame_width: int("fw", "frame_width")?,
+                            frame_height: int("fh", "frame_height")?,
+                            rotated: attr("r", "rotated")? == "1",
+                            pivot_x: float("px", "pivot_x")?,
+                            pivot_y: float("py", "pivot_y")?,
+                            data: BTreeMap::new(),
+                            aliases: vec![],
+                            index: match attr("i", "index") {
+                                Ok(index) => Some(index.parse().map_err(|err| {
+                                    ImpactError::InvalidAtlas {
+                                        reason: format!("invalid index attribute: {}", err),
+                                    }
+                                })?),
+                                Err(_) => None,
+                            },
+                        };
+                        atlas
+                            .textures
+                            .last_mut()
+                            .ok_or_else(|| ImpactError::InvalidAtlas {
+                                reason: "<Image> outside of a <Texture>".to_owned(),
+                            })?
+                            .images
+                            .push(image);
+                    }
+                    "Data" => {
+                        let key = attr("k", "key")?.to_owned();
+                        // Values that aren't valid JSON were written as plain strings
+                        let value = attr("v", "value")?;
+                        let value = serde_json::from_str(value)
+                            .unwrap_or_else(|_| serde_json::Value::String(value.to_owned()));
+                        atlas
+                            .textures
+                            .last_mut()
+                            .and_then(|texture| texture.images.last_mut())
+                            .ok_or_else(|| ImpactError::InvalidAtlas {
+                                reason: "<Data> outside of an <Image>".to_owned(),
+                            })?
+                            .data
+                            .insert(key, value);
+                    }
+                    "Alias" => {
+                        let alias = attr("n", "name")?.to_owned();
+                        atlas
+                            .textures
+                            .last_mut()
+                            .and_then(|texture| texture.images.last_mut())
+                            .ok_or_else(|| ImpactError::InvalidAtlas {
+                                reason: "<Alias> outside of an <Image>".to_owned(),
+                            })?
+                            .aliases
+                            .push(alias);
+                    }
+                    "Animation" => atlas.animations.push(Animation {
+                        name: attr("n", "name")?.to_owned(),
+                        frames: vec![],
+                    }),
+                    "Frame" => {
+                        let frame = attr("n", "name")?.to_owned();
+                        atlas
+                            .animations
+                            .last_mut()
+                            .ok_or_else(|| ImpactError::InvalidAtlas {
+                                reason: "<Frame> outside of an <Animation>".to_owned(),
+                            })?
+                            .frames
+                            .push(frame);
+                    }
+                    "Tiled" => atlas.tiled.push(TiledImage {
+                        name: attr("n", "name")?.to_owned(),
+                        width: int("w", "width")?,
+                        height: int("h", "height")?,
+                        tiles: vec![],
+                    }),
+                    "Tile" => {
+                        let tile = Tile {
+                            name: attr("n", "name")?.to_owned(),
+                            x: int("x", "x")?,
+                            y: int("y", "y")?,
+                        };
+                        atlas
+                            .tiled
+                            .last_mut()
+                            .ok_or_else(|| ImpactError::InvalidAtlas {
+                                reason: "<Tile> outside of <Tiled>".to_owned(),
+                            })?
+                            .tiles
+                            .push(tile);
+                    }
+                    "Source" => atlas.sources.push(Source {
+                        name: attr("n", "name")?.to_owned(),
+                        path: attr("p", "path")?.to_owned(),
+                        modified: attr("m", "modified")?.parse().map_err(|err| {
+                            ImpactError::InvalidAtlas {
+                                reason: format!("invalid modified attribute: {}", err),
+                            }
+                        })?,
+                        hash: attr("h", "hash")?.to_owned(),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(atlas)
     }
 }
@@ -0,0 +1,68 @@
+use crate::error::{ImpactError, Result};
+use crate::image_wrapper::ImageWrapper;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The extensions a sidecar file can have, appended to the full name of its image
+const EXTENSIONS: &[&str] = &["meta", "json"];
+
+/// Per-sprite settings, read from a JSON file next to the image (`sprite.png.meta` or
+/// `sprite.png.json`).
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sidecar {
+    /// The pivot point, in pixels from the top-left of the untrimmed image
+    pub pivot_x: f32,
+    pub pivot_y: f32,
+    /// The padding after the sprite, overriding `--pad`
+    pub padding: Option<u8>,
+    /// Sprites with a higher priority are packed first, into the first pages
+    pub priority: i32,
+    /// Custom values to write to the atlas metadata along with the sprite
+    pub data: BTreeMap<String, serde_json::Value>,
+}
+
+/// The sidecar file of the image at `image`, if it has one
+pub fn path(image: &Path) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|ext| {
+            let mut path = image.as_os_str().to_owned();
+            path.push(".");
+            path.push(ext);
+            PathBuf::from(path)
+        })
+        .find(|path| path.is_file())
+}
+
+impl Sidecar {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)?;
+        let sidecar: Sidecar =
+            serde_json::from_slice(&contents).map_err(|err| invalid(path, err))?;
+        if let Some(padding) = sidecar.padding {
+            if padding > 16 {
+                return Err(invalid(path, "padding can be from 0 to 16"));
+            }
+        }
+        Ok(sidecar)
+    }
+
+    pub fn apply(&self, image: &mut ImageWrapper) {
+        image.pivot_x = self.pivot_x;
+        image.pivot_y = self.pivot_y;
+        if let Some(padding) = self.padding {
+            image.pad = Some(i32::from(padding));
+        }
+        image.priority = self.priority;
+        image.user_data = self.data.clone();
+    }
+}
+
+fn invalid<E: ToString>(path: &Path, reason: E) -> ImpactError {
+    ImpactError::InvalidSidecar {
+        path: path.display().to_string(),
+        reason: reason.to_string(),
+    }
+}
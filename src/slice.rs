@@ -0,0 +1,149 @@
+use image::RgbaImage;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A spritesheet to cut into a grid of cells before packing, given as `sheet.png:32x32`
+#[derive(Deserialize, Debug, Clone, Hash, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Slice {
+    pub path: PathBuf,
+    /// The size of every cell
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Slice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, size) =
+            split_path(s).ok_or_else(|| format!("invalid slice {}, expected path:WxH", s))?;
+        let (width, height) = size
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+            .filter(|&(width, height)| width > 0 && height > 0)
+            .ok_or_else(|| format!("invalid slice size {}, expected WxH", size))?;
+        Ok(Self {
+            path: PathBuf::from(path),
+            width,
+            height,
+        })
+    }
+}
+
+impl TryFrom<String> for Slice {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Slice {
+    /// Whether this is the slice for the image at `path`, however either of them was written
+    pub fn matches(&self, path: &Path) -> bool {
+        same_path(&self.path, path)
+    }
+
+    /// Cuts `image` into cells, named `name_0`, `name_1`... from left to right and top to
+    /// bottom. Cells that are completely transparent are left out, and any pixels to the right
+    /// or below the last full cells are ignored.
+    pub fn apply(&self, image: &RgbaImage, name: &str) -> Vec<(String, RgbaImage)> {
+        let columns = image.width() / self.width;
+        let rows = image.height() / self.height;
+        if image.width() % self.width != 0 || image.height() % self.height != 0 {
+            log::warn!(
+                "{} isn't a whole number of {}x{} cells, ignoring the rest",
+                name,
+                self.width,
+                self.height
+            );
+        }
+
+        let mut cells = vec![];
+        for row in 0..rows {
+            for column in 0..columns {
+                let index = row * columns + column;
+                let cell = image::imageops::crop_imm(
+                    image,
+                    column * self.width,
+                    row * self.height,
+                    self.width,
+                    self.height,
+                )
+                .to_image();
+                if cell.pixels().all(|pixel| pixel[3] == 0) {
+                    log::info!("skipping empty cell {} of {}", index, name);
+                    continue;
+                }
+                cells.push((format!("{}_{}", name, index), cell));
+            }
+        }
+        cells
+    }
+}
+
+/// A horizontal strip of animation frames to cut apart before packing, given as `walk.png:8`
+#[derive(Deserialize, Debug, Clone, Hash, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Strip {
+    pub path: PathBuf,
+    /// The number of frames in the strip
+    pub frames: u32,
+}
+
+impl FromStr for Strip {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, frames) =
+            split_path(s).ok_or_else(|| format!("invalid strip {}, expected path:N", s))?;
+        let frames = frames
+            .parse()
+            .ok()
+            .filter(|&frames| frames > 0)
+            .ok_or_else(|| format!("invalid frame count {}", frames))?;
+        Ok(Self {
+            path: PathBuf::from(path),
+            frames,
+        })
+    }
+}
+
+impl TryFrom<String> for Strip {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Strip {
+    /// Whether this is the strip for the image at `path`, however either of them was written
+    pub fn matches(&self, path: &Path) -> bool {
+        same_path(&self.path, path)
+    }
+
+    /// The grid that cuts `image` into its frames, a single row of `frames` cells
+    pub fn slice(&self, image: &RgbaImage) -> Slice {
+        Slice {
+            path: self.path.clone(),
+            width: std::cmp::max(image.width() / self.frames, 1),
+            height: std::cmp::max(image.height(), 1),
+        }
+    }
+}
+
+/// Splits `path:suffix` on the last colon, so that Windows paths keep their drive letter
+fn split_path(s: &str) -> Option<(&str, &str)> {
+    s.rsplit_once(':')
+}
+
+fn same_path(own: &Path, other: &Path) -> bool {
+    match (own.canonicalize(), other.canonicalize()) {
+        (Ok(own), Ok(other)) => own == other,
+        _ => own == other,
+    }
+}
@@ -0,0 +1,95 @@
+use crate::error::{ImpactError, Result};
+use crate::{is_image_file, walk, Opt};
+use std::path::{Path, PathBuf};
+
+/// Splits the atlas described by `opt` into an independent atlas for every immediate
+/// subfolder of the input folders, named after the folder and saved next to the output.
+///
+/// Files directly inside the input folders, and inputs that are files, are still packed into
+/// the output atlas, which is left out if there are none.
+pub fn split_by_folder(opt: &Opt) -> Result<Vec<Opt>> {
+    let output = opt.output.clone().ok_or(ImpactError::MissingOutput)?;
+    let output_dir = output.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut loose = vec![];
+    let mut folders = vec![];
+    for input in &opt.inputs {
+        if !input.is_dir() {
+            loose.push(input.clone());
+            continue;
+        }
+        if has_loose_images(input, opt)? {
+            loose.push(input.clone());
+        }
+        if opt.max_depth.map_or(false, |max_depth| max_depth <= 1) {
+            continue;
+        }
+        folders.extend(subfolders(input, opt)?);
+    }
+
+    let mut names = vec![];
+    if !loose.is_empty() {
+        names.push(output.file_name().unwrap_or_default().to_owned());
+    }
+    let mut atlases = vec![];
+    for folder in folders {
+        let name = folder.file_name().unwrap_or_default().to_owned();
+        if names.contains(&name) {
+            return Err(ImpactError::SplitNameClash {
+                name: name.to_string_lossy().into_owned(),
+            });
+        }
+        names.push(name.clone());
+        atlases.push(Opt {
+            inputs: vec![folder],
+            output: Some(output_dir.join(name)),
+            max_depth: opt.max_depth.map(|max_depth| max_depth - 1),
+            split_by_folder: false,
+            ..opt.clone()
+        });
+    }
+
+    if !loose.is_empty() {
+        atlases.insert(
+            0,
+            Opt {
+                inputs: loose,
+                // Only the files directly inside the input folders are left for the output
+                max_depth: Some(1),
+                split_by_folder: false,
+                ..opt.clone()
+            },
+        );
+    }
+    Ok(atlases)
+}
+
+/// Whether there are any images directly inside the folder `input`
+fn has_loose_images(input: &Path, opt: &Opt) -> Result<bool> {
+    let opt = Opt {
+        max_depth: Some(1),
+        ..opt.clone()
+    };
+    let mut found = false;
+    walk::walk_dir(input, &opt, &mut |file| {
+        found |= is_image_file(file);
+        Ok(())
+    })?;
+    Ok(found)
+}
+
+/// The folders directly inside the folder `input`, in a stable order
+fn subfolders(input: &Path, opt: &Opt) -> Result<Vec<PathBuf>> {
+    let opt = Opt {
+        max_depth: Some(2),
+        ..opt.clone()
+    };
+    let mut folders = vec![];
+    walk::walk_folders(input, &opt, &mut |folder| {
+        if folder != input {
+            folders.push(folder.to_path_buf());
+        }
+        Ok(())
+    })?;
+    Ok(folders)
+}
@@ -0,0 +1,178 @@
+use crate::error::Result;
+use crate::layout::{Layout, Page};
+use crate::packer::Packer;
+use crate::Opt;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// How well the images were packed, for `--stats` and `--stats-file`
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub pages: Vec<PageStats>,
+    /// The number of sprites, including duplicates
+    pub sprites: usize,
+    /// The transparent pixels trimmed off the sprites
+    pub trimmed_pixels: i64,
+    /// The number of sprites sharing the rectangle of an identical one, and the pixels it saved
+    pub duplicates: usize,
+    pub duplicate_pixels: i64,
+    /// The pixels of every page not covered by a sprite
+    pub wasted_pixels: i64,
+    /// The size of the files the sprites were loaded from, in bytes
+    pub input_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PageStats {
+    pub width: i32,
+    pub height: i32,
+    pub sprites: usize,
+    /// The fraction of the page covered by sprites
+    pub occupancy: f32,
+}
+
+impl Stats {
+    pub fn new(packers: &[Packer]) -> Self {
+        Self::from_pages(&Layout::from_packers(0, packers, vec![]).pages)
+    }
+
+    /// The statistics of the pages of a layout, e.g. the stored one of an atlas that's up to
+    /// date
+    pub fn from_pages(pages: &[Page]) -> Self {
+        let mut stats = Self {
+            pages: vec![],
+            sprites: 0,
+            trimmed_pixels: 0,
+            duplicates: 0,
+            duplicate_pixels: 0,
+            wasted_pixels: 0,
+            input_bytes: 0,
+        };
+        for page in pages {
+            let mut used = 0;
+            for sprite in page.sprites.iter() {
+                let area = i64::from(sprite.width) * i64::from(sprite.height);
+                stats.trimmed_pixels +=
+                    i64::from(sprite.frame_w) * i64::from(sprite.frame_h) - area;
+                if sprite.dup_id < 0 {
+                    used += area;
+                } else {
+                    stats.duplicates += 1;
+                    stats.duplicate_pixels += area;
+                }
+                stats.input_bytes += sprite.original_size;
+            }
+            stats.sprites += page.sprites.len();
+            let total = i64::from(page.width) * i64::from(page.height);
+            stats.wasted_pixels += total - used;
+            stats.pages.push(PageStats {
+                width: page.width,
+                height: page.height,
+                sprites: page.sprites.len(),
+                occupancy: used as f32 / total as f32,
+            });
+        }
+        stats
+    }
+
+    pub fn print(&self, opt: &Opt) {
+        reportln!(opt, "{} page(s)", self.pages.len());
+        for (idx, page) in self.pages.iter().enumerate() {
+            reportln!(
+                opt,
+                "page {}: {}x{}, {} images, {:.1}% occupancy",
+                idx,
+                page.width,
+                page.height,
+                page.sprites,
+                page.occupancy * 100.0
+            );
+        }
+        reportln!(opt, "trimming saved {} pixels", self.trimmed_pixels);
+        reportln!(
+            opt,
+            "removing {} duplicate(s) saved {} pixels",
+            self.duplicates,
+            self.duplicate_pixels
+        );
+        reportln!(
+            opt,
+            "{} pixels wasted ({:.1}% of all pages)",
+            self.wasted_pixels,
+            (1.0 - self.occupancy()) * 100.0
+        );
+    }
+
+    /// The fraction of all the pages covered by sprites
+    pub fn occupancy(&self) -> f64 {
+        let total: i64 = self
+            .pages
+            .iter()
+            .map(|page| i64::from(page.width) * i64::from(page.height))
+            .sum();
+        if total > 0 {
+            1.0 - self.wasted_pixels as f64 / total as f64
+        } else {
+            1.0
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// The packing statistics along with the files that were written, for `--report`
+#[derive(Serialize, Debug)]
+pub struct Report {
+    #[serde(flatten)]
+    pub stats: Stats,
+    /// The pages and atlas data written
+    pub outputs: Vec<Output>,
+    /// The size of all the outputs, in bytes
+    pub output_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Output {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+impl Report {
+    /// The report for an atlas with the given `stats`, written to `outputs`
+    pub fn new(stats: Stats, outputs: Vec<PathBuf>) -> Result<Self> {
+        let outputs = outputs
+            .into_iter()
+            .map(|path| {
+                let bytes = size_of(&path)?;
+                Ok(Output { path, bytes })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            stats,
+            output_bytes: outputs.iter().map(|output| output.bytes).sum(),
+            outputs,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// The size of the file at `path` in bytes, or of all the files in it if it's a folder (such as
+/// a SpriteKit `.atlasc`)
+fn size_of(path: &Path) -> Result<u64> {
+    let metadata = std::fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += size_of(&entry?.path())?;
+    }
+    Ok(size)
+}
@@ -0,0 +1,80 @@
+use crate::image_wrapper::{ImageWrapper, TilePosition};
+use crate::Opt;
+use image::Rgba32FImage;
+
+/// Splits every image of `images` that's too large for a page into tiles that fit, with
+/// `--tile-oversized`, keeping the other images as they are
+pub fn split_oversized(opt: &Opt, images: Vec<ImageWrapper>) -> Vec<ImageWrapper> {
+    let mut split = Vec::with_capacity(images.len());
+    for image in images {
+        // The largest tile that still fits in a page along with its border and padding
+        let border = 2 * image.extrude + image.pad.unwrap_or(opt.pad as i32);
+        let size = opt.size as i32 - border;
+        if size <= 0 || (image.width <= size && image.height <= size) {
+            split.push(image);
+        } else {
+            split.extend(tiles(&image, size));
+        }
+    }
+    split
+}
+
+/// Cuts the trimmed bitmap of `image` into tiles of up to `size` pixels square, named
+/// `name_<row>_<column>`. Tiles that are completely transparent are left out.
+fn tiles(image: &ImageWrapper, size: i32) -> Vec<ImageWrapper> {
+    log::info!(
+        "splitting {} ({}x{}) into tiles of up to {}x{}",
+        image.name,
+        image.width,
+        image.height,
+        size,
+        size
+    );
+    let pixels = image.get_image();
+    let hdr = image.hdr.as_ref().map(|hdr| {
+        Rgba32FImage::from_raw(image.width as u32, image.height as u32, hdr.clone())
+            .expect("HDR pixels have the size of the image")
+    });
+
+    let mut tiles = vec![];
+    for (row, y) in (0..image.height).step_by(size as usize).enumerate() {
+        for (column, x) in (0..image.width).step_by(size as usize).enumerate() {
+            let width = std::cmp::min(size, image.width - x) as u32;
+            let height = std::cmp::min(size, image.height - y) as u32;
+            let cell =
+                image::imageops::crop_imm(&pixels, x as u32, y as u32, width, height).to_image();
+            if cell.pixels().all(|pixel| pixel[3] == 0) {
+                log::info!("skipping empty tile {}_{} of {}", row, column, image.name);
+                continue;
+            }
+
+            let name = format!("{}_{}_{}", image.name, row, column);
+            // The image was already premultiplied and trimmed as a whole
+            let mut tile = ImageWrapper::new(cell, name, false, false, 0, 0);
+            if let Some(hdr) = &hdr {
+                let cell = image::imageops::crop_imm(hdr, x as u32, y as u32, width, height);
+                tile = tile.with_hdr(&cell.to_image(), false);
+            }
+            // The size of the file is only counted once, however many tiles it's split into
+            tile.original_size = if tiles.is_empty() {
+                image.original_size
+            } else {
+                0
+            };
+            tile.pad = image.pad;
+            tile.extrude = image.extrude;
+            tile.priority = image.priority;
+            tile.user_data = image.user_data.clone();
+            tile.source = image.source.clone();
+            tile.tile = Some(TilePosition {
+                image: image.name.clone(),
+                width: image.frame_w,
+                height: image.frame_h,
+                x: x - image.frame_x,
+                y: y - image.frame_y,
+            });
+            tiles.push(tile);
+        }
+    }
+    tiles
+}
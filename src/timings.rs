@@ -0,0 +1,141 @@
+//! How long each phase of packing an atlas took, logged at the end of every run and printed
+//! with `--timings`.
+
+use crate::{LogFormat, Opt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The phases of a run, in the order they happen
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Phase {
+    /// Hashing the inputs to check whether the atlas is up to date
+    Hashing,
+    /// Reading, decoding and trimming the input files
+    Loading,
+    /// Premultiplying, trimming and hashing the sprites, as part of loading
+    Trimming,
+    /// Sorting the sprites and packing them into pages, including finding duplicates
+    Packing,
+    /// Compositing and encoding the pages, and writing them out
+    Saving,
+    /// Drawing the sprites into the pages, as part of saving
+    Compositing,
+    /// Encoding the pages and writing the files, as part of saving
+    Encoding,
+    /// Writing the atlas data
+    Metadata,
+}
+
+impl Phase {
+    const ALL: [Phase; 8] = [
+        Phase::Hashing,
+        Phase::Loading,
+        Phase::Trimming,
+        Phase::Packing,
+        Phase::Saving,
+        Phase::Compositing,
+        Phase::Encoding,
+        Phase::Metadata,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Hashing => "hashing",
+            Phase::Loading => "loading",
+            Phase::Trimming => "trimming",
+            Phase::Packing => "packing",
+            Phase::Saving => "saving",
+            Phase::Compositing => "compositing",
+            Phase::Encoding => "encoding",
+            Phase::Metadata => "metadata",
+        }
+    }
+
+    /// Whether the phase is done a sprite or page at a time within another one, on many
+    /// threads at once, so that its time is added up across threads
+    fn is_parallel(self) -> bool {
+        matches!(self, Phase::Trimming | Phase::Compositing | Phase::Encoding)
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static NANOS: [AtomicU64; 8] = [ZERO; 8];
+
+/// Starts timing a new atlas
+pub fn reset() {
+    for nanos in NANOS.iter() {
+        nanos.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Adds `duration` to the time spent on `phase`
+pub fn add(phase: Phase, duration: Duration) {
+    NANOS[phase as usize].fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Runs `f`, adding the time it took to `phase`
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    add(phase, start.elapsed());
+    result
+}
+
+fn get(phase: Phase) -> Duration {
+    Duration::from_nanos(NANOS[phase as usize].load(Ordering::Relaxed))
+}
+
+/// Logs how long each phase of packing the atlas `name` took, and prints it for `--timings`
+pub fn report(opt: &Opt, name: &str) {
+    let phases: Vec<Phase> = Phase::ALL
+        .iter()
+        .copied()
+        .filter(|phase| get(*phase) > Duration::default())
+        .collect();
+    let total: Duration = phases
+        .iter()
+        .filter(|phase| !phase.is_parallel())
+        .map(|phase| get(*phase))
+        .sum();
+
+    for phase in &phases {
+        log::info!(
+            target: "impact::timings",
+            "{}: {:.3}s",
+            phase.name(),
+            get(*phase).as_secs_f64()
+        );
+    }
+    log::info!(target: "impact::timings", "total: {:.3}s", total.as_secs_f64());
+
+    match opt.timings {
+        Some(LogFormat::Text) => {
+            reportln!(opt, "timings for {}", name);
+            for phase in &phases {
+                let seconds = get(*phase).as_secs_f64();
+                if phase.is_parallel() {
+                    reportln!(
+                        opt,
+                        "  {:<12} {:>9.3}s (all threads)",
+                        phase.name(),
+                        seconds
+                    );
+                } else {
+                    reportln!(opt, "{:<14} {:>9.3}s", phase.name(), seconds);
+                }
+            }
+            reportln!(opt, "{:<14} {:>9.3}s", "total", total.as_secs_f64());
+        }
+        Some(LogFormat::Json) => {
+            let mut seconds = serde_json::Map::new();
+            for phase in &phases {
+                seconds.insert(phase.name().to_owned(), get(*phase).as_secs_f64().into());
+            }
+            seconds.insert("total".to_owned(), total.as_secs_f64().into());
+            let report = serde_json::json!({ "atlas": name, "seconds": seconds });
+            reportln!(opt, "{}", report);
+        }
+        None => {}
+    }
+}
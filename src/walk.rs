@@ -0,0 +1,115 @@
+use crate::error::Result;
+use crate::Opt;
+use std::path::{Path, PathBuf};
+
+/// Calls `visit` for every file inside the directory `path` and its subdirectories
+/// that passes the `--include` filter.
+///
+/// Hidden files and folders and OS or editor junk are skipped unless `--hidden` is set.
+/// Symlinks are skipped unless `--follow-symlinks` is set, in which case symlinked
+/// directories that lead back into one of their own ancestors are skipped as well.
+/// With `--max-depth`, only files that many levels deep are visited (1 being the files
+/// directly inside `path`).
+pub fn walk_dir(path: &Path, opt: &Opt, visit: &mut dyn FnMut(&Path) -> Result<()>) -> Result<()> {
+    let mut ancestors = vec![];
+    walk_dir_inner(path, opt, 1, &mut ancestors, &mut |entry| match entry {
+        Entry::File(file) => visit(file),
+        Entry::Folder(_) => Ok(()),
+    })
+}
+
+/// Calls `visit` for the directory `path` and every subdirectory that `walk_dir` would
+/// look for files in
+pub fn walk_folders(
+    path: &Path,
+    opt: &Opt,
+    visit: &mut dyn FnMut(&Path) -> Result<()>,
+) -> Result<()> {
+    let mut ancestors = vec![];
+    walk_dir_inner(path, opt, 1, &mut ancestors, &mut |entry| match entry {
+        Entry::File(_) => Ok(()),
+        Entry::Folder(folder) => visit(folder),
+    })
+}
+
+enum Entry<'a> {
+    File(&'a Path),
+    Folder(&'a Path),
+}
+
+fn walk_dir_inner(
+    path: &Path,
+    opt: &Opt,
+    depth: usize,
+    ancestors: &mut Vec<PathBuf>,
+    visit: &mut dyn FnMut(Entry) -> Result<()>,
+) -> Result<()> {
+    if opt.follow_symlinks {
+        let canonical = path.canonicalize()?;
+        if ancestors.contains(&canonical) {
+            log::warn!("symlink cycle detected, skipping {}", path.display());
+            return Ok(());
+        }
+        ancestors.push(canonical);
+    }
+    visit(Entry::Folder(path))?;
+
+    let mut entries = std::fs::read_dir(path)?.collect::<std::io::Result<Vec<_>>>()?;
+    // Visit files in a stable order, so hashing doesn't depend on the file system
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let entry_path = entry.path();
+        if !opt.hidden && is_hidden(&entry.file_name().to_string_lossy()) {
+            log::info!("Skipping hidden file {}", entry_path.display());
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        let is_dir = if file_type.is_symlink() {
+            if !opt.follow_symlinks {
+                log::info!("Skipping symlink {}", entry_path.display());
+                continue;
+            }
+            std::fs::metadata(&entry_path)?.is_dir()
+        } else {
+            file_type.is_dir()
+        };
+
+        if is_dir {
+            if opt.max_depth.map_or(false, |max_depth| depth >= max_depth) {
+                log::info!("Max depth reached, skipping {}", entry_path.display());
+                continue;
+            }
+            walk_dir_inner(&entry_path, opt, depth + 1, ancestors, visit)?;
+        } else if is_included(&entry_path, opt) {
+            visit(Entry::File(&entry_path))?;
+        }
+    }
+
+    if opt.follow_symlinks {
+        ancestors.pop();
+    }
+    Ok(())
+}
+
+/// Checks a file found inside an input directory against the `--include` patterns
+fn is_included(path: &Path, opt: &Opt) -> bool {
+    if opt.include.is_empty() {
+        return true;
+    }
+    let name = path.file_name().map_or("".into(), |s| s.to_string_lossy());
+    opt.include.iter().any(|pattern| pattern.matches(&name))
+}
+
+/// Whether a file or folder is hidden (a dotfile), or left behind by the OS or an editor, such
+/// as `Thumbs.db`, `__MACOSX` or backups like `sprite.png~` and `sprite.png.bak`
+fn is_hidden(name: &str) -> bool {
+    const JUNK: &[&str] = &["thumbs.db", "ehthumbs.db", "desktop.ini", "__macosx"];
+    const BACKUP_EXTENSIONS: &[&str] = &[".bak", ".orig", ".swp", ".tmp"];
+    let lower = name.to_lowercase();
+    name.starts_with('.')
+        || name.ends_with('~')
+        || (name.starts_with('#') && name.ends_with('#'))
+        || JUNK.contains(&lower.as_str())
+        || BACKUP_EXTENSIONS.iter().any(|ext| lower.ends_with(ext))
+}
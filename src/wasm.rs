@@ -0,0 +1,120 @@
+//! Bindings for packing atlases in the browser, built with the `wasm` feature.
+//!
+//! Images are passed in as encoded bytes and the pages come back as PNG bytes, so nothing here
+//! touches the filesystem.
+
+use crate::bin_packs::max_rects::FreeRectChoiceHeuristic;
+use crate::error::ImpactError;
+use crate::image_wrapper::ImageWrapper;
+use crate::packer::Packer;
+use crate::serial::{Atlas, Texture};
+use wasm_bindgen::prelude::*;
+
+/// Collects images and packs them into an atlas
+#[wasm_bindgen]
+pub struct AtlasBuilder {
+    images: Vec<ImageWrapper>,
+    size: i32,
+    pad: i32,
+    premultiply: bool,
+    trim: bool,
+    unique: bool,
+    rotate: bool,
+}
+
+#[wasm_bindgen]
+impl AtlasBuilder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        size: u32,
+        pad: u32,
+        premultiply: bool,
+        trim: bool,
+        unique: bool,
+        rotate: bool,
+    ) -> AtlasBuilder {
+        AtlasBuilder {
+            images: vec![],
+            size: size as i32,
+            pad: pad as i32,
+            premultiply,
+            trim,
+            unique,
+            rotate,
+        }
+    }
+
+    /// Decodes an image file (PNG, JPEG, ...) and adds it to the atlas as `name`
+    #[wasm_bindgen(js_name = addImage)]
+    pub fn add_image(&mut self, name: String, bytes: &[u8]) -> Result<(), JsError> {
+        let image = image::load_from_memory(bytes)?.to_rgba8();
+        self.images.push(ImageWrapper::new(
+            image,
+            name,
+            self.premultiply,
+            self.trim,
+            0,
+            bytes.len() as u64,
+        ));
+        Ok(())
+    }
+
+    /// Packs every image added so far, naming the pages `{name}0`, `{name}1`, ...
+    pub fn pack(self, name: &str) -> Result<PackedAtlas, JsError> {
+        let mut images = self.images;
+        images.sort_unstable_by(|a, b| (a.width * a.height).cmp(&(b.width * b.height)));
+
+        let mut atlas = Atlas::default();
+        let mut pages = vec![];
+        while !images.is_empty() {
+            let mut packer = Packer::new(self.size, self.size, self.pad);
+            packer.pack(
+                &mut images,
+                self.unique,
+                self.rotate,
+                FreeRectChoiceHeuristic::RectBestShortSideFit,
+                &mut || (),
+            );
+            if packer.images.is_empty() {
+                return Err(ImpactError::CantFitError.into());
+            }
+
+            pages.push(packer.encode_png()?);
+            let page_name = format!("{}{}", name, atlas.textures.len());
+            atlas
+                .textures
+                .push(Texture::from_packer(page_name, &packer));
+        }
+
+        Ok(PackedAtlas {
+            pages,
+            json: serde_json::to_string(&atlas)?,
+        })
+    }
+}
+
+/// The pages and metadata of a packed atlas
+#[wasm_bindgen]
+pub struct PackedAtlas {
+    pages: Vec<Vec<u8>>,
+    json: String,
+}
+
+#[wasm_bindgen]
+impl PackedAtlas {
+    #[wasm_bindgen(getter, js_name = pageCount)]
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// The PNG bytes of a page
+    pub fn page(&self, idx: usize) -> Option<Vec<u8>> {
+        self.pages.get(idx).cloned()
+    }
+
+    /// The atlas metadata, in the same format as `--json`
+    #[wasm_bindgen(getter)]
+    pub fn json(&self) -> String {
+        self.json.clone()
+    }
+}
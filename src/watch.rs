@@ -0,0 +1,93 @@
+use crate::error::Result;
+use notify::{RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long to wait for further changes before repacking, so that saving many
+/// files at once only triggers a single pack
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Runs `pack`, then runs it again every time something inside `paths` changes.
+///
+/// Changes inside `ignored` (the directories the atlas is written to) don't count, unless
+/// they're also inside a more specific one of `paths`, so that packing doesn't trigger itself.
+/// Errors while packing are logged rather than returned, so that a bad save
+/// doesn't stop the watcher.
+pub fn watch<F: FnMut() -> Result<()>>(
+    paths: &[PathBuf],
+    ignored: &[PathBuf],
+    mut pack: F,
+) -> Result<()> {
+    let paths: Vec<PathBuf> = paths.iter().map(|path| absolute(path)).collect();
+    let ignored: Vec<PathBuf> = ignored.iter().map(|path| absolute(path)).collect();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for path in &paths {
+        match path.parent() {
+            // Editors often save by replacing the file, which a watch on the file itself misses
+            Some(dir) if path.is_file() => watcher.watch(dir, RecursiveMode::NonRecursive)?,
+            _ => watcher.watch(path, RecursiveMode::Recursive)?,
+        }
+    }
+
+    if let Err(err) = pack() {
+        log::error!("{}", err);
+    }
+    log::info!("watching for changes...");
+
+    while let Ok(event) = rx.recv() {
+        match event {
+            Ok(event) if event.kind.is_access() => continue,
+            Ok(event)
+                if !event.paths.is_empty()
+                    && !event
+                        .paths
+                        .iter()
+                        .any(|path| is_watched(path, &paths, &ignored)) =>
+            {
+                continue
+            }
+            Ok(event) => log::debug!("change detected: {:?}", event.paths),
+            Err(err) => {
+                log::error!("watch error: {}", err);
+                continue;
+            }
+        }
+
+        // Wait for things to settle down before repacking
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+
+        log::info!("repacking...");
+        if let Err(err) = pack() {
+            log::error!("{}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a change to `path` should trigger a pack: the most specific of the watched and
+/// ignored paths containing it decides, and ignored directories win ties
+fn is_watched(path: &Path, paths: &[PathBuf], ignored: &[PathBuf]) -> bool {
+    let depth = |dirs: &[PathBuf]| {
+        dirs.iter()
+            .filter(|dir| path.starts_with(dir))
+            .map(|dir| dir.components().count())
+            .max()
+    };
+    match (depth(paths), depth(ignored)) {
+        (Some(watched), Some(ignored)) => watched > ignored,
+        (watched, _) => watched.is_some(),
+    }
+}
+
+/// `path` as the watcher reports it, since events always have absolute paths. Directories that
+/// don't exist yet, like an output directory before the first pack, are made absolute as is.
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .unwrap_or_else(|_| match std::env::current_dir() {
+            Ok(dir) => dir.join(path),
+            Err(_) => path.to_owned(),
+        })
+}